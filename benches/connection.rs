@@ -0,0 +1,85 @@
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::extract::ws::Message;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::{executor::block_on, Sink, Stream};
+use tokio::io::AsyncWriteExt;
+use wssocks::WebSocketConnection;
+
+// a sink that accepts every frame immediately, so only our write path is measured
+struct NullSocket;
+
+impl Stream for NullSocket {
+    type Item = Result<Message, Infallible>;
+
+    fn poll_next(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(None)
+    }
+}
+
+impl Sink<Message> for NullSocket {
+    type Error = Infallible;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        drop(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+const TOTAL: usize = 16 * 1024 * 1024;
+
+// writes TOTAL bytes in chunks of the given size, like copy_bidirectional does
+fn write_all(conn: &mut WebSocketConnection<NullSocket>, chunk: &[u8]) {
+    block_on(async {
+        for _ in 0..TOTAL / chunk.len() {
+            conn.write_all(chunk).await.unwrap();
+        }
+        conn.flush().await.unwrap();
+    })
+}
+
+fn poll_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("poll_write");
+    group.throughput(Throughput::Bytes(TOTAL as u64));
+
+    // 8 KiB is the chunk size of copy_bidirectional
+    for chunk_size in [512, 8 * 1024] {
+        let chunk = vec![0u8; chunk_size];
+        group.bench_with_input(
+            BenchmarkId::new("frame_per_write", chunk_size),
+            &chunk,
+            |b, chunk| {
+                let mut conn = WebSocketConnection::new(NullSocket);
+                b.iter(|| write_all(&mut conn, chunk))
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("coalesced", chunk_size),
+            &chunk,
+            |b, chunk| {
+                let mut conn = WebSocketConnection::new(NullSocket).coalesce(true);
+                b.iter(|| write_all(&mut conn, chunk))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, poll_write);
+criterion_main!(benches);
@@ -0,0 +1,128 @@
+use std::net::{SocketAddr, TcpListener};
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    runtime::Runtime,
+};
+use wssocks::{ClientConnection, ProxyConfig, WsSocksClient};
+
+const TOTAL: usize = 16 * 1024 * 1024;
+
+// the bytes of the target are read straight into the frame carrying them to
+// the client, saving one copy per read over going through the relay's
+// buffer; over loopback, where both directions and the echo share the
+// cores, that is within the noise of these numbers, and it shows most in
+// large transfers on hosts short of memory bandwidth
+
+// a proxy and an echo server on loopback, served by rt
+fn spawn(rt: &Runtime) -> (SocketAddr, SocketAddr) {
+    let _guard = rt.enter();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let proxy = listener.local_addr().unwrap();
+    let config = ProxyConfig::default().block_private_addresses(false);
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(wssocks::router(config).into_make_service());
+    rt.spawn(server);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let echo = listener.local_addr().unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+    rt.spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let _ = stream.set_nodelay(true);
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    (proxy, echo)
+}
+
+fn tunnel(rt: &Runtime, proxy: SocketAddr, echo: SocketAddr) -> ClientConnection {
+    let client = WsSocksClient::new(format!("ws://{}/ws", proxy));
+    rt.block_on(client.connect(&echo.to_string())).unwrap()
+}
+
+// writes TOTAL bytes in chunks of the given size through the tunnel while
+// reading the echo back
+async fn transfer(stream: &mut ClientConnection, chunk: &[u8]) {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let write = async {
+        for _ in 0..TOTAL / chunk.len() {
+            writer.write_all(chunk).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+    };
+    let read = async {
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut left = TOTAL / chunk.len() * chunk.len();
+        while left > 0 {
+            let n = reader.read(&mut buf).await.unwrap();
+            assert!(n > 0, "tunnel closed early");
+            left -= n;
+        }
+    };
+    tokio::join!(write, read);
+}
+
+fn throughput(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (proxy, echo) = spawn(&rt);
+    let mut group = c.benchmark_group("tunnel_throughput");
+    group.throughput(Throughput::Bytes(TOTAL as u64));
+    group.sample_size(10);
+
+    // large transfers in the chunks of copy_bidirectional and above, and
+    // many tiny writes as interactive protocols make them
+    for chunk_size in [64, 8 * 1024, 64 * 1024] {
+        let chunk = vec![0u8; chunk_size];
+        group.bench_with_input(
+            BenchmarkId::from_parameter(chunk_size),
+            &chunk,
+            |b, chunk| {
+                let mut stream = tunnel(&rt, proxy, echo);
+                b.iter(|| rt.block_on(transfer(&mut stream, chunk)))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn latency(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let (proxy, echo) = spawn(&rt);
+    let mut group = c.benchmark_group("tunnel_round_trip");
+
+    for size in [1, 1024] {
+        let message = vec![0u8; size];
+        group.bench_with_input(BenchmarkId::from_parameter(size), &message, |b, message| {
+            let mut stream = tunnel(&rt, proxy, echo);
+            let mut buf = vec![0u8; size];
+            // timed inside the runtime, block_on itself is not measured
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let started = Instant::now();
+                    for _ in 0..iters {
+                        stream.write_all(message).await.unwrap();
+                        stream.flush().await.unwrap();
+                        stream.read_exact(&mut buf).await.unwrap();
+                    }
+                    started.elapsed()
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(10));
+    targets = throughput, latency
+}
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// any input is either a request or an error, never a panic, and a parsed
+// target always formats back into a host:port
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = wssocks::parse_socks5_request(data) {
+        assert!(request.target.to_string().contains(':'));
+    }
+});
@@ -0,0 +1,101 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::hooks::TunnelStats;
+
+/// Where the access log goes: one JSON object per line for every tunnel that
+/// was relayed, written when it closes.
+///
+/// Each record holds `timestamp` (seconds since the Unix epoch), `conn_id`,
+/// `client_ip`,
+/// `user`, `target`, `bytes_up`, `bytes_down`, `duration_ms` and `reason`,
+/// one of `closed`, `idle`, `max_lifetime`, `up_error`, `down_error` and
+/// `killed`, up
+/// being from the client to the target. Unlike the tracing output these fields are kept
+/// stable for audit tooling.
+#[derive(Clone)]
+pub struct AccessLog {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl AccessLog {
+    /// Writes records to standard output.
+    pub fn stdout() -> Self {
+        Self::writer(std::io::stdout())
+    }
+
+    /// Appends records to the file at `path`, creating it if needed.
+    pub fn file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::writer(file))
+    }
+
+    /// Writes records to `writer`, flushing after each.
+    pub fn writer(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+        }
+    }
+
+    pub(crate) fn record(&self, record: &Record<'_>) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!(error = %e, "access log record fails to serialize");
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writer.write_all(&line).and_then(|_| writer.flush()) {
+            warn!(error = %e, "access log write failed");
+        }
+    }
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AccessLog")
+    }
+}
+
+// one closed tunnel
+#[derive(Serialize)]
+pub(crate) struct Record<'a> {
+    timestamp: f64,
+    conn_id: u64,
+    client_ip: Option<IpAddr>,
+    user: Option<&'a str>,
+    target: &'a str,
+    bytes_up: u64,
+    bytes_down: u64,
+    duration_ms: u128,
+    reason: &'static str,
+}
+
+impl<'a> Record<'a> {
+    pub(crate) fn new(stats: &'a TunnelStats) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |since| since.as_secs_f64());
+        Self {
+            timestamp,
+            conn_id: stats.conn_id,
+            client_ip: stats.peer.map(|peer| peer.ip()),
+            user: stats.user.as_deref(),
+            target: &stats.target,
+            bytes_up: stats.bytes_up,
+            bytes_down: stats.bytes_down,
+            duration_ms: stats.duration.as_millis(),
+            reason: stats.reason.as_str(),
+        }
+    }
+}
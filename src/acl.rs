@@ -0,0 +1,135 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use ipnet::IpNet;
+
+/// A destination pattern for the allow and deny lists of [`ProxyConfig`].
+///
+/// Parses from a domain, which also matches all of its subdomains, or from an
+/// IP address or CIDR network such as `10.0.0.0/8` or `fd00::/8`.
+///
+/// [`ProxyConfig`]: crate::ProxyConfig
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TargetRule {
+    Domain(String),
+    Network(IpNet),
+}
+
+impl TargetRule {
+    fn matches_domain(&self, host: &str) -> bool {
+        match self {
+            TargetRule::Domain(domain) => {
+                let host = host.trim_end_matches('.').as_bytes();
+                let domain = domain.as_bytes();
+                host.eq_ignore_ascii_case(domain)
+                    || (host.len() > domain.len()
+                        && host[host.len() - domain.len() - 1] == b'.'
+                        && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain))
+            }
+            TargetRule::Network(_) => false,
+        }
+    }
+
+    fn matches_ip(&self, ip: IpAddr) -> bool {
+        match self {
+            // a v4-mapped v6 address is the v4 one, v4 ranges match it, as
+            // do rules written in the mapped form
+            TargetRule::Network(net) => net.contains(&ip) || net.contains(&ip.to_canonical()),
+            TargetRule::Domain(_) => false,
+        }
+    }
+}
+
+impl FromStr for TargetRule {
+    type Err = ParseRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(net) = s.parse::<IpNet>() {
+            return Ok(TargetRule::Network(net));
+        }
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(TargetRule::Network(ip.into()));
+        }
+
+        // `*.example.com` and `.example.com` mean the same as `example.com`
+        let domain = s.trim_start_matches("*.").trim_start_matches('.');
+        let domain = domain.trim_end_matches('.');
+        let valid = !domain.is_empty()
+            && domain.split('.').all(|label| {
+                !label.is_empty()
+                    && label
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+            });
+        if !valid {
+            return Err(ParseRuleError(s.to_string()));
+        }
+        Ok(TargetRule::Domain(domain.to_ascii_lowercase()))
+    }
+}
+
+/// The error returned when a [`TargetRule`] is neither a domain nor an IP
+/// network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseRuleError(String);
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid target rule `{}`", self.0)
+    }
+}
+
+impl std::error::Error for ParseRuleError {}
+
+// the allow and deny lists, an empty allow list allows everything not denied
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Acl {
+    pub(crate) allow: Vec<TargetRule>,
+    pub(crate) deny: Vec<TargetRule>,
+}
+
+impl Acl {
+    // check an address we are about to connect to, host is the domain it was
+    // resolved from, which may satisfy a rule by name
+    pub(crate) fn allows(&self, host: Option<&str>, ip: IpAddr) -> bool {
+        let matches = |rule: &TargetRule| {
+            rule.matches_ip(ip) || host.is_some_and(|host| rule.matches_domain(host))
+        };
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+
+    // check a domain passed on unresolved, only rules naming a domain can
+    // match it
+    pub(crate) fn allows_domain(&self, host: &str) -> bool {
+        let matches = |rule: &TargetRule| rule.matches_domain(host);
+        !self.deny.iter().any(matches) && (self.allow.is_empty() || self.allow.iter().any(matches))
+    }
+}
+
+// loopback, private, link-local and unique-local addresses, the ones a public
+// relay must never be tricked into reaching
+pub(crate) fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_v4(ip),
+            None => is_private_v6(ip),
+        },
+    }
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // fc00::/7 unique local
+        || (first & 0xfe00) == 0xfc00
+        // fe80::/10 link local
+        || (first & 0xffc0) == 0xfe80
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::config::ProxyConfig;
+
+// the tunnels relaying right now, shared by every clone of a config
+#[derive(Clone, Default)]
+pub(crate) struct Registry {
+    tunnels: Arc<Mutex<HashMap<u64, Arc<Active>>>>,
+}
+
+// what the admin endpoint tells about a tunnel, its bytes counted as they go
+pub(crate) struct Active {
+    peer: Option<SocketAddr>,
+    user: Option<String>,
+    target: String,
+    started: Instant,
+    up: AtomicU64,
+    down: AtomicU64,
+    // cancelled to kill the tunnel
+    cancel: CancellationToken,
+}
+
+impl Registry {
+    // lists the tunnel until the returned entry is dropped, which happens on
+    // every way out of the tunnel, a cancelled or panicking one included
+    pub(crate) fn register(
+        &self,
+        conn_id: u64,
+        peer: Option<SocketAddr>,
+        user: Option<String>,
+        target: String,
+        early: u64,
+    ) -> Entry {
+        let active = Arc::new(Active {
+            peer,
+            user,
+            target,
+            started: Instant::now(),
+            up: AtomicU64::new(early),
+            down: AtomicU64::new(0),
+            cancel: CancellationToken::new(),
+        });
+        self.lock().insert(conn_id, active.clone());
+        Entry {
+            registry: self.clone(),
+            conn_id,
+            active,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Arc<Active>>> {
+        self.tunnels.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("tunnels", &self.lock().len())
+            .finish()
+    }
+}
+
+pub(crate) struct Entry {
+    registry: Registry,
+    conn_id: u64,
+    active: Arc<Active>,
+}
+
+impl Entry {
+    // cancelled once an operator kills the tunnel
+    pub(crate) fn cancel(&self) -> &CancellationToken {
+        &self.active.cancel
+    }
+
+    // the outbound stream, counting the bytes each way as the relay moves them
+    pub(crate) fn count<S>(&self, outbound: S) -> Counted<S> {
+        Counted {
+            inner: outbound,
+            active: self.active.clone(),
+        }
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.conn_id);
+    }
+}
+
+// writes to the target are bytes up, reads from it bytes down
+pub(crate) struct Counted<S> {
+    inner: S,
+    active: Arc<Active>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let polled = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let n = buf.filled().len() - before;
+        self.active.down.fetch_add(n as u64, Ordering::Relaxed);
+        polled
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let polled = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = polled {
+            self.active.up.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        polled
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// one active tunnel
+#[derive(Serialize)]
+struct Tunnel {
+    conn_id: u64,
+    client_ip: Option<IpAddr>,
+    user: Option<String>,
+    target: String,
+    bytes_up: u64,
+    bytes_down: u64,
+    age_secs: f64,
+}
+
+// the request carries the admin token, and there is one
+fn authorized(config: &ProxyConfig, headers: &HeaderMap) -> bool {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    token.is_some() && token == config.admin_token.as_deref()
+}
+
+// the active tunnels as JSON, for the admin token only
+pub(crate) async fn tunnels(
+    Extension(config): Extension<Arc<ProxyConfig>>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(&config, &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let mut tunnels: Vec<Tunnel> = config
+        .admin
+        .lock()
+        .iter()
+        .map(|(conn_id, active)| Tunnel {
+            conn_id: *conn_id,
+            client_ip: active.peer.map(|peer| peer.ip()),
+            user: active.user.clone(),
+            target: active.target.clone(),
+            bytes_up: active.up.load(Ordering::Relaxed),
+            bytes_down: active.down.load(Ordering::Relaxed),
+            age_secs: active.started.elapsed().as_secs_f64(),
+        })
+        .collect();
+    tunnels.sort_by_key(|tunnel| tunnel.conn_id);
+    Json(tunnels).into_response()
+}
+
+// end a tunnel by its conn id, its relay stops and the client gets a close
+// with CLOSE_KILLED; who asked is logged, as the audit trail of kills
+pub(crate) async fn kill(
+    Extension(config): Extension<Arc<ProxyConfig>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Path(conn_id): Path<u64>,
+) -> StatusCode {
+    let admin = connect_info.map(|ConnectInfo(peer)| peer);
+    if !authorized(&config, &headers) {
+        warn!(conn_id, ?admin, "tunnel kill refused, bad admin token");
+        return StatusCode::UNAUTHORIZED;
+    }
+    let active = config.admin.lock().get(&conn_id).cloned();
+    match active {
+        Some(active) => {
+            warn!(
+                conn_id,
+                ?admin,
+                target = %active.target,
+                user = active.user.as_deref(),
+                "tunnel killed through the admin endpoint"
+            );
+            active.cancel.cancel();
+            StatusCode::NO_CONTENT
+        }
+        None => StatusCode::NOT_FOUND,
+    }
+}
@@ -0,0 +1,572 @@
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::copy_bidirectional,
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    time::{sleep, Instant},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        client::IntoClientRequest,
+        handshake::client::Response,
+        http::{
+            header::{AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL},
+            HeaderValue,
+        },
+        Error, Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    connection::{
+        data_message, decode_text, WebSocketConnection, CONN_ID_HEADER, HALF_CLOSE_HEADER,
+        SUBPROTOCOL, TEXT_FRAMES_HEADER,
+    },
+    retry::{transient, Retry},
+    socks5::{
+        encode_target, encode_userpass, reply_error, Target, CMD_CONNECT, METHOD_NO_AUTH,
+        METHOD_USERPASS,
+    },
+};
+
+/// A tunnel opened by [`WsSocksClient`], use it like a `TcpStream` to the target.
+pub type ClientConnection = WebSocketConnection<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+
+// a websocket to the server, with what its upgrade response agreed on
+struct Upgraded {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    compressed: bool,
+    half_close: bool,
+    text_frames: bool,
+    conn_id: Option<String>,
+}
+
+/// The client side of the tunnel: connects to a wssocks server and asks it to
+/// open a connection to a target.
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// use tokio::io::AsyncWriteExt;
+///
+/// let client = wssocks::WsSocksClient::new("ws://127.0.0.1:8000/ws");
+/// let mut stream = client.connect("example.com:80").await?;
+/// stream.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct WsSocksClient {
+    url: String,
+    credentials: Option<(String, String)>,
+    auth_token: Option<String>,
+    text_frames: bool,
+    retry: Option<Retry>,
+    #[cfg(feature = "compression")]
+    compression: bool,
+    #[cfg(feature = "rustls")]
+    root_certificates: Vec<Vec<u8>>,
+    #[cfg(feature = "rustls")]
+    accept_invalid_certs: bool,
+    #[cfg(feature = "rustls")]
+    client_certificate: Option<(Vec<Vec<u8>>, Vec<u8>)>,
+}
+
+impl WsSocksClient {
+    /// A client for the server at `url`, e.g. `ws://example.com/ws`. `wss://`
+    /// urls need the `rustls` feature.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            credentials: None,
+            auth_token: None,
+            text_frames: false,
+            retry: None,
+            #[cfg(feature = "compression")]
+            compression: false,
+            #[cfg(feature = "rustls")]
+            root_certificates: Vec::new(),
+            #[cfg(feature = "rustls")]
+            accept_invalid_certs: false,
+            #[cfg(feature = "rustls")]
+            client_certificate: None,
+        }
+    }
+
+    /// Authenticates with a username and password, for servers that require it.
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Sends `Authorization: Bearer <token>` with the websocket upgrade, for
+    /// servers that require a token.
+    pub fn auth_token(mut self, token: impl Into<String>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    /// Asks the server to carry the tunnel, handshake included, base64
+    /// encoded in text frames, for networks that mangle or drop binary
+    /// frames. A server with text frames disabled answers in binary ones, and
+    /// the tunnel follows. Disabled by default.
+    pub fn text_frames(mut self, enabled: bool) -> Self {
+        self.text_frames = enabled;
+        self
+    }
+
+    /// Retries the websocket connect of every tunnel as `retry` says when it
+    /// fails on the way, such as while the server restarts, instead of
+    /// failing the tunnel at once. `None`, the default, tries once.
+    pub fn retry(mut self, retry: Option<Retry>) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Asks the server to deflate the tunnel, which it only does when it has
+    /// compression enabled too. Disabled by default, and ignored by
+    /// [`run_local_proxy`](Self::run_local_proxy).
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    /// Trusts the DER encoded CA certificate instead of the bundled web roots,
+    /// to pin the CA that issued the server certificate. Can be called several
+    /// times to trust more than one.
+    #[cfg(feature = "rustls")]
+    pub fn root_certificate(mut self, der: impl Into<Vec<u8>>) -> Self {
+        self.root_certificates.push(der.into());
+        self
+    }
+
+    /// Skips verifying the server certificate, for self-hosted testing only:
+    /// anyone on the path can read and change the tunnel. Disabled by default.
+    #[cfg(feature = "rustls")]
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Presents the DER encoded certificate chain, the client's own first,
+    /// with its DER encoded PKCS#8, SEC1 or PKCS#1 private key, to servers
+    /// that ask for one.
+    #[cfg(feature = "rustls")]
+    pub fn client_certificate(mut self, chain: Vec<Vec<u8>>, key: impl Into<Vec<u8>>) -> Self {
+        self.client_certificate = Some((chain, key.into()));
+        self
+    }
+
+    /// Opens a websocket to the server and has it connect to `target`, a
+    /// `host:port` pair. Resolves once the server reports the connection as
+    /// established.
+    pub async fn connect(&self, target: &str) -> std::io::Result<ClientConnection> {
+        let mut request = vec![0x05, CMD_CONNECT, 0x00];
+        let encoded = target
+            .parse::<Target>()
+            .ok()
+            .and_then(|target| encode_target(&mut request, &target));
+        if encoded.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid target {:?}, expected host:port", target),
+            ));
+        }
+
+        let upgraded = self.open().await?;
+        let conn_id = upgraded.conn_id;
+        // the id goes into every error, it is what the server logged them by
+        let with_id = |e: std::io::Error| match &conn_id {
+            Some(id) => std::io::Error::new(e.kind(), format!("{} (conn id {})", e, id)),
+            None => e,
+        };
+        let mut socket = upgraded.socket;
+        self.handshake(&mut socket, upgraded.text_frames, request)
+            .await
+            .map_err(with_id)?;
+        let connection = into_connection(socket, upgraded.compressed, upgraded.half_close)
+            .text_frames(upgraded.text_frames);
+        Ok(connection.with_conn_id(conn_id))
+    }
+
+    // the socks5 handshake asking the server for the connection
+    async fn handshake(
+        &self,
+        socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+        text: bool,
+        request: Vec<u8>,
+    ) -> std::io::Result<()> {
+        // offer the one method we can do
+        let method = match self.credentials {
+            Some(_) => METHOD_USERPASS,
+            None => METHOD_NO_AUTH,
+        };
+        send(socket, text, vec![0x05, 0x01, method]).await?;
+        match recv(socket, text).await?[..] {
+            [0x05, selected] if selected == method => {}
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "server accepts no offered auth method",
+                ))
+            }
+        }
+
+        if let Some((username, password)) = &self.credentials {
+            // username/password sub-negotiation
+            let auth = match encode_userpass(username, password) {
+                Some(auth) => auth,
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "username and password must be at most 255 bytes",
+                    ))
+                }
+            };
+            send(socket, text, auth).await?;
+            if recv(socket, text).await? != [0x01, 0x00] {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "username/password authentication failed",
+                ));
+            }
+        }
+
+        send(socket, text, request).await?;
+        match recv(socket, text).await?[..] {
+            [0x05, 0x00, ..] => Ok(()),
+            [0x05, rep, ..] => Err(reply_error(rep)),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "malformed socks5 reply",
+            )),
+        }
+    }
+
+    /// Runs a plain SOCKS5 proxy on `listen_addr` that tunnels every
+    /// connection to the server.
+    ///
+    /// Each accepted connection gets its own websocket and its bytes are
+    /// relayed as is, so the SOCKS5 handshake, credentials included, is
+    /// answered by the server and the credentials set here are not used. Only
+    /// returns when the listener fails.
+    pub async fn run_local_proxy(self, listen_addr: impl ToSocketAddrs) -> std::io::Result<()> {
+        let listener = TcpListener::bind(listen_addr).await?;
+        info!(addr = %listener.local_addr()?, url = %self.url, "local proxy listening");
+        // the handshake is the local client's, relayed as is, so nothing
+        // could tell where the server starts deflating; never ask for it
+        #[cfg(feature = "compression")]
+        let client = Self {
+            compression: false,
+            ..self
+        };
+        #[cfg(not(feature = "compression"))]
+        let client = self;
+        let client = Arc::new(client);
+        loop {
+            let (mut inbound, peer) = listener.accept().await?;
+            let client = client.clone();
+            tokio::spawn(async move {
+                let upgraded = match client.open().await {
+                    Ok(upgraded) => upgraded,
+                    Err(e) => {
+                        warn!(%peer, "{}", e);
+                        return;
+                    }
+                };
+                let (half_close, conn_id) = (upgraded.half_close, upgraded.conn_id);
+                debug!(%peer, ?conn_id, "tunnel opened");
+                // the local client's FIN reaches the target without ending
+                // the other direction
+                let mut outbound = WebSocketConnection::new(upgraded.socket)
+                    .half_close(half_close)
+                    .text_frames(upgraded.text_frames);
+                if let Err(e) = copy_bidirectional(&mut inbound, &mut outbound).await {
+                    debug!(%peer, ?conn_id, "tunnel closed with error: {}", e);
+                }
+                if let Some((code, reason)) = outbound.peer_close() {
+                    debug!(%peer, ?conn_id, code, reason, "server closed the tunnel");
+                }
+                if half_close {
+                    let _ = outbound.close().await;
+                }
+            });
+        }
+    }
+
+    async fn open(&self) -> std::io::Result<Upgraded> {
+        let started = Instant::now();
+        let mut failed = 0;
+        let (socket, response) = loop {
+            let e = match self.dial().await? {
+                Ok(connected) => break connected,
+                Err(e) => e,
+            };
+            failed += 1;
+            let backoff = match &self.retry {
+                Some(retry) if transient(&e) => retry.backoff(failed, started.elapsed()),
+                _ => None,
+            };
+            match backoff {
+                Some(backoff) => {
+                    debug!(
+                        attempt = failed,
+                        ?backoff,
+                        "websocket connect failed, retrying: {:?}",
+                        e
+                    );
+                    sleep(backoff).await;
+                }
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionRefused,
+                        format!("websocket connect fails, detail error is {:?}", e),
+                    ))
+                }
+            }
+        };
+        let half_close = response
+            .headers()
+            .get(HALF_CLOSE_HEADER)
+            .is_some_and(|value| value == "1");
+        let text_frames = self.text_frames
+            && response
+                .headers()
+                .get(TEXT_FRAMES_HEADER)
+                .is_some_and(|value| value == "base64");
+        let conn_id = response
+            .headers()
+            .get(CONN_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok(Upgraded {
+            compressed: self.compression_agreed(&response),
+            socket,
+            half_close,
+            text_frames,
+            conn_id,
+        })
+    }
+
+    // one try at the websocket, the outer error is a config one no retry fixes
+    async fn dial(
+        &self,
+    ) -> std::io::Result<Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, Response), Error>>
+    {
+        let mut request = self.url.as_str().into_client_request().map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid websocket url, detail error is {:?}", e),
+            )
+        })?;
+        if let Some(token) = &self.auth_token {
+            let value = HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "auth token is not a valid header value",
+                )
+            })?;
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+        request.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(SUBPROTOCOL),
+        );
+        request
+            .headers_mut()
+            .insert(HALF_CLOSE_HEADER, HeaderValue::from_static("1"));
+        if self.text_frames {
+            request
+                .headers_mut()
+                .insert(TEXT_FRAMES_HEADER, HeaderValue::from_static("base64"));
+        }
+        #[cfg(feature = "compression")]
+        if self.compression {
+            request.headers_mut().insert(
+                crate::connection::COMPRESSION_HEADER,
+                HeaderValue::from_static("deflate"),
+            );
+        }
+
+        #[cfg(feature = "rustls")]
+        let connected =
+            tokio_tungstenite::connect_async_tls_with_config(request, None, self.tls_connector()?)
+                .await;
+        #[cfg(not(feature = "rustls"))]
+        let connected = tokio_tungstenite::connect_async(request).await;
+        Ok(connected)
+    }
+
+    #[cfg(feature = "compression")]
+    fn compression_agreed(&self, response: &Response) -> bool {
+        self.compression
+            && response
+                .headers()
+                .get(crate::connection::COMPRESSION_HEADER)
+                .is_some_and(|value| value == "deflate")
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn compression_agreed(&self, _: &Response) -> bool {
+        false
+    }
+
+    // None keeps the default connector, which verifies against the web roots
+    #[cfg(feature = "rustls")]
+    fn tls_connector(&self) -> std::io::Result<Option<tokio_tungstenite::Connector>> {
+        if self.root_certificates.is_empty()
+            && !self.accept_invalid_certs
+            && self.client_certificate.is_none()
+        {
+            return Ok(None);
+        }
+
+        let builder = rustls::ClientConfig::builder().with_safe_defaults();
+        let builder = if self.accept_invalid_certs {
+            builder.with_custom_certificate_verifier(Arc::new(NoVerification))
+        } else {
+            let mut roots = rustls::RootCertStore::empty();
+            for der in &self.root_certificates {
+                roots.add(&rustls::Certificate(der.clone())).map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("invalid root certificate, detail error is {:?}", e),
+                    )
+                })?;
+            }
+            // a client certificate alone keeps verifying against the web roots
+            if self.root_certificates.is_empty() {
+                roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(
+                    |anchor| {
+                        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                            anchor.subject,
+                            anchor.spki,
+                            anchor.name_constraints,
+                        )
+                    },
+                ));
+            }
+            builder.with_custom_certificate_verifier(Arc::new(rustls::client::WebPkiVerifier::new(
+                roots, None,
+            )))
+        };
+        let config = match &self.client_certificate {
+            Some((chain, key)) => builder
+                .with_single_cert(
+                    chain.iter().cloned().map(rustls::Certificate).collect(),
+                    rustls::PrivateKey(key.clone()),
+                )
+                .map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("invalid client certificate, detail error is {:?}", e),
+                    )
+                })?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Some(tokio_tungstenite::Connector::Rustls(Arc::new(config))))
+    }
+}
+
+// accepts any server certificate, see danger_accept_invalid_certs
+#[cfg(feature = "rustls")]
+struct NoVerification;
+
+#[cfg(feature = "rustls")]
+impl rustls::client::ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+/// Runs a plain SOCKS5 proxy on `listen_addr` that tunnels every connection
+/// to the wssocks server at `ws_url`, e.g. `ws://example.com/ws`. See
+/// [`WsSocksClient::run_local_proxy`].
+pub async fn run_local_proxy(
+    listen_addr: impl ToSocketAddrs,
+    ws_url: impl Into<String>,
+) -> std::io::Result<()> {
+    WsSocksClient::new(ws_url)
+        .run_local_proxy(listen_addr)
+        .await
+}
+
+#[cfg(feature = "compression")]
+fn into_connection(
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    compressed: bool,
+    half_close: bool,
+) -> ClientConnection {
+    WebSocketConnection::new(socket)
+        .compression(compressed)
+        .half_close(half_close)
+}
+
+#[cfg(not(feature = "compression"))]
+fn into_connection(
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    _: bool,
+    half_close: bool,
+) -> ClientConnection {
+    WebSocketConnection::new(socket).half_close(half_close)
+}
+
+// a handshake message, base64 encoded in a text one when text is set
+async fn send(
+    socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    text: bool,
+    data: Vec<u8>,
+) -> std::io::Result<()> {
+    let message = data_message(data, text)?;
+    socket.send(message).await.map_err(ws_error)
+}
+
+// the next handshake message, skipping keepalives
+async fn recv(
+    socket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+    text: bool,
+) -> std::io::Result<Vec<u8>> {
+    loop {
+        match socket.next().await {
+            Some(Ok(Message::Binary(data))) if !text => return Ok(data),
+            Some(Ok(Message::Text(data))) if text => return decode_text(data.as_bytes()),
+            Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+            Some(Err(e)) => return Err(ws_error(e)),
+            // the server tells why it gave up on the tunnel
+            Some(Ok(Message::Close(Some(frame)))) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionAborted,
+                    format!(
+                        "websocket closed during the handshake with code {}, {}",
+                        u16::from(frame.code),
+                        frame.reason
+                    ),
+                ))
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "websocket closed during the handshake",
+                ))
+            }
+        }
+    }
+}
+
+fn ws_error(e: Error) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::ConnectionAborted,
+        format!("websocket stream fails, detail error is {:?}", e),
+    )
+}
@@ -0,0 +1,1094 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::RangeInclusive;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::HeaderValue;
+use tokio::sync::Semaphore;
+
+#[cfg(feature = "access-log")]
+use crate::access_log::AccessLog;
+use crate::acl::{Acl, TargetRule};
+#[cfg(feature = "admin")]
+use crate::admin::Registry;
+use crate::connector::{Connector, OutboundConnector};
+#[cfg(feature = "geoip")]
+use crate::geoip::GeoIp;
+use crate::hooks::{
+    AbuseClassifier, AbuseHook, CloseHook, ConnectHook, Hook, RewriteHook, TunnelRequest,
+    TunnelStats, Verdict,
+};
+#[cfg(all(feature = "netns", target_os = "linux"))]
+use crate::netns::NetNs;
+use crate::policy::UserPolicy;
+use crate::rate_limit::RateLimit;
+use crate::resolver::{ConfiguredResolver, Resolver};
+use crate::shutdown::Shutdown;
+use crate::socks5::Target;
+
+/// Default time a tunnel may go without relaying any data, 300 seconds.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Default time allowed for connecting to a target, 10 seconds.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default time allowed for a client to send its request, 15 seconds.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Default size of the buffer each direction of a tunnel reads into, 8 KiB.
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Default cap on the size of a message, or frame, a client may send, 1 MiB.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Target ports refused by default, the SMTP ports 25, 465 and 587 open
+/// relays get abused to send spam through.
+pub const DEFAULT_BLOCKED_PORTS: [RangeInclusive<u16>; 3] = [25..=25, 465..=465, 587..=587];
+
+/// Which addresses of a domain target are tried, and in what order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum AddressFamily {
+    /// Try the addresses in the order the resolver returned them.
+    #[default]
+    Auto,
+    /// Only try IPv4 addresses.
+    Ipv4Only,
+    /// Only try IPv6 addresses.
+    Ipv6Only,
+    /// Try IPv4 addresses first, then IPv6 ones.
+    PreferIpv4,
+    /// Try IPv6 addresses first, then IPv4 ones.
+    PreferIpv6,
+}
+
+impl AddressFamily {
+    // filter and order resolved addresses, keeping the resolver's order
+    // within each family
+    pub(crate) fn apply(self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        match self {
+            AddressFamily::Auto => {}
+            AddressFamily::Ipv4Only => addrs.retain(SocketAddr::is_ipv4),
+            AddressFamily::Ipv6Only => addrs.retain(SocketAddr::is_ipv6),
+            AddressFamily::PreferIpv4 => addrs.sort_by_key(SocketAddr::is_ipv6),
+            AddressFamily::PreferIpv6 => addrs.sort_by_key(SocketAddr::is_ipv4),
+        }
+        addrs
+    }
+}
+
+/// Options for the proxy served on the WebSocket route.
+#[derive(Clone, Debug)]
+pub struct ProxyConfig {
+    pub(crate) ws_path: String,
+    pub(crate) credentials: HashMap<String, String>,
+    pub(crate) policies: HashMap<String, UserPolicy>,
+    pub(crate) auth_token: Option<String>,
+    pub(crate) allowed_origins: Vec<String>,
+    pub(crate) strict_subprotocol: bool,
+    pub(crate) error_replies: bool,
+    pub(crate) text_frames: bool,
+    pub(crate) accept_proxy_protocol: bool,
+    #[cfg(feature = "mtls")]
+    pub(crate) require_client_certificate: bool,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) max_lifetime: Option<Duration>,
+    pub(crate) bandwidth_limit: Option<u64>,
+    pub(crate) buffer_size: usize,
+    pub(crate) max_message_size: usize,
+    pub(crate) ping_interval: Option<Duration>,
+    pub(crate) connect_timeout: Duration,
+    pub(crate) handshake_timeout: Duration,
+    pub(crate) acl: Acl,
+    pub(crate) allowed_ports: Vec<RangeInclusive<u16>>,
+    pub(crate) blocked_ports: Vec<RangeInclusive<u16>>,
+    pub(crate) block_private: bool,
+    pub(crate) address_family: AddressFamily,
+    pub(crate) happy_eyeballs: bool,
+    pub(crate) connector: Option<Connector>,
+    pub(crate) resolver: ConfiguredResolver,
+    // the targets sent a PROXY header, None for none
+    pub(crate) proxy_protocol: Option<Acl>,
+    pub(crate) bind_command: bool,
+    pub(crate) resolve_command: bool,
+    // the unix sockets reachable as `unix:/path` domains
+    #[cfg(unix)]
+    pub(crate) unix_sockets: Vec<PathBuf>,
+    pub(crate) rewrite_target: Option<Hook<RewriteHook>>,
+    pub(crate) on_connect: Option<Hook<ConnectHook>>,
+    pub(crate) on_close: Option<Hook<CloseHook>>,
+    pub(crate) abuse_classifier: Option<Hook<AbuseClassifier>>,
+    pub(crate) on_abuse: Option<Hook<AbuseHook>>,
+    pub(crate) outbound_bind: Option<IpAddr>,
+    pub(crate) outbound_source_port: Option<u16>,
+    pub(crate) ipv6_scope_id: Option<u32>,
+    #[cfg(all(feature = "fwmark", target_os = "linux"))]
+    pub(crate) fwmark: Option<u32>,
+    #[cfg(all(feature = "netns", target_os = "linux"))]
+    pub(crate) netns: Option<Arc<NetNs>>,
+    pub(crate) tcp_nodelay: bool,
+    pub(crate) tcp_keepalive: Option<(Duration, Duration)>,
+    pub(crate) shutdown: Shutdown,
+    pub(crate) tunnel_limit: Option<Arc<Semaphore>>,
+    pub(crate) soft_max_tunnels: Option<usize>,
+    pub(crate) soft_memory_limit: Option<usize>,
+    pub(crate) rate_limit: Option<RateLimit>,
+    #[cfg(feature = "geoip")]
+    pub(crate) geoip: Option<GeoIp>,
+    #[cfg(feature = "compression")]
+    pub(crate) compression: bool,
+    pub(crate) root: bool,
+    // content type and body, None for the default page
+    pub(crate) root_page: Option<(HeaderValue, String)>,
+    pub(crate) health_path: Option<String>,
+    pub(crate) ready_path: Option<String>,
+    #[cfg(feature = "prometheus")]
+    pub(crate) metrics_path: Option<String>,
+    #[cfg(feature = "access-log")]
+    pub(crate) access_log: Option<AccessLog>,
+    #[cfg(feature = "admin")]
+    pub(crate) admin_path: Option<String>,
+    #[cfg(feature = "admin")]
+    pub(crate) admin_token: Option<String>,
+    #[cfg(feature = "admin")]
+    pub(crate) admin: Registry,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            ws_path: "/ws".to_string(),
+            credentials: HashMap::new(),
+            policies: HashMap::new(),
+            auth_token: None,
+            allowed_origins: vec!["*".to_string()],
+            strict_subprotocol: false,
+            error_replies: true,
+            text_frames: true,
+            accept_proxy_protocol: false,
+            #[cfg(feature = "mtls")]
+            require_client_certificate: false,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            max_lifetime: None,
+            bandwidth_limit: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            ping_interval: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            acl: Acl::default(),
+            allowed_ports: Vec::new(),
+            blocked_ports: DEFAULT_BLOCKED_PORTS.to_vec(),
+            block_private: true,
+            address_family: AddressFamily::Auto,
+            happy_eyeballs: false,
+            connector: None,
+            resolver: ConfiguredResolver::default(),
+            proxy_protocol: None,
+            bind_command: false,
+            resolve_command: false,
+            #[cfg(unix)]
+            unix_sockets: Vec::new(),
+            rewrite_target: None,
+            on_connect: None,
+            on_close: None,
+            abuse_classifier: None,
+            on_abuse: None,
+            outbound_bind: None,
+            outbound_source_port: None,
+            ipv6_scope_id: None,
+            #[cfg(all(feature = "fwmark", target_os = "linux"))]
+            fwmark: None,
+            #[cfg(all(feature = "netns", target_os = "linux"))]
+            netns: None,
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            shutdown: Shutdown::default(),
+            tunnel_limit: None,
+            soft_max_tunnels: None,
+            soft_memory_limit: None,
+            rate_limit: None,
+            #[cfg(feature = "geoip")]
+            geoip: None,
+            #[cfg(feature = "compression")]
+            compression: false,
+            root: true,
+            root_page: None,
+            health_path: Some("/healthz".to_string()),
+            ready_path: Some("/readyz".to_string()),
+            #[cfg(feature = "prometheus")]
+            metrics_path: None,
+            #[cfg(feature = "access-log")]
+            access_log: None,
+            #[cfg(feature = "admin")]
+            admin_path: None,
+            #[cfg(feature = "admin")]
+            admin_token: None,
+            #[cfg(feature = "admin")]
+            admin: Registry::default(),
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// Serves the WebSocket endpoint on this path, `/ws` by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the path does not start with a `/`.
+    pub fn ws_path(mut self, path: impl Into<String>) -> Self {
+        let path = path.into();
+        assert!(path.starts_with('/'), "ws_path must start with a `/`");
+        self.ws_path = path;
+        self
+    }
+
+    /// Serves the plain text page on `/`. Enabled by default. Disabled, `/`
+    /// answers 404, or whatever route the router is merged with puts there.
+    pub fn root(mut self, enabled: bool) -> Self {
+        self.root = enabled;
+        self
+    }
+
+    /// Serves `body` with this content type on `/` in place of the default
+    /// page, which gives away that this is wssocks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `content_type` is not a valid header value.
+    pub fn root_page(mut self, content_type: &str, body: impl Into<String>) -> Self {
+        let content_type = HeaderValue::from_str(content_type)
+            .expect("root_page content type must be a valid header value");
+        self.root_page = Some((content_type, body.into()));
+        self
+    }
+
+    /// Answers health checks on this path with a 200 for as long as the
+    /// server runs. Defaults to `/healthz`, `None` leaves the route out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the path does not start with a `/`.
+    pub fn health_path(mut self, path: Option<String>) -> Self {
+        if let Some(path) = &path {
+            assert!(path.starts_with('/'), "health_path must start with a `/`");
+        }
+        self.health_path = path;
+        self
+    }
+
+    /// Answers readiness checks on this path, with a 200 while new tunnels are
+    /// accepted and a 503 once the server drains or every tunnel slot is
+    /// taken. Defaults to `/readyz`, `None` leaves the route out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the path does not start with a `/`.
+    pub fn ready_path(mut self, path: Option<String>) -> Self {
+        if let Some(path) = &path {
+            assert!(path.starts_with('/'), "ready_path must start with a `/`");
+        }
+        self.ready_path = path;
+        self
+    }
+
+    /// Requires clients to log in with one of these username/password pairs
+    /// (RFC 1929). Authentication is disabled while this is empty, the default.
+    pub fn credentials(mut self, credentials: HashMap<String, String>) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Applies `policy` to the tunnels of `username`, one of the users in
+    /// [`credentials`](Self::credentials). Users without a policy only get the
+    /// server wide rules.
+    pub fn user_policy(mut self, username: impl Into<String>, policy: UserPolicy) -> Self {
+        self.policies.insert(username.into(), policy);
+        self
+    }
+
+    /// Requires the websocket upgrade to carry `Authorization: Bearer <token>`,
+    /// clients without it are turned away with 401 before any SOCKS5 byte is
+    /// read. `None`, the default, accepts every upgrade.
+    pub fn auth_token(mut self, token: Option<String>) -> Self {
+        self.auth_token = token;
+        self
+    }
+
+    /// Only upgrades browser requests whose `Origin` header exactly matches one
+    /// of these, others get 403 so web pages can not use the proxy. `*` allows
+    /// any origin and is the default. Requests without an `Origin` header,
+    /// which non-browser clients usually omit, are always let through.
+    pub fn allowed_origins(mut self, origins: impl IntoIterator<Item = String>) -> Self {
+        self.allowed_origins = origins.into_iter().collect();
+        self
+    }
+
+    /// Refuses with 400 the upgrades whose `Sec-WebSocket-Protocol` header
+    /// offers only subprotocols other than [`SUBPROTOCOL`], so a client
+    /// expecting some other protocol fails at once instead of mid tunnel.
+    /// Upgrades without the header are accepted either way. Disabled by
+    /// default.
+    ///
+    /// [`SUBPROTOCOL`]: crate::SUBPROTOCOL
+    pub fn strict_subprotocol(mut self, enabled: bool) -> Self {
+        self.strict_subprotocol = enabled;
+        self
+    }
+
+    /// Answers failed handshakes and connects with the error reply of the
+    /// client's protocol, such as `\x05\x02` or a 407, followed by a close
+    /// frame whose code tells why, such as 1008 for a refused login or
+    /// target. Disabled, the websocket is closed without a word, which tells
+    /// a scanner nothing but leaves real clients guessing. Enabled by default.
+    pub fn error_replies(mut self, enabled: bool) -> Self {
+        self.error_replies = enabled;
+        self
+    }
+
+    /// Carries the tunnels of clients that ask for it in base64 text frames,
+    /// handshake included, such as a [`WsSocksClient`] with text frames
+    /// enabled, for networks that mangle or drop binary frames. Disabled,
+    /// those clients get binary frames like any other. Enabled by default.
+    ///
+    /// [`WsSocksClient`]: crate::WsSocksClient
+    pub fn text_frames(mut self, enabled: bool) -> Self {
+        self.text_frames = enabled;
+        self
+    }
+
+    /// Has [`serve`] read a PROXY protocol v1 or v2 header at the start of
+    /// every connection, as sent by a load balancer in front of the proxy,
+    /// so access control and logs see the client it names instead of the
+    /// balancer. Connections without a valid header within the handshake
+    /// timeout are dropped, so only enable this when every connection comes
+    /// through such a balancer. Disabled by default, and ignored when the
+    /// router is served some other way.
+    ///
+    /// [`serve`]: crate::serve
+    pub fn accept_proxy_protocol(mut self, enabled: bool) -> Self {
+        self.accept_proxy_protocol = enabled;
+        self
+    }
+
+    /// Refuses websocket upgrades with 403 unless the client presented a
+    /// certificate one of the [`ServerTls`] client roots issued, for
+    /// deployments where nobody gets a tunnel without one. The other
+    /// endpoints stay open to clients without a certificate, health checks
+    /// included. Disabled by default; nothing but [`serve_tls`] verifies
+    /// client certificates, so every upgrade is refused when served otherwise.
+    ///
+    /// [`ServerTls`]: crate::ServerTls
+    /// [`serve_tls`]: crate::serve_tls
+    #[cfg(feature = "mtls")]
+    pub fn require_client_certificate(mut self, required: bool) -> Self {
+        self.require_client_certificate = required;
+        self
+    }
+
+    /// Closes a tunnel once no data was relayed in either direction for this
+    /// long, and a UDP association once no datagram was, `None` keeps idle
+    /// tunnels open. Defaults to [`DEFAULT_IDLE_TIMEOUT`].
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Closes a tunnel once it was open for this long, however busy it is, so
+    /// long lived clients reconnect and spread over the servers again. Such
+    /// tunnels close as [`CloseReason::MaxLifetime`]. `None`, the default,
+    /// lets tunnels live as long as they are used.
+    ///
+    /// [`CloseReason::MaxLifetime`]: crate::CloseReason::MaxLifetime
+    pub fn max_lifetime(mut self, lifetime: Option<Duration>) -> Self {
+        self.max_lifetime = lifetime;
+        self
+    }
+
+    /// Caps each direction of a tunnel at this many bytes per second, 5 Mbps
+    /// being `625_000`. Short bursts of up to a tenth of a second's worth go
+    /// through at once. `None`, the default, relays as fast as both sides go.
+    pub fn bandwidth_limit(mut self, bytes_per_second: Option<u64>) -> Self {
+        self.bandwidth_limit = bytes_per_second;
+        self
+    }
+
+    /// Sets the size of the buffer each direction of a tunnel reads into.
+    /// Larger buffers, such as 64 KiB, take fewer reads and wakeups on fast
+    /// links, at the cost of memory: every open tunnel holds two of them, so
+    /// 10,000 tunnels with 64 KiB buffers hold 1.25 GiB. Defaults to
+    /// [`DEFAULT_BUFFER_SIZE`].
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size.max(1);
+        self
+    }
+
+    /// Caps the size of a message and of a frame clients may send, so one
+    /// huge frame can not exhaust the server's memory. A client going over it
+    /// is closed with 1009, message too big. Tunnel frames of the bundled
+    /// client are at most [`DEFAULT_MAX_FRAME_SIZE`]. Defaults to
+    /// [`DEFAULT_MAX_MESSAGE_SIZE`].
+    ///
+    /// [`DEFAULT_MAX_FRAME_SIZE`]: crate::DEFAULT_MAX_FRAME_SIZE
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size.max(1);
+        self
+    }
+
+    /// Pings clients whose tunnel carried no data for this long, so load
+    /// balancers on the path keep the websocket open. Pings do not count as
+    /// activity for the idle timeout. `None`, the default, sends no pings.
+    pub fn ping_interval(mut self, interval: Option<Duration>) -> Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    /// Gives up on a target that does not accept the connection within this
+    /// time and replies host unreachable. Defaults to [`DEFAULT_CONNECT_TIMEOUT`].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Closes the websocket of a client that has not finished negotiating and
+    /// sent its request within this time, so stalled clients do not hold on
+    /// to a task. Defaults to [`DEFAULT_HANDSHAKE_TIMEOUT`].
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Only lets clients reach destinations matching one of these rules. An
+    /// empty list, the default, allows every destination that is not denied.
+    pub fn allow(mut self, rules: impl IntoIterator<Item = TargetRule>) -> Self {
+        self.acl.allow = rules.into_iter().collect();
+        self
+    }
+
+    /// Refuses destinations matching any of these rules, even when they are
+    /// allowed. Networks never match a domain handed to a connector that
+    /// [resolves it remotely](OutboundConnector::resolves_remotely), only
+    /// rules naming the domain do.
+    pub fn deny(mut self, rules: impl IntoIterator<Item = TargetRule>) -> Self {
+        self.acl.deny = rules.into_iter().collect();
+        self
+    }
+
+    /// Only lets clients reach target ports in one of these ranges. An empty
+    /// list, the default, allows every port that is not blocked.
+    pub fn allowed_ports(mut self, ranges: impl IntoIterator<Item = RangeInclusive<u16>>) -> Self {
+        self.allowed_ports = ranges.into_iter().collect();
+        self
+    }
+
+    /// Refuses target ports in any of these ranges, even when they are
+    /// allowed. Defaults to [`DEFAULT_BLOCKED_PORTS`], pass an empty list to
+    /// let clients send mail.
+    pub fn blocked_ports(mut self, ranges: impl IntoIterator<Item = RangeInclusive<u16>>) -> Self {
+        self.blocked_ports = ranges.into_iter().collect();
+        self
+    }
+
+    /// Refuses destinations on loopback, private (RFC 1918), link-local and
+    /// unique-local addresses, so the proxy can't be used to reach the host's
+    /// internal network or cloud metadata endpoints. Domains are checked after
+    /// resolving them, except those handed to a connector that
+    /// [resolves them remotely](OutboundConnector::resolves_remotely), which
+    /// is then trusted not to reach such addresses. Enabled by default.
+    pub fn block_private_addresses(mut self, enabled: bool) -> Self {
+        self.block_private = enabled;
+        self
+    }
+
+    /// Picks which addresses of a domain target to connect to, useful when
+    /// one family has no working egress. Defaults to [`AddressFamily::Auto`].
+    pub fn address_family(mut self, family: AddressFamily) -> Self {
+        self.address_family = family;
+        self
+    }
+
+    /// Races the addresses of a target, alternating families and starting a
+    /// new attempt every 250ms until one connects, instead of trying them one
+    /// after the other. Each attempt gets the full connect timeout. This cuts
+    /// the connect latency a lot when one family is blackholed. Disabled by
+    /// default.
+    pub fn happy_eyeballs(mut self, enabled: bool) -> Self {
+        self.happy_eyeballs = enabled;
+        self
+    }
+
+    /// Opens connections to targets through `connector` instead of connecting
+    /// to them directly. Outbound socket options such as
+    /// [`outbound_bind`](Self::outbound_bind) and happy eyeballs are then up
+    /// to the connector, which is handed targets as the client named them,
+    /// see [`OutboundConnector`] for how the rules apply. UDP is still relayed
+    /// directly.
+    pub fn connector(mut self, connector: impl OutboundConnector + 'static) -> Self {
+        self.connector = Some(Connector(Arc::new(connector)));
+        self
+    }
+
+    /// Resolves the domains clients connect or bind to through `resolver`
+    /// instead of the system resolver, e.g. a [`DohResolver`] with the `doh`
+    /// feature. Defaults to [`SystemResolver`].
+    ///
+    /// [`DohResolver`]: crate::DohResolver
+    /// [`SystemResolver`]: crate::SystemResolver
+    pub fn resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = ConfiguredResolver(Arc::new(resolver));
+        self
+    }
+
+    /// Starts every outbound connection with a PROXY protocol v2 header
+    /// carrying the client's address, so targets behind the proxy can log it.
+    /// The client is only known when the router is served with connect info,
+    /// without it the header says so and the target falls back to the
+    /// connection's own address. Targets must expect the header, so this is
+    /// disabled by default, see [`proxy_protocol_targets`] to send it to some
+    /// targets only.
+    ///
+    /// [`proxy_protocol_targets`]: Self::proxy_protocol_targets
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled.then(Acl::default);
+        self
+    }
+
+    /// Sends the PROXY protocol header of [`proxy_protocol`] only to targets
+    /// matching one of these rules. A domain rule matches when the client
+    /// named the target by that domain, an IP rule the address connected to,
+    /// which is unknown behind a [`connector`] unless the client sent one.
+    ///
+    /// [`proxy_protocol`]: Self::proxy_protocol
+    /// [`connector`]: Self::connector
+    pub fn proxy_protocol_targets(mut self, rules: impl IntoIterator<Item = TargetRule>) -> Self {
+        self.proxy_protocol = Some(Acl {
+            allow: rules.into_iter().collect(),
+            deny: Vec::new(),
+        });
+        self
+    }
+
+    /// Accepts the SOCKS5 BIND command, for which the server listens for one
+    /// inbound connection and relays it to the client, as active mode FTP
+    /// needs. The listener waits up to the idle timeout and takes the first
+    /// connection from the address in the request that passes the allow and
+    /// deny lists. Each request opens a port on the server, so this is
+    /// disabled by default and BIND is refused as unsupported.
+    pub fn bind_command(mut self, enabled: bool) -> Self {
+        self.bind_command = enabled;
+        self
+    }
+
+    /// Accepts Tor's SOCKS5 RESOLVE command, `0xF0`, for which the server
+    /// resolves the domain and replies with the first address the allow and
+    /// deny lists let through, without connecting anywhere, so clients can
+    /// have their DNS leave through the proxy too. The request's port is not
+    /// checked, the target rewrite and [`on_connect`](Self::on_connect) hooks
+    /// are called as for CONNECT. Disabled by default, which leaves RESOLVE
+    /// unsupported; RESOLVE_PTR, `0xF1`, is unsupported either way.
+    pub fn resolve_command(mut self, enabled: bool) -> Self {
+        self.resolve_command = enabled;
+        self
+    }
+
+    /// Connects requests for a domain like `unix:/run/app.sock` to that Unix
+    /// domain socket, ignoring their port, for services on the same host
+    /// that listen on no TCP port. Only the sockets at these paths are
+    /// reachable, other `unix:` domains are refused as not allowed by the
+    /// ruleset; the allow and deny lists and port rules do not apply to
+    /// them. Other domains are resolved as usual. Empty by default, and such
+    /// a domain is then resolved like any other, which fails.
+    #[cfg(unix)]
+    pub fn unix_sockets(mut self, paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        self.unix_sockets = paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Calls `hook` with the target of each CONNECT and RESOLVE request
+    /// before anything else looks at it, and connects to or resolves the
+    /// target it returns instead, if any, e.g. to send `example.com:80` to a
+    /// staging host. The new target goes through the allow and deny lists,
+    /// the port rules and [`on_connect`](Self::on_connect), and is what logs
+    /// and hooks see. BIND requests and UDP datagrams keep their targets.
+    pub fn rewrite_target(
+        mut self,
+        hook: impl Fn(&Target) -> Option<Target> + Send + Sync + 'static,
+    ) -> Self {
+        self.rewrite_target = Some(Hook(Arc::new(hook)));
+        self
+    }
+
+    /// Calls `hook` for each tunnel that passed the allow and deny lists,
+    /// right before connecting to its target, e.g. to check a quota or a
+    /// dynamic allow list. Returning `false` refuses the tunnel as not allowed
+    /// by the ruleset. The hook runs on the tunnel's task, so it should not
+    /// block. UDP associations are not passed to it, BIND requests are, and
+    /// so are RESOLVE requests, before they are resolved.
+    pub fn on_connect(
+        mut self,
+        hook: impl Fn(&TunnelRequest) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.on_connect = Some(Hook(Arc::new(hook)));
+        self
+    }
+
+    /// Calls `hook` with the totals of each relayed tunnel once it closes,
+    /// e.g. for billing or auditing.
+    pub fn on_close(mut self, hook: impl Fn(&TunnelStats) + Send + Sync + 'static) -> Self {
+        self.on_close = Some(Hook(Arc::new(hook)));
+        self
+    }
+
+    /// Runs `classifier` on the host of each target before anything else is
+    /// checked, e.g. to spot honeypots. A target it returns
+    /// [`Verdict::BlockAndFlag`] for is not connected to, the WebSocket is
+    /// closed without a reply and the client is passed to [`on_abuse`], if
+    /// set. Like [`on_connect`] it sees CONNECT and BIND requests but not UDP
+    /// associations, and should not block.
+    ///
+    /// [`on_abuse`]: Self::on_abuse
+    /// [`on_connect`]: Self::on_connect
+    pub fn abuse_classifier(
+        mut self,
+        classifier: impl Fn(&str) -> Verdict + Send + Sync + 'static,
+    ) -> Self {
+        self.abuse_classifier = Some(Hook(Arc::new(classifier)));
+        self
+    }
+
+    /// Calls `hook` with the client and target of each tunnel flagged by the
+    /// [`abuse_classifier`], so the operator can ban its address or
+    /// credentials.
+    ///
+    /// [`abuse_classifier`]: Self::abuse_classifier
+    pub fn on_abuse(mut self, hook: impl Fn(&TunnelRequest) + Send + Sync + 'static) -> Self {
+        self.on_abuse = Some(Hook(Arc::new(hook)));
+        self
+    }
+
+    /// Connects to targets, and relays UDP, from this local address, to pick
+    /// the egress interface on a multi-homed host. Only targets of the same
+    /// family as the address are reachable. `None`, the default, leaves the
+    /// choice to the OS.
+    pub fn outbound_bind(mut self, addr: Option<IpAddr>) -> Self {
+        self.outbound_bind = addr;
+        self
+    }
+
+    /// Connects to targets from this local port, for firewalls that pin the
+    /// proxy's traffic by source port. Tunnels share the port as long as
+    /// they go to different targets; when it is taken, by another program or
+    /// a tunnel to the same target, the connection falls back to an
+    /// ephemeral port. Set it for users alone with
+    /// [`UserPolicy::outbound_source_port`]. `None`, the default, always
+    /// lets the OS pick one.
+    ///
+    /// [`UserPolicy::outbound_source_port`]: crate::UserPolicy::outbound_source_port
+    pub fn outbound_source_port(mut self, port: Option<u16>) -> Self {
+        self.outbound_source_port = port;
+        self
+    }
+
+    /// Connects to link-local IPv6 targets (`fe80::/10`) through the
+    /// interface with this index, as they are unreachable without one. Such
+    /// targets are private, so [`block_private_addresses`] must be disabled
+    /// too. `None`, the default, leaves their scope to the OS.
+    ///
+    /// [`block_private_addresses`]: Self::block_private_addresses
+    pub fn ipv6_scope_id(mut self, scope_id: Option<u32>) -> Self {
+        self.ipv6_scope_id = scope_id;
+        self
+    }
+
+    /// Tags outbound sockets with this `SO_MARK`, so policy routing rules can
+    /// send tunneled traffic through another table or a VPN. Setting the mark
+    /// needs `CAP_NET_ADMIN`, without it every connect fails. `None`, the
+    /// default, leaves sockets untagged.
+    #[cfg(all(feature = "fwmark", target_os = "linux"))]
+    pub fn fwmark(mut self, mark: Option<u32>) -> Self {
+        self.fwmark = mark;
+        self
+    }
+
+    /// Opens outbound sockets inside this network namespace, named as by
+    /// `ip netns add` or by a path such as `/proc/1/ns/net`, so tunneled
+    /// traffic leaves through its interfaces and routes. Entering it needs
+    /// `CAP_SYS_ADMIN`, without it every connect fails. `None`, the default,
+    /// keeps the proxy's own namespace. A custom connector opens its own
+    /// sockets and is not moved.
+    #[cfg(all(feature = "netns", target_os = "linux"))]
+    pub fn netns(mut self, netns: Option<&str>) -> Self {
+        self.netns = netns.map(|netns| Arc::new(NetNs::new(netns)));
+        self
+    }
+
+    /// Sets `TCP_NODELAY` on outbound connections, so small writes of
+    /// interactive protocols like SSH go out at once instead of waiting on
+    /// Nagle's algorithm. Enabled by default.
+    pub fn tcp_nodelay(mut self, enabled: bool) -> Self {
+        self.tcp_nodelay = enabled;
+        self
+    }
+
+    /// Enables TCP keepalive on outbound connections, probing a target after
+    /// `idle` without traffic and then every `interval`, so dead peers are
+    /// noticed. Disabled by default.
+    pub fn tcp_keepalive(mut self, idle: Duration, interval: Duration) -> Self {
+        self.tcp_keepalive = Some((idle, interval));
+        self
+    }
+
+    /// Caps the number of tunnels open at the same time. A client over the cap
+    /// waits up to a second for a free slot and is then refused. `None`, the
+    /// default, admits every client.
+    pub fn max_tunnels(mut self, max: Option<usize>) -> Self {
+        self.tunnel_limit = max.map(|max| Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    /// Refuses new upgrades with 503 while `max` tunnels are open, as a
+    /// safety valve under a connection storm. Open tunnels are kept, and
+    /// refused clients cost no more than the HTTP request, unlike those over
+    /// [`max_tunnels`](Self::max_tunnels). `None`, the default, sets no such
+    /// limit.
+    pub fn soft_max_tunnels(mut self, max: Option<usize>) -> Self {
+        self.soft_max_tunnels = max;
+        self
+    }
+
+    /// Refuses new upgrades with 503 while one more tunnel could take the
+    /// memory the tunnels buffer past `bytes`, so the server sheds load
+    /// instead of being killed for running out of memory. Each tunnel is
+    /// counted at the most it buffers, a [`buffer_size`](Self::buffer_size)
+    /// read buffer each way and one
+    /// [`max_message_size`](Self::max_message_size) message. `None`, the
+    /// default, sets no such limit.
+    pub fn soft_memory_limit(mut self, bytes: Option<usize>) -> Self {
+        self.soft_memory_limit = bytes;
+        self
+    }
+
+    // too many tunnels open, or buffering too much, to take another
+    pub(crate) fn under_pressure(&self) -> bool {
+        let active = self.shutdown.active();
+        let per_tunnel = 2 * self.buffer_size + self.max_message_size;
+        self.soft_max_tunnels.is_some_and(|max| active >= max)
+            || self
+                .soft_memory_limit
+                .is_some_and(|limit| (active + 1).saturating_mul(per_tunnel) > limit)
+    }
+
+    /// Limits how often each client may open a tunnel, see [`RateLimit`].
+    /// Disabled by default.
+    pub fn rate_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.rate_limit = limit;
+        self
+    }
+
+    /// Admits clients by the country of their IP address, see [`GeoIp`].
+    /// Disabled by default.
+    #[cfg(feature = "geoip")]
+    pub fn geoip(mut self, geoip: Option<GeoIp>) -> Self {
+        self.geoip = geoip;
+        self
+    }
+
+    /// Ties the proxy to this handle, so [`Shutdown::drain`] stops it.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Deflates the tunnels of clients that ask for it, such as a
+    /// [`WsSocksClient`](crate::WsSocksClient) with compression enabled.
+    /// Disabled by default.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    /// Serves the Prometheus metrics on this path, `None` (the default) leaves
+    /// the endpoint out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the path does not start with a `/`.
+    #[cfg(feature = "prometheus")]
+    pub fn metrics_path(mut self, path: Option<String>) -> Self {
+        if let Some(path) = &path {
+            assert!(path.starts_with('/'), "metrics_path must start with a `/`");
+        }
+        self.metrics_path = path;
+        self
+    }
+
+    /// Writes a JSON record of every closed tunnel to `log`, see
+    /// [`AccessLog`] for the fields. `None`, the default, keeps no access log.
+    #[cfg(feature = "access-log")]
+    pub fn access_log(mut self, log: Option<AccessLog>) -> Self {
+        self.access_log = log;
+        self
+    }
+
+    /// Serves a JSON array of the tunnels relaying right now on this path,
+    /// each with its `conn_id`, `client_ip`, `user`, `target`, `bytes_up`,
+    /// `bytes_down` and `age_secs`. A `DELETE` of the path followed by
+    /// `/<conn_id>` kills that tunnel, which closes with
+    /// [`CLOSE_KILLED`](crate::CLOSE_KILLED), and logs who asked. Only
+    /// requests with the [`admin_token`](Self::admin_token) as their bearer
+    /// token get through, others get 401. `None` (the default) leaves the
+    /// endpoint out.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the path does not start with a `/`.
+    #[cfg(feature = "admin")]
+    pub fn admin_path(mut self, path: Option<String>) -> Self {
+        if let Some(path) = &path {
+            assert!(path.starts_with('/'), "admin_path must start with a `/`");
+        }
+        self.admin_path = path;
+        self
+    }
+
+    /// The bearer token the admin endpoint asks for, kept apart from the
+    /// tunnels' [`auth_token`](Self::auth_token). `None`, the default,
+    /// refuses every request.
+    #[cfg(feature = "admin")]
+    pub fn admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = token;
+        self
+    }
+
+    pub(crate) fn origin_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Checks the config for entries that would fail or never match once
+    /// served, for a dry run before deploying. Target rules are checked as
+    /// they are parsed, so this looks at what the builders take as is:
+    /// timeouts and limits of zero, port ranges that end before they start,
+    /// credentials SOCKS5 can not carry, policies for unknown users, outbound
+    /// bind addresses that are not this host's, and routes sharing a path.
+    /// Returns the first problem found.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// let config = wssocks::ProxyConfig::default().connect_timeout(Duration::ZERO);
+    /// assert_eq!(
+    ///     config.validate().unwrap_err().to_string(),
+    ///     "connect_timeout must not be zero"
+    /// );
+    /// ```
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let durations = [
+            ("connect_timeout", Some(self.connect_timeout)),
+            ("handshake_timeout", Some(self.handshake_timeout)),
+            ("idle_timeout", self.idle_timeout),
+            ("max_lifetime", self.max_lifetime),
+            ("ping_interval", self.ping_interval),
+            (
+                "tcp_keepalive idle",
+                self.tcp_keepalive.map(|(idle, _)| idle),
+            ),
+            (
+                "tcp_keepalive interval",
+                self.tcp_keepalive.map(|(_, interval)| interval),
+            ),
+        ];
+        for (name, duration) in durations {
+            if duration == Some(Duration::ZERO) {
+                return Err(ConfigError::Zero(name));
+            }
+        }
+        if self.bandwidth_limit == Some(0) {
+            return Err(ConfigError::Zero("bandwidth_limit"));
+        }
+        // port 0 asks for an ephemeral port, which is what None means
+        let mut source_ports = std::iter::once(self.outbound_source_port).chain(
+            self.policies
+                .values()
+                .map(|policy| policy.outbound_source_port),
+        );
+        if source_ports.any(|port| port == Some(0)) {
+            return Err(ConfigError::Zero("outbound_source_port"));
+        }
+        if self.auth_token.as_deref() == Some("") {
+            return Err(ConfigError::EmptyAuthToken);
+        }
+
+        let ports = [
+            ("allowed_ports", &self.allowed_ports),
+            ("blocked_ports", &self.blocked_ports),
+        ];
+        for (name, ranges) in ports {
+            if let Some(range) = ranges.iter().find(|range| range.is_empty()) {
+                return Err(ConfigError::EmptyPortRange(name, range.clone()));
+            }
+        }
+
+        for (username, password) in &self.credentials {
+            if username.is_empty() || username.len() > 255 || password.len() > 255 {
+                return Err(ConfigError::BadCredentials(username.clone()));
+            }
+        }
+        for username in self.policies.keys() {
+            if !self.credentials.contains_key(username) {
+                return Err(ConfigError::UnknownPolicyUser(username.clone()));
+            }
+        }
+
+        let binds = std::iter::once((None, self.outbound_bind)).chain(
+            self.policies
+                .iter()
+                .map(|(username, policy)| (Some(username), policy.outbound_bind)),
+        );
+        for (username, addr) in binds {
+            let Some(addr) = addr else { continue };
+            if std::net::TcpListener::bind((addr, 0)).is_err() {
+                return Err(ConfigError::BadBindAddress(username.cloned(), addr));
+            }
+        }
+
+        let mut paths = vec![self.ws_path.as_str()];
+        if self.root {
+            paths.push("/");
+        }
+        paths.extend(self.health_path.as_deref());
+        paths.extend(self.ready_path.as_deref());
+        #[cfg(feature = "prometheus")]
+        paths.extend(self.metrics_path.as_deref());
+        #[cfg(feature = "admin")]
+        paths.extend(self.admin_path.as_deref());
+        for (i, path) in paths.iter().enumerate() {
+            if paths[..i].contains(path) {
+                return Err(ConfigError::DuplicatePath(path.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn port_allowed(&self, port: u16) -> bool {
+        !self.blocked_ports.iter().any(|range| range.contains(&port))
+            && (self.allowed_ports.is_empty()
+                || self.allowed_ports.iter().any(|range| range.contains(&port)))
+    }
+
+    pub(crate) fn verify(&self, username: &str, password: &str) -> bool {
+        self.credentials
+            .get(username)
+            .is_some_and(|expected| expected == password)
+    }
+}
+
+// the builders panic on a path without its leading slash
+pub(crate) fn check_path(name: &str, path: String) -> Result<String, String> {
+    if !path.starts_with('/') {
+        return Err(format!("{} `{}` must start with a `/`", name, path));
+    }
+    Ok(path)
+}
+
+pub(crate) fn parse_rules(rules: Vec<String>) -> Result<Vec<TargetRule>, String> {
+    rules
+        .iter()
+        .map(|rule| {
+            rule.parse()
+                .map_err(|e: crate::acl::ParseRuleError| e.to_string())
+        })
+        .collect()
+}
+
+// "443" or "8000-9000"
+pub(crate) fn parse_port_ranges(ranges: Vec<String>) -> Result<Vec<RangeInclusive<u16>>, String> {
+    ranges
+        .iter()
+        .map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            match (start.trim().parse(), end.trim().parse()) {
+                (Ok(start), Ok(end)) => Ok(start..=end),
+                _ => Err(format!("invalid port range `{}`", range)),
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn seconds(name: &str, secs: f64) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid {} of {} seconds", name, secs))
+}
+
+/// What [`ProxyConfig::validate`] found wrong with a config, or why one could
+/// not be loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A timeout, interval or limit that must not be zero, named by its
+    /// builder.
+    Zero(&'static str),
+    /// The auth token is the empty string.
+    EmptyAuthToken,
+    /// A range in `allowed_ports` or `blocked_ports` ends before it starts.
+    EmptyPortRange(&'static str, RangeInclusive<u16>),
+    /// The username is empty, or it or its password is longer than the 255
+    /// bytes a SOCKS5 login carries.
+    BadCredentials(String),
+    /// A policy names a user without credentials.
+    UnknownPolicyUser(String),
+    /// An outbound bind address no socket can be bound to on this host, with
+    /// the user whose policy sets it.
+    BadBindAddress(Option<String>, IpAddr),
+    /// Two routes share this path.
+    DuplicatePath(String),
+    /// A config file could not be read or holds an invalid setting, with
+    /// its path and what is wrong. Only loading one with the `config-file`
+    /// feature returns it, the variant is there either way so matches need
+    /// not depend on features.
+    File(String),
+    /// An environment variable holds an invalid setting, with its name and
+    /// what is wrong.
+    Env(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Zero(name) => write!(f, "{} must not be zero", name),
+            ConfigError::EmptyAuthToken => f.write_str("auth_token must not be empty"),
+            ConfigError::EmptyPortRange(name, range) => {
+                write!(f, "{} range {:?} is empty", name, range)
+            }
+            ConfigError::BadCredentials(username) => write!(
+                f,
+                "credentials of user `{}` must be 1 to 255 bytes",
+                username
+            ),
+            ConfigError::UnknownPolicyUser(username) => {
+                write!(f, "user_policy for `{}`, who has no credentials", username)
+            }
+            ConfigError::BadBindAddress(None, addr) => {
+                write!(f, "outbound_bind {} is not an address of this host", addr)
+            }
+            ConfigError::BadBindAddress(Some(username), addr) => write!(
+                f,
+                "outbound_bind {} of user `{}` is not an address of this host",
+                addr, username
+            ),
+            ConfigError::DuplicatePath(path) => write!(f, "two routes on path {}", path),
+            ConfigError::File(message) => f.write_str(message),
+            ConfigError::Env(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
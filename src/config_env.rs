@@ -0,0 +1,196 @@
+use std::str::FromStr;
+
+use crate::config::{
+    check_path, parse_port_ranges, parse_rules, seconds, ConfigError, ProxyConfig,
+};
+
+impl ProxyConfig {
+    /// Builds a config from the defaults, then the file named by
+    /// `WSSOCKS_CONFIG` with the `config-file` feature, then the environment,
+    /// each overriding the one before. See [`merge_env`](Self::merge_env)
+    /// for the variables read.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let config = Self::default();
+        #[cfg(feature = "config-file")]
+        let config = match std::env::var_os("WSSOCKS_CONFIG") {
+            Some(path) => config.merge_file(path)?,
+            None => config,
+        };
+        config.merge_env()
+    }
+
+    /// Applies the `WSSOCKS_*` environment variables over this config, for
+    /// container deployments. Unset and empty variables keep the config's
+    /// setting. Durations are in seconds, lists are comma separated and
+    /// switches are `true` or `false`:
+    ///
+    /// | Variable | Sets |
+    /// | --- | --- |
+    /// | `WSSOCKS_WS_PATH` | [`ws_path`](Self::ws_path) |
+    /// | `WSSOCKS_HEALTH_PATH` | [`health_path`](Self::health_path) |
+    /// | `WSSOCKS_READY_PATH` | [`ready_path`](Self::ready_path) |
+    /// | `WSSOCKS_AUTH_TOKEN` | [`auth_token`](Self::auth_token) |
+    /// | `WSSOCKS_AUTH_USER`, `WSSOCKS_AUTH_PASS` | one more pair of [`credentials`](Self::credentials), both or neither |
+    /// | `WSSOCKS_ALLOWED_ORIGINS` | [`allowed_origins`](Self::allowed_origins) |
+    /// | `WSSOCKS_ALLOW` | [`allow`](Self::allow) |
+    /// | `WSSOCKS_DENY` | [`deny`](Self::deny) |
+    /// | `WSSOCKS_ALLOWED_PORTS` | [`allowed_ports`](Self::allowed_ports), as `443` or `8000-9000` |
+    /// | `WSSOCKS_BLOCKED_PORTS` | [`blocked_ports`](Self::blocked_ports), as above |
+    /// | `WSSOCKS_BLOCK_PRIVATE_ADDRESSES` | [`block_private_addresses`](Self::block_private_addresses) |
+    /// | `WSSOCKS_OUTBOUND_BIND` | [`outbound_bind`](Self::outbound_bind) |
+    /// | `WSSOCKS_OUTBOUND_SOURCE_PORT` | [`outbound_source_port`](Self::outbound_source_port) |
+    /// | `WSSOCKS_CONNECT_TIMEOUT` | [`connect_timeout`](Self::connect_timeout) |
+    /// | `WSSOCKS_HANDSHAKE_TIMEOUT` | [`handshake_timeout`](Self::handshake_timeout) |
+    /// | `WSSOCKS_IDLE_TIMEOUT` | [`idle_timeout`](Self::idle_timeout) |
+    /// | `WSSOCKS_MAX_LIFETIME` | [`max_lifetime`](Self::max_lifetime) |
+    /// | `WSSOCKS_PING_INTERVAL` | [`ping_interval`](Self::ping_interval) |
+    /// | `WSSOCKS_BANDWIDTH_LIMIT` | [`bandwidth_limit`](Self::bandwidth_limit), in bytes per second |
+    /// | `WSSOCKS_BUFFER_SIZE` | [`buffer_size`](Self::buffer_size), in bytes |
+    /// | `WSSOCKS_MAX_MESSAGE_SIZE` | [`max_message_size`](Self::max_message_size), in bytes |
+    /// | `WSSOCKS_MAX_TUNNELS` | [`max_tunnels`](Self::max_tunnels) |
+    /// | `WSSOCKS_SOFT_MAX_TUNNELS` | [`soft_max_tunnels`](Self::soft_max_tunnels) |
+    /// | `WSSOCKS_SOFT_MEMORY_LIMIT` | [`soft_memory_limit`](Self::soft_memory_limit), in bytes |
+    pub fn merge_env(self) -> Result<Self, ConfigError> {
+        self.merge_vars(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+    }
+
+    // apply the variables var looks up, Some only for set ones
+    fn merge_vars(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let path = |name: &str, builder: &str| {
+            var(name)
+                .map(|path| check_path(builder, path).map_err(|e| failed(name, e)))
+                .transpose()
+        };
+        let list = |name: &str| {
+            var(name).map(|value| {
+                value
+                    .split(',')
+                    .map(|item| item.trim().to_string())
+                    .filter(|item| !item.is_empty())
+                    .collect::<Vec<_>>()
+            })
+        };
+        let duration = |name: &str| match parse(name, var(name)) {
+            Ok(Some(secs)) => seconds("duration", secs)
+                .map(Some)
+                .map_err(|e| failed(name, e)),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        };
+
+        if let Some(path) = path("WSSOCKS_WS_PATH", "ws_path")? {
+            self = self.ws_path(path);
+        }
+        if let Some(path) = path("WSSOCKS_HEALTH_PATH", "health_path")? {
+            self = self.health_path(Some(path));
+        }
+        if let Some(path) = path("WSSOCKS_READY_PATH", "ready_path")? {
+            self = self.ready_path(Some(path));
+        }
+        if let Some(token) = var("WSSOCKS_AUTH_TOKEN") {
+            self = self.auth_token(Some(token));
+        }
+        match (var("WSSOCKS_AUTH_USER"), var("WSSOCKS_AUTH_PASS")) {
+            (Some(username), Some(password)) => {
+                self.credentials.insert(username, password);
+            }
+            (None, None) => {}
+            (Some(_), None) => {
+                return Err(failed(
+                    "WSSOCKS_AUTH_PASS",
+                    "not set next to WSSOCKS_AUTH_USER".into(),
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(failed(
+                    "WSSOCKS_AUTH_USER",
+                    "not set next to WSSOCKS_AUTH_PASS".into(),
+                ))
+            }
+        }
+        if let Some(origins) = list("WSSOCKS_ALLOWED_ORIGINS") {
+            self = self.allowed_origins(origins);
+        }
+        if let Some(rules) = list("WSSOCKS_ALLOW") {
+            self = self.allow(parse_rules(rules).map_err(|e| failed("WSSOCKS_ALLOW", e))?);
+        }
+        if let Some(rules) = list("WSSOCKS_DENY") {
+            self = self.deny(parse_rules(rules).map_err(|e| failed("WSSOCKS_DENY", e))?);
+        }
+        if let Some(ranges) = list("WSSOCKS_ALLOWED_PORTS") {
+            let ranges =
+                parse_port_ranges(ranges).map_err(|e| failed("WSSOCKS_ALLOWED_PORTS", e))?;
+            self = self.allowed_ports(ranges);
+        }
+        if let Some(ranges) = list("WSSOCKS_BLOCKED_PORTS") {
+            let ranges =
+                parse_port_ranges(ranges).map_err(|e| failed("WSSOCKS_BLOCKED_PORTS", e))?;
+            self = self.blocked_ports(ranges);
+        }
+        if let Some(enabled) = parse(
+            "WSSOCKS_BLOCK_PRIVATE_ADDRESSES",
+            var("WSSOCKS_BLOCK_PRIVATE_ADDRESSES"),
+        )? {
+            self = self.block_private_addresses(enabled);
+        }
+        if let Some(addr) = parse("WSSOCKS_OUTBOUND_BIND", var("WSSOCKS_OUTBOUND_BIND"))? {
+            self = self.outbound_bind(Some(addr));
+        }
+        let source_port = var("WSSOCKS_OUTBOUND_SOURCE_PORT");
+        if let Some(port) = parse("WSSOCKS_OUTBOUND_SOURCE_PORT", source_port)? {
+            self = self.outbound_source_port(Some(port));
+        }
+        if let Some(timeout) = duration("WSSOCKS_CONNECT_TIMEOUT")? {
+            self = self.connect_timeout(timeout);
+        }
+        if let Some(timeout) = duration("WSSOCKS_HANDSHAKE_TIMEOUT")? {
+            self = self.handshake_timeout(timeout);
+        }
+        if let Some(timeout) = duration("WSSOCKS_IDLE_TIMEOUT")? {
+            self = self.idle_timeout(Some(timeout));
+        }
+        if let Some(lifetime) = duration("WSSOCKS_MAX_LIFETIME")? {
+            self = self.max_lifetime(Some(lifetime));
+        }
+        if let Some(interval) = duration("WSSOCKS_PING_INTERVAL")? {
+            self = self.ping_interval(Some(interval));
+        }
+        if let Some(limit) = parse("WSSOCKS_BANDWIDTH_LIMIT", var("WSSOCKS_BANDWIDTH_LIMIT"))? {
+            self = self.bandwidth_limit(Some(limit));
+        }
+        if let Some(size) = parse("WSSOCKS_BUFFER_SIZE", var("WSSOCKS_BUFFER_SIZE"))? {
+            self = self.buffer_size(size);
+        }
+        let max_message_size = var("WSSOCKS_MAX_MESSAGE_SIZE");
+        if let Some(size) = parse("WSSOCKS_MAX_MESSAGE_SIZE", max_message_size)? {
+            self = self.max_message_size(size);
+        }
+        if let Some(max) = parse("WSSOCKS_MAX_TUNNELS", var("WSSOCKS_MAX_TUNNELS"))? {
+            self = self.max_tunnels(Some(max));
+        }
+        if let Some(max) = parse("WSSOCKS_SOFT_MAX_TUNNELS", var("WSSOCKS_SOFT_MAX_TUNNELS"))? {
+            self = self.soft_max_tunnels(Some(max));
+        }
+        let soft_memory_limit = var("WSSOCKS_SOFT_MEMORY_LIMIT");
+        if let Some(bytes) = parse("WSSOCKS_SOFT_MEMORY_LIMIT", soft_memory_limit)? {
+            self = self.soft_memory_limit(Some(bytes));
+        }
+        Ok(self)
+    }
+}
+
+// the error naming the variable
+fn failed(name: &str, message: String) -> ConfigError {
+    ConfigError::Env(format!("{}: {}", name, message))
+}
+
+fn parse<T: FromStr>(name: &str, value: Option<String>) -> Result<Option<T>, ConfigError> {
+    value
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| failed(name, format!("invalid value `{}`", value)))
+        })
+        .transpose()
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::config::{
+    check_path, parse_port_ranges, parse_rules, seconds, AddressFamily, ConfigError, ProxyConfig,
+};
+use crate::rate_limit::RateLimit;
+
+// the settings a config file may hold, each left out keeps the config's own,
+// durations are in seconds and port ranges like "8000-9000"
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    ws_path: Option<String>,
+    health_path: Option<String>,
+    ready_path: Option<String>,
+    root: Option<bool>,
+    auth_token: Option<String>,
+    credentials: Option<HashMap<String, String>>,
+    allowed_origins: Option<Vec<String>>,
+    strict_subprotocol: Option<bool>,
+    error_replies: Option<bool>,
+    text_frames: Option<bool>,
+    accept_proxy_protocol: Option<bool>,
+    #[cfg(feature = "mtls")]
+    require_client_certificate: Option<bool>,
+    allow: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
+    allowed_ports: Option<Vec<String>>,
+    blocked_ports: Option<Vec<String>>,
+    block_private_addresses: Option<bool>,
+    address_family: Option<AddressFamily>,
+    happy_eyeballs: Option<bool>,
+    bind_command: Option<bool>,
+    resolve_command: Option<bool>,
+    #[cfg(unix)]
+    unix_sockets: Option<Vec<PathBuf>>,
+    proxy_protocol: Option<bool>,
+    outbound_bind: Option<IpAddr>,
+    outbound_source_port: Option<u16>,
+    connect_timeout: Option<f64>,
+    handshake_timeout: Option<f64>,
+    idle_timeout: Option<f64>,
+    max_lifetime: Option<f64>,
+    ping_interval: Option<f64>,
+    bandwidth_limit: Option<u64>,
+    buffer_size: Option<usize>,
+    max_message_size: Option<usize>,
+    max_tunnels: Option<usize>,
+    soft_max_tunnels: Option<usize>,
+    soft_memory_limit: Option<usize>,
+    rate_limit: Option<FileRateLimit>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileRateLimit {
+    per_minute: u32,
+    burst: u32,
+}
+
+impl ProxyConfig {
+    /// Loads a config from the TOML or JSON file at `path`, told apart by its
+    /// `.toml` or `.json` extension, with the defaults for everything it
+    /// leaves out. See [`merge_file`](Self::merge_file) for the format.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        Self::default().merge_file(path)
+    }
+
+    /// Applies the settings in the TOML or JSON file at `path` over this
+    /// config, so the file can hold what operators change while hooks and
+    /// connectors stay in code. Keys are named after the builders and take
+    /// the same values, with durations in seconds and port ranges as `"443"`
+    /// or `"8000-9000"`:
+    ///
+    /// ```toml
+    /// ws_path = "/tunnel"
+    /// credentials = { alice = "secret" }
+    /// allow = ["example.com", "10.0.0.0/8"]
+    /// blocked_ports = ["25", "465", "587"]
+    /// idle_timeout = 300
+    /// max_tunnels = 1000
+    /// rate_limit = { per_minute = 60, burst = 10 }
+    /// ```
+    ///
+    /// Unknown keys are refused, to catch typos. The result is not
+    /// [validated](Self::validate).
+    pub fn merge_file(self, path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let failed =
+            |message: String| ConfigError::File(format!("{}: {}", path.display(), message));
+        let text = std::fs::read_to_string(path).map_err(|e| failed(e.to_string()))?;
+        let file: FileConfig = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&text).map_err(|e| failed(e.to_string()))?,
+            Some("json") => serde_json::from_str(&text).map_err(|e| failed(e.to_string()))?,
+            _ => return Err(failed("expected a .toml or .json file".to_string())),
+        };
+        file.apply(self).map_err(failed)
+    }
+}
+
+impl FileConfig {
+    fn apply(self, mut config: ProxyConfig) -> Result<ProxyConfig, String> {
+        if let Some(path) = self.ws_path {
+            config = config.ws_path(check_path("ws_path", path)?);
+        }
+        if let Some(path) = self.health_path {
+            config = config.health_path(Some(check_path("health_path", path)?));
+        }
+        if let Some(path) = self.ready_path {
+            config = config.ready_path(Some(check_path("ready_path", path)?));
+        }
+        if let Some(enabled) = self.root {
+            config = config.root(enabled);
+        }
+        if let Some(token) = self.auth_token {
+            config = config.auth_token(Some(token));
+        }
+        if let Some(credentials) = self.credentials {
+            config = config.credentials(credentials);
+        }
+        if let Some(origins) = self.allowed_origins {
+            config = config.allowed_origins(origins);
+        }
+        if let Some(enabled) = self.strict_subprotocol {
+            config = config.strict_subprotocol(enabled);
+        }
+        if let Some(enabled) = self.error_replies {
+            config = config.error_replies(enabled);
+        }
+        if let Some(enabled) = self.text_frames {
+            config = config.text_frames(enabled);
+        }
+        if let Some(enabled) = self.accept_proxy_protocol {
+            config = config.accept_proxy_protocol(enabled);
+        }
+        #[cfg(feature = "mtls")]
+        if let Some(required) = self.require_client_certificate {
+            config = config.require_client_certificate(required);
+        }
+        if let Some(rules) = self.allow {
+            config = config.allow(parse_rules(rules)?);
+        }
+        if let Some(rules) = self.deny {
+            config = config.deny(parse_rules(rules)?);
+        }
+        if let Some(ranges) = self.allowed_ports {
+            config = config.allowed_ports(parse_port_ranges(ranges)?);
+        }
+        if let Some(ranges) = self.blocked_ports {
+            config = config.blocked_ports(parse_port_ranges(ranges)?);
+        }
+        if let Some(enabled) = self.block_private_addresses {
+            config = config.block_private_addresses(enabled);
+        }
+        if let Some(family) = self.address_family {
+            config = config.address_family(family);
+        }
+        if let Some(enabled) = self.happy_eyeballs {
+            config = config.happy_eyeballs(enabled);
+        }
+        if let Some(enabled) = self.bind_command {
+            config = config.bind_command(enabled);
+        }
+        if let Some(enabled) = self.resolve_command {
+            config = config.resolve_command(enabled);
+        }
+        #[cfg(unix)]
+        if let Some(paths) = self.unix_sockets {
+            config = config.unix_sockets(paths);
+        }
+        if let Some(enabled) = self.proxy_protocol {
+            config = config.proxy_protocol(enabled);
+        }
+        if let Some(addr) = self.outbound_bind {
+            config = config.outbound_bind(Some(addr));
+        }
+        if let Some(port) = self.outbound_source_port {
+            config = config.outbound_source_port(Some(port));
+        }
+        if let Some(secs) = self.connect_timeout {
+            config = config.connect_timeout(seconds("connect_timeout", secs)?);
+        }
+        if let Some(secs) = self.handshake_timeout {
+            config = config.handshake_timeout(seconds("handshake_timeout", secs)?);
+        }
+        if let Some(secs) = self.idle_timeout {
+            config = config.idle_timeout(Some(seconds("idle_timeout", secs)?));
+        }
+        if let Some(secs) = self.max_lifetime {
+            config = config.max_lifetime(Some(seconds("max_lifetime", secs)?));
+        }
+        if let Some(secs) = self.ping_interval {
+            config = config.ping_interval(Some(seconds("ping_interval", secs)?));
+        }
+        if let Some(limit) = self.bandwidth_limit {
+            config = config.bandwidth_limit(Some(limit));
+        }
+        if let Some(size) = self.buffer_size {
+            config = config.buffer_size(size);
+        }
+        if let Some(size) = self.max_message_size {
+            config = config.max_message_size(size);
+        }
+        if let Some(max) = self.max_tunnels {
+            config = config.max_tunnels(Some(max));
+        }
+        if let Some(max) = self.soft_max_tunnels {
+            config = config.soft_max_tunnels(Some(max));
+        }
+        if let Some(bytes) = self.soft_memory_limit {
+            config = config.soft_memory_limit(Some(bytes));
+        }
+        if let Some(limit) = self.rate_limit {
+            config = config.rate_limit(Some(RateLimit::new(limit.per_minute, limit.burst)));
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,877 @@
+use std::time::Duration;
+use std::{future::Future, pin::Pin, task::Poll};
+
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::{BufMut, BytesMut};
+use futures::{ready, Sink, Stream};
+use pin_project::pin_project;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time::{sleep_until, Instant, Sleep},
+};
+use tokio_tungstenite::tungstenite;
+
+/// Default upper bound for the payload of a single outbound frame, 16 KiB.
+///
+/// Larger frames mean less framing overhead on fast links, smaller ones keep
+/// latency low on slow or lossy links.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024;
+
+/// The WebSocket subprotocol spoken in a tunnel, offered by clients in
+/// `Sec-WebSocket-Protocol` and echoed by the server.
+pub const SUBPROTOCOL: &str = "wssocks.v1";
+
+/// Close code of a tunnel that relayed nothing for the server's idle timeout.
+pub const CLOSE_IDLE_TIMEOUT: u16 = 4000;
+
+/// Close code of a tunnel that was open for the server's maximum lifetime.
+pub const CLOSE_MAX_LIFETIME: u16 = 4001;
+
+/// Close code of a tunnel whose handshake took longer than the server allows.
+pub const CLOSE_HANDSHAKE_TIMEOUT: u16 = 4002;
+
+/// Close code of a tunnel an operator ended through the admin endpoint.
+pub const CLOSE_KILLED: u16 = 4003;
+
+// the close code of a tunnel that ended the normal way
+pub(crate) const CLOSE_NORMAL: u16 = 1000;
+
+// the close code of a peer that sent a message over the size limit
+pub(crate) const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+// what a read fails with when the peer's message is over the size limit,
+// inside the io::Error
+#[derive(Debug)]
+pub(crate) struct MessageTooBig;
+
+impl std::fmt::Display for MessageTooBig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("websocket message over the size limit")
+    }
+}
+
+impl std::error::Error for MessageTooBig {}
+
+impl MessageTooBig {
+    // whether a websocket error is one, axum's errors wrap tungstenite's
+    pub(crate) fn caused(e: &(dyn std::any::Any + 'static)) -> bool {
+        let mut source = match e.downcast_ref::<axum::Error>() {
+            Some(e) => Some(e as &(dyn std::error::Error + 'static)),
+            None => e
+                .downcast_ref::<tungstenite::Error>()
+                .map(|e| e as &(dyn std::error::Error + 'static)),
+        };
+        while let Some(e) = source {
+            if let Some(tungstenite::Error::Capacity(
+                tungstenite::error::CapacityError::MessageTooLong { .. },
+            )) = e.downcast_ref()
+            {
+                return true;
+            }
+            source = e.source();
+        }
+        false
+    }
+
+    // whether a read failed with one
+    pub(crate) fn is(e: &std::io::Error) -> bool {
+        e.get_ref().is_some_and(|e| e.is::<MessageTooBig>())
+    }
+}
+
+/// Upgrade request and response header agreeing on compressed tunnel frames.
+#[cfg(feature = "compression")]
+pub(crate) const COMPRESSION_HEADER: &str = "x-wssocks-compression";
+
+/// Upgrade response header carrying the id the server's log lines and access
+/// log give the tunnel, see [`WebSocketConnection::conn_id`].
+pub const CONN_ID_HEADER: &str = "x-wssocks-conn-id";
+
+/// Upgrade request and response header agreeing on half-close tunnels, see
+/// [`WebSocketConnection::half_close`].
+pub(crate) const HALF_CLOSE_HEADER: &str = "x-wssocks-half-close";
+
+/// Upgrade request and response header agreeing on base64 text frames, see
+/// [`WebSocketConnection::text_frames`].
+pub(crate) const TEXT_FRAMES_HEADER: &str = "x-wssocks-text-frames";
+
+// an inflated frame larger than this is refused, so a small frame can not
+// blow up into a huge allocation
+#[cfg(feature = "compression")]
+const MAX_INFLATED_FRAME_SIZE: usize = 1024 * 1024;
+
+/// A WebSocket message the tunnel bytes can travel in, implemented for axum's
+/// [`Message`] and tungstenite's.
+pub trait TunnelMessage {
+    /// Wraps outbound tunnel bytes in a binary message.
+    fn binary(data: Vec<u8>) -> Self;
+
+    /// A keepalive ping.
+    fn ping() -> Self;
+
+    /// Wraps outbound tunnel bytes, base64 encoded, in a text message. `None`
+    /// by default for message types that carry no text.
+    fn text(_data: String) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// The tunnel bytes of a received message, empty for control messages and
+    /// `None` once the peer closed the tunnel.
+    fn into_payload(self) -> Option<Vec<u8>>;
+
+    /// Whether this is an empty binary or text message, which ends the peer's writes
+    /// on a half-close tunnel. No message is by default.
+    fn is_eof(&self) -> bool {
+        false
+    }
+
+    /// A close message with this code and reason, `None` by default for
+    /// message types that carry none.
+    fn close(_code: u16, _reason: &str) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// The code and reason of a close message, `None` for other messages and
+    /// by default.
+    fn close_code(&self) -> Option<(u16, String)> {
+        None
+    }
+}
+
+impl TunnelMessage for Message {
+    fn binary(data: Vec<u8>) -> Self {
+        Message::Binary(data)
+    }
+
+    fn ping() -> Self {
+        Message::Ping(Vec::new())
+    }
+
+    fn text(data: String) -> Option<Self> {
+        Some(Message::Text(data))
+    }
+
+    fn into_payload(self) -> Option<Vec<u8>> {
+        match self {
+            Message::Binary(data) => Some(data),
+            Message::Text(text) => Some(text.into_bytes()),
+            Message::Ping(_) | Message::Pong(_) => Some(Vec::new()),
+            Message::Close(_) => None,
+        }
+    }
+
+    fn is_eof(&self) -> bool {
+        match self {
+            Message::Binary(data) => data.is_empty(),
+            Message::Text(text) => text.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn close(code: u16, reason: &str) -> Option<Self> {
+        Some(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.to_string().into(),
+        })))
+    }
+
+    fn close_code(&self) -> Option<(u16, String)> {
+        match self {
+            Message::Close(Some(frame)) => Some((frame.code, frame.reason.to_string())),
+            _ => None,
+        }
+    }
+}
+
+impl TunnelMessage for tungstenite::Message {
+    fn binary(data: Vec<u8>) -> Self {
+        tungstenite::Message::Binary(data)
+    }
+
+    fn ping() -> Self {
+        tungstenite::Message::Ping(Vec::new())
+    }
+
+    fn text(data: String) -> Option<Self> {
+        Some(tungstenite::Message::Text(data))
+    }
+
+    fn into_payload(self) -> Option<Vec<u8>> {
+        match self {
+            tungstenite::Message::Binary(data) => Some(data),
+            tungstenite::Message::Text(text) => Some(text.into_bytes()),
+            tungstenite::Message::Ping(_)
+            | tungstenite::Message::Pong(_)
+            | tungstenite::Message::Frame(_) => Some(Vec::new()),
+            tungstenite::Message::Close(_) => None,
+        }
+    }
+
+    fn is_eof(&self) -> bool {
+        match self {
+            tungstenite::Message::Binary(data) => data.is_empty(),
+            tungstenite::Message::Text(text) => text.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn close(code: u16, reason: &str) -> Option<Self> {
+        Some(tungstenite::Message::Close(Some(
+            tungstenite::protocol::CloseFrame {
+                code: code.into(),
+                reason: reason.to_string().into(),
+            },
+        )))
+    }
+
+    fn close_code(&self) -> Option<(u16, String)> {
+        match self {
+            tungstenite::Message::Close(Some(frame)) => {
+                Some((frame.code.into(), frame.reason.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Adapts a WebSocket to `AsyncRead` + `AsyncWrite`, carrying the tunnel
+/// bytes in binary frames. Works over any stream and sink of
+/// [`TunnelMessage`]s, axum's [`WebSocket`] by default.
+#[pin_project]
+pub struct WebSocketConnection<S = WebSocket> {
+    #[pin]
+    inner: S,
+    // bytes of a received frame that did not fit into the caller's ReadBuf
+    remaining: BytesMut,
+    // outbound bytes not handed to the sink yet
+    pending: Vec<u8>,
+    // a frame was handed to the sink and not flushed yet
+    unflushed: bool,
+    max_frame_size: usize,
+    coalesce: bool,
+    ping_interval: Option<Duration>,
+    // armed on the first read, a Sleep needs a runtime to be created
+    ping_timer: Option<Pin<Box<Sleep>>>,
+    // when tunnel data last went either way, only kept with pings enabled
+    last_active: Instant,
+    // a ping was handed to the sink and not flushed yet
+    ping_unflushed: bool,
+    half_close: bool,
+    // the peer ended its writes, or we ended ours, on a half-close tunnel
+    read_eof: bool,
+    write_eof: bool,
+    // our close frame was handed to the sink
+    close_sent: bool,
+    // the code and reason the peer closed with
+    peer_close: Option<(u16, String)>,
+    conn_id: Option<String>,
+    text_frames: bool,
+    #[cfg(feature = "compression")]
+    deflate: Option<Box<Deflate>>,
+}
+
+// one deflate stream per direction, so later frames reuse the dictionary of
+// earlier ones
+#[cfg(feature = "compression")]
+struct Deflate {
+    compress: flate2::Compress,
+    decompress: flate2::Decompress,
+}
+
+impl<S> WebSocketConnection<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            remaining: BytesMut::new(),
+            pending: Vec::new(),
+            unflushed: false,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            coalesce: false,
+            ping_interval: None,
+            ping_timer: None,
+            last_active: Instant::now(),
+            ping_unflushed: false,
+            half_close: false,
+            read_eof: false,
+            write_eof: false,
+            close_sent: false,
+            peer_close: None,
+            conn_id: None,
+            text_frames: false,
+            #[cfg(feature = "compression")]
+            deflate: None,
+        }
+    }
+
+    /// Sets the maximum payload size of an outbound frame, writes larger than
+    /// this are split over several frames. Defaults to [`DEFAULT_MAX_FRAME_SIZE`].
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size.max(1);
+        self
+    }
+
+    /// Collects small writes into one frame until it reaches the maximum frame
+    /// size or the connection is flushed. Disabled by default, so every write
+    /// is sent as its own frame.
+    pub fn coalesce(mut self, enabled: bool) -> Self {
+        self.coalesce = enabled;
+        self
+    }
+
+    /// Sends a ping once no tunnel data went either way for this long, so load
+    /// balancers and proxies on the path do not drop idle tunnels. The pings
+    /// go out while the connection is being read. `None`, the default, sends
+    /// no pings.
+    pub fn ping_interval(mut self, interval: Option<Duration>) -> Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    /// Ends writes with an empty binary frame instead of a close frame, and
+    /// reads an empty frame from the peer as the end of its writes, so each
+    /// direction can end on its own like a TCP half-close. Both ends of the
+    /// tunnel have to agree on it, and one of them then sends the close frame
+    /// with [`close`](Self::close). Disabled by default.
+    pub fn half_close(mut self, enabled: bool) -> Self {
+        self.half_close = enabled;
+        self
+    }
+
+    /// Carries the tunnel bytes base64 encoded in text frames instead of
+    /// binary ones, for networks whose middleboxes mangle or drop binary
+    /// frames. It costs a third more bytes on the wire and both ends of the
+    /// tunnel have to agree on it. Disabled by default.
+    pub fn text_frames(mut self, enabled: bool) -> Self {
+        self.text_frames = enabled;
+        self
+    }
+
+    /// The id the server gave the tunnel in [`CONN_ID_HEADER`], known on
+    /// tunnels opened by a [`WsSocksClient`]. Its log lines and access log
+    /// record carry the same, quote it when reporting a problem.
+    ///
+    /// [`WsSocksClient`]: crate::WsSocksClient
+    pub fn conn_id(&self) -> Option<&str> {
+        self.conn_id.as_deref()
+    }
+
+    pub(crate) fn with_conn_id(mut self, conn_id: Option<String>) -> Self {
+        self.conn_id = conn_id;
+        self
+    }
+
+    /// The code and reason of the peer's close frame, once one was read. A
+    /// server closes with a code telling why, such as
+    /// [`CLOSE_IDLE_TIMEOUT`] or 1008 for a refused target.
+    pub fn peer_close(&self) -> Option<(u16, &str)> {
+        self.peer_close
+            .as_ref()
+            .map(|(code, reason)| (*code, reason.as_str()))
+    }
+
+    /// Deflates outbound frames and inflates inbound ones, both ends of the
+    /// tunnel have to agree on it. Disabled by default.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.deflate = enabled.then(|| {
+            Box::new(Deflate {
+                compress: flate2::Compress::new(flate2::Compression::fast(), false),
+                decompress: flate2::Decompress::new(false),
+            })
+        });
+        self
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Deflate {
+    // compress a frame and sync flush, so the peer can inflate it on its own
+    fn compress(&mut self, input: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(input.len() / 2 + 64);
+        let start = self.compress.total_in();
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity());
+            }
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress
+                .compress_vec(&input[consumed..], &mut output, flate2::FlushCompress::Sync)
+                .map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("deflate frame fails, detail error is {:?}", e),
+                    )
+                })?;
+            // done once all input is in and the flush did not fill the output
+            if (self.compress.total_in() - start) as usize == input.len()
+                && output.len() < output.capacity()
+            {
+                return Ok(output);
+            }
+        }
+    }
+
+    fn decompress(&mut self, input: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(input.len() * 2 + 64);
+        let start = self.decompress.total_in();
+        loop {
+            if output.len() == output.capacity() {
+                if output.len() >= MAX_INFLATED_FRAME_SIZE {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "inflated frame too large",
+                    ));
+                }
+                output.reserve(output.capacity());
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            self.decompress
+                .decompress_vec(
+                    &input[consumed..],
+                    &mut output,
+                    flate2::FlushDecompress::Sync,
+                )
+                .map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("inflate frame fails, detail error is {:?}", e),
+                    )
+                })?;
+            if (self.decompress.total_in() - start) as usize == input.len()
+                && output.len() < output.capacity()
+            {
+                return Ok(output);
+            }
+        }
+    }
+}
+
+// the message carrying outbound tunnel bytes, a base64 text one on a text
+// frames tunnel
+pub(crate) fn data_message<M: TunnelMessage>(data: Vec<u8>, text: bool) -> std::io::Result<M> {
+    if !text {
+        return Ok(M::binary(data));
+    }
+    M::text(STANDARD.encode(data)).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "websocket message type carries no text",
+        )
+    })
+}
+
+// the tunnel bytes of a message received on a text frames tunnel
+pub(crate) fn decode_text(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    STANDARD.decode(data).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("decode text frame fails, detail error is {}", e),
+        )
+    })
+}
+
+impl<S, M, E> WebSocketConnection<S>
+where
+    S: Stream<Item = Result<M, E>> + Sink<M>,
+    <S as Sink<M>>::Error: std::fmt::Debug,
+    M: TunnelMessage,
+{
+    // hand the pending bytes to the sink as one frame
+    fn poll_send_pending(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let mut this = self.project();
+        if this.pending.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+
+        ready!(this.inner.as_mut().poll_ready(cx)).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("websocket stream poll ready fails, detail error is {:?}", e),
+            )
+        })?;
+        let frame = std::mem::take(this.pending);
+        #[cfg(feature = "compression")]
+        let frame = match this.deflate {
+            Some(deflate) => deflate.compress(&frame)?,
+            None => frame,
+        };
+        let message = data_message(frame, *this.text_frames)?;
+        this.inner.as_mut().start_send(message).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("websocket stream start send fails, detail error is {:?}", e),
+            )
+        })?;
+        *this.unflushed = true;
+        Poll::Ready(Ok(()))
+    }
+
+    // send a ping when the tunnel idled for the ping interval, never blocks
+    // the caller
+    fn poll_keepalive(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Result<(), std::io::Error> {
+        let mut this = self.project();
+        let interval = match *this.ping_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+
+        if *this.ping_unflushed {
+            if let Poll::Ready(res) = this.inner.as_mut().poll_flush(cx) {
+                res.map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("websocket stream poll flush fails, detail error is {:?}", e),
+                    )
+                })?;
+                *this.ping_unflushed = false;
+            }
+        }
+
+        let last_active = *this.last_active;
+        let timer = this
+            .ping_timer
+            .get_or_insert_with(|| Box::pin(sleep_until(last_active + interval)));
+        if timer.as_mut().poll(cx).is_pending() {
+            return Ok(());
+        }
+        if last_active.elapsed() < interval {
+            // data went through since the timer was armed
+            timer.as_mut().reset(last_active + interval);
+            let _ = timer.as_mut().poll(cx);
+            return Ok(());
+        }
+
+        // a busy sink wakes us once it has room
+        match this.inner.as_mut().poll_ready(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("websocket stream poll ready fails, detail error is {:?}", e),
+                ))
+            }
+            Poll::Pending => return Ok(()),
+        }
+        this.inner.as_mut().start_send(M::ping()).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("websocket stream start send fails, detail error is {:?}", e),
+            )
+        })?;
+        *this.ping_unflushed = true;
+        *this.last_active = Instant::now();
+        timer.as_mut().reset(*this.last_active + interval);
+        let _ = timer.as_mut().poll(cx);
+        if let Poll::Ready(Ok(())) = this.inner.as_mut().poll_flush(cx) {
+            *this.ping_unflushed = false;
+        }
+        Ok(())
+    }
+
+    // end our writes with an empty frame, the websocket stays open for reading
+    fn poll_send_eof(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        if !self.write_eof {
+            let mut this = self.as_mut().project();
+            ready!(this.inner.as_mut().poll_ready(cx)).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("websocket stream poll ready fails, detail error is {:?}", e),
+                )
+            })?;
+            let message = data_message(Vec::new(), *this.text_frames)?;
+            this.inner.as_mut().start_send(message).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("websocket stream start send fails, detail error is {:?}", e),
+                )
+            })?;
+            *this.write_eof = true;
+            *this.unflushed = true;
+        }
+        self.poll_flush_sent(cx)
+    }
+
+    /// Sends the close frame and waits until it is written, ending the tunnel
+    /// both ways.
+    pub async fn close(&mut self) -> std::io::Result<()>
+    where
+        S: Unpin,
+    {
+        self.close_with(CLOSE_NORMAL, "").await
+    }
+
+    /// Like [`close`](Self::close), with a close code and reason telling the
+    /// peer why. Only the first close frame is sent, later calls and a
+    /// shutdown just wait for it.
+    pub async fn close_with(&mut self, code: u16, reason: &str) -> std::io::Result<()>
+    where
+        S: Unpin,
+    {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_close_with(cx, code, reason)).await
+    }
+
+    fn poll_close_with(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        code: u16,
+        reason: &str,
+    ) -> Poll<Result<(), std::io::Error>> {
+        ready!(self.as_mut().poll_send_pending(cx))?;
+        let mut this = self.project();
+        if !*this.close_sent {
+            // a peer that closed first had its close frame answered already,
+            // and the sink then refuses ours
+            if let Some(msg) = M::close(code, reason) {
+                if ready!(this.inner.as_mut().poll_ready(cx)).is_ok() {
+                    let _ = this.inner.as_mut().start_send(msg);
+                }
+            }
+            *this.close_sent = true;
+        }
+        this.inner.poll_close(cx).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("websocket stream close fails, detail error is {:?}", e),
+            )
+        })
+    }
+
+    // wait until the frames handed to the sink are written out
+    fn poll_flush_sent(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let this = self.project();
+        if !*this.unflushed {
+            return Poll::Ready(Ok(()));
+        }
+
+        ready!(this.inner.poll_flush(cx)).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("websocket stream poll flush fails, detail error is {:?}", e),
+            )
+        })?;
+        *this.unflushed = false;
+        Poll::Ready(Ok(()))
+    }
+
+    // like poll_write, with at most limit bytes read from reader straight
+    // into the frame instead of copied from a buffer, 0 once it is at EOF
+    pub(crate) fn poll_write_from<R: AsyncRead>(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        reader: Pin<&mut R>,
+        limit: usize,
+    ) -> Poll<Result<usize, std::io::Error>> {
+        // nothing is read while a frame waits in the sink, so a slow peer
+        // leaves the bytes in the reader's socket, not in our buffers
+        if self.pending.len() >= self.max_frame_size {
+            ready!(self.as_mut().poll_send_pending(cx))?;
+        }
+        ready!(self.as_mut().poll_flush_sent(cx))?;
+
+        let this = self.as_mut().project();
+        let room = limit.max(1).min(*this.max_frame_size - this.pending.len());
+        if this.pending.is_empty() {
+            let capacity = if *this.coalesce {
+                *this.max_frame_size
+            } else {
+                room
+            };
+            this.pending.reserve_exact(capacity);
+        }
+        let n = ready!(tokio_util::io::poll_read_buf(
+            reader,
+            cx,
+            &mut (&mut *this.pending).limit(room)
+        ))?;
+        if n == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        if this.ping_interval.is_some() {
+            *this.last_active = Instant::now();
+        }
+
+        if !self.coalesce || self.pending.len() >= self.max_frame_size {
+            if let Poll::Ready(Err(e)) = self.as_mut().poll_send_pending(cx) {
+                return Poll::Ready(Err(e));
+            }
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<S, M, E> AsyncRead for WebSocketConnection<S>
+where
+    S: Stream<Item = Result<M, E>> + Sink<M>,
+    <S as Sink<M>>::Error: std::fmt::Debug,
+    M: TunnelMessage,
+    E: std::fmt::Debug + 'static,
+{
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        self.as_mut().poll_keepalive(cx)?;
+        let mut this = self.project();
+
+        // drain leftover bytes first, a new frame must not overtake them
+        if !this.remaining.is_empty() {
+            let n = this.remaining.len().min(buf.remaining());
+            buf.put_slice(&this.remaining.split_to(n));
+            return Poll::Ready(Ok(()));
+        }
+
+        if *this.read_eof {
+            return Poll::Ready(Ok(()));
+        }
+
+        loop {
+            let data = match ready!(this.inner.as_mut().poll_next(cx)) {
+                Some(Ok(msg)) if *this.half_close && msg.is_eof() => {
+                    *this.read_eof = true;
+                    return Poll::Ready(Ok(()));
+                }
+                Some(Ok(msg)) => {
+                    if let Some(close) = msg.close_code() {
+                        *this.peer_close = Some(close);
+                    }
+                    match msg.into_payload() {
+                        Some(data) => data,
+                        // peer closed the tunnel, leave buf untouched to signal EOF
+                        None => return Poll::Ready(Ok(())),
+                    }
+                }
+                Some(Err(e)) if MessageTooBig::caused(&e) => {
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        MessageTooBig,
+                    )))
+                }
+                Some(Err(e)) => {
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionAborted,
+                        format!(
+                            "get data from websocket connection error, detail is {:?}",
+                            e
+                        ),
+                    )))
+                }
+                None => return Poll::Ready(Ok(())),
+            };
+
+            // keepalive and empty frames carry no tunnel data, and an empty
+            // read would signal EOF
+            if data.is_empty() {
+                continue;
+            }
+            let data = match this.text_frames {
+                true => decode_text(&data)?,
+                false => data,
+            };
+            #[cfg(feature = "compression")]
+            let data = match this.deflate {
+                Some(deflate) => {
+                    let data = deflate.decompress(&data)?;
+                    if data.is_empty() {
+                        continue;
+                    }
+                    data
+                }
+                None => data,
+            };
+
+            if this.ping_interval.is_some() {
+                *this.last_active = Instant::now();
+            }
+
+            // a frame may be larger than buf, keep the rest for the next read
+            let n = data.len().min(buf.remaining());
+            buf.put_slice(&data[..n]);
+            this.remaining.extend_from_slice(&data[n..]);
+            return Poll::Ready(Ok(()));
+        }
+    }
+}
+
+impl<S, M, E> AsyncWrite for WebSocketConnection<S>
+where
+    S: Stream<Item = Result<M, E>> + Sink<M>,
+    <S as Sink<M>>::Error: std::fmt::Debug,
+    M: TunnelMessage,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        // a full frame goes out before taking more data, and only one frame
+        // may wait in the sink so a slow peer pushes back on the writer
+        if self.pending.len() >= self.max_frame_size {
+            ready!(self.as_mut().poll_send_pending(cx))?;
+        }
+        ready!(self.as_mut().poll_flush_sent(cx))?;
+
+        let this = self.as_mut().project();
+        let n = buf.len().min(*this.max_frame_size - this.pending.len());
+        if this.pending.is_empty() {
+            // size the frame once up front, the Vec is handed to the sink as is
+            let capacity = if *this.coalesce {
+                *this.max_frame_size
+            } else {
+                n
+            };
+            this.pending.reserve_exact(capacity);
+        }
+        this.pending.extend_from_slice(&buf[..n]);
+        if this.ping_interval.is_some() {
+            *this.last_active = Instant::now();
+        }
+
+        if !self.coalesce || self.pending.len() >= self.max_frame_size {
+            // the bytes are accepted already, a busy sink is retried on the
+            // next write or flush
+            if let Poll::Ready(Err(e)) = self.as_mut().poll_send_pending(cx) {
+                return Poll::Ready(Err(e));
+            }
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        ready!(self.as_mut().poll_send_pending(cx))?;
+        self.poll_flush_sent(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        ready!(self.as_mut().poll_send_pending(cx))?;
+        if self.half_close {
+            return self.poll_send_eof(cx);
+        }
+        self.poll_close_with(cx, CLOSE_NORMAL, "")
+    }
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::socks5::Target;
+
+/// A byte stream to a target, as returned by an [`OutboundConnector`].
+pub trait AsyncReadWrite: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncReadWrite for T {}
+
+/// Opens the connections to targets in place of a direct TCP connect, e.g. to
+/// chain to an upstream proxy or to inject faults in tests. Set one with
+/// [`ProxyConfig::connector`].
+///
+/// The target is checked against the allow and deny lists before the
+/// connector is called, and the whole call is bounded by the connect timeout.
+/// A domain is resolved and its addresses checked as for direct connects,
+/// unless the connector [resolves it remotely](Self::resolves_remotely).
+/// Errors are reported to the client by their
+/// [`ErrorKind`](std::io::ErrorKind), as for direct connects.
+///
+/// [`ProxyConfig::connector`]: crate::ProxyConfig::connector
+#[async_trait]
+pub trait OutboundConnector: Send + Sync {
+    /// Connects to `target`, as the client named it.
+    async fn connect(&self, target: &Target) -> std::io::Result<Box<dyn AsyncReadWrite>>;
+
+    /// Whether domains are resolved by whatever the connector reaches, such
+    /// as an upstream proxy, which may know names the server does not. The
+    /// server then checks a domain by its name alone: rules naming an
+    /// address and the private address block cannot apply to it. `false` by
+    /// default, for connectors that dial the target themselves.
+    fn resolves_remotely(&self) -> bool {
+        false
+    }
+}
+
+// the configured connector, wrapped to keep the config Debug
+#[derive(Clone)]
+pub(crate) struct Connector(pub(crate) Arc<dyn OutboundConnector>);
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Connector")
+    }
+}
@@ -0,0 +1,97 @@
+use axum::extract::ws::WebSocket;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+
+use crate::connection::{data_message, decode_text, MessageTooBig, TunnelMessage};
+
+/// Carries discrete messages over a WebSocket, one binary frame per datagram,
+/// where [`WebSocketConnection`] would merge and split them into a byte
+/// stream. The UDP relay sends its SOCKS5 UDP datagrams this way.
+///
+/// Control frames and empty frames carry no datagram and are skipped. Works
+/// over any stream and sink of [`TunnelMessage`]s, axum's [`WebSocket`] by
+/// default.
+///
+/// [`WebSocketConnection`]: crate::WebSocketConnection
+pub struct WebSocketDatagram<S = WebSocket> {
+    inner: S,
+    text_frames: bool,
+}
+
+impl<S> WebSocketDatagram<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            text_frames: false,
+        }
+    }
+
+    /// Carries the datagrams base64 encoded in text frames, see
+    /// [`WebSocketConnection::text_frames`]. Disabled by default.
+    ///
+    /// [`WebSocketConnection::text_frames`]: crate::WebSocketConnection::text_frames
+    pub fn text_frames(mut self, enabled: bool) -> Self {
+        self.text_frames = enabled;
+        self
+    }
+
+    /// Gives back the wrapped WebSocket.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, M, E> WebSocketDatagram<S>
+where
+    S: Stream<Item = Result<M, E>> + Sink<M> + Unpin,
+    <S as Sink<M>>::Error: std::fmt::Debug,
+    M: TunnelMessage,
+    E: std::fmt::Debug + 'static,
+{
+    /// Waits for the next datagram, `None` once the peer closed the
+    /// WebSocket. Cancel safe, no datagram is lost when the future is
+    /// dropped.
+    pub async fn recv(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            let data = match self.inner.next().await {
+                Some(Ok(msg)) => match msg.into_payload() {
+                    Some(data) => data,
+                    None => return Ok(None),
+                },
+                Some(Err(e)) if MessageTooBig::caused(&e) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        MessageTooBig,
+                    ))
+                }
+                Some(Err(e)) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionAborted,
+                        format!(
+                            "get data from websocket connection error, detail is {:?}",
+                            e
+                        ),
+                    ))
+                }
+                None => return Ok(None),
+            };
+            if data.is_empty() {
+                continue;
+            }
+            return match self.text_frames {
+                true => decode_text(&data).map(Some),
+                false => Ok(Some(data)),
+            };
+        }
+    }
+
+    /// Sends `datagram` in a frame of its own.
+    pub async fn send(&mut self, datagram: Vec<u8>) -> std::io::Result<()> {
+        let message = data_message(datagram, self.text_frames)?;
+        self.inner.send(message).await.map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("websocket stream send fails, detail error is {:?}", e),
+            )
+        })
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use hyper::{
+    header::{ACCEPT, CONTENT_TYPE, HOST},
+    Body, Request, Uri,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use crate::resolver::Resolver;
+
+const DNS_MESSAGE: &str = "application/dns-message";
+
+// record types asked for, and the only class
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// A [`Resolver`] sending its queries to a DNS-over-HTTPS server (RFC 8484),
+/// so lookups are encrypted on the way and answered by the server's view of
+/// DNS instead of the system's.
+///
+/// ```no_run
+/// let doh = wssocks::DohResolver::new("https://cloudflare-dns.com/dns-query").unwrap();
+/// let config = wssocks::ProxyConfig::default().resolver(doh);
+/// ```
+///
+/// Each lookup opens one HTTPS connection and asks it for the A and AAAA
+/// records in turn. The server's certificate is verified against the bundled
+/// web roots, unless [`root_certificate`](Self::root_certificate) is set. The
+/// server must be named by its domain, which is found through the system
+/// resolver: certificates are only verified for domains.
+#[derive(Clone)]
+pub struct DohResolver {
+    // the server's domain, as in the url
+    host: String,
+    port: u16,
+    uri: Uri,
+    root_certificates: Vec<Vec<u8>>,
+}
+
+impl DohResolver {
+    /// Queries the server at `url`, an `https://` url with the path of its
+    /// endpoint, `/dns-query` on most servers. A url naming the server by an
+    /// IP address is refused, as its certificate could not be verified.
+    pub fn new(url: &str) -> std::io::Result<Self> {
+        let invalid =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+        let uri = url
+            .parse::<Uri>()
+            .map_err(|e| invalid(format!("invalid doh url {:?}, detail error is {}", url, e)))?;
+        let (Some("https"), Some(host)) = (uri.scheme_str(), uri.host()) else {
+            return Err(invalid(format!("doh url {:?} is not an https url", url)));
+        };
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        if host.parse::<IpAddr>().is_ok() {
+            return Err(invalid(format!(
+                "doh url {:?} names its server by address, only domains can be verified",
+                url
+            )));
+        }
+        Ok(Self {
+            host,
+            port: uri.port_u16().unwrap_or(443),
+            uri,
+            root_certificates: Vec::new(),
+        })
+    }
+
+    /// Trusts the DER encoded CA certificate instead of the bundled web roots,
+    /// e.g. for a server of your own. Can be called several times to trust
+    /// more than one.
+    pub fn root_certificate(mut self, der: impl Into<Vec<u8>>) -> Self {
+        self.root_certificates.push(der.into());
+        self
+    }
+
+    // the connector verifying the server against the configured roots
+    fn tls(&self) -> std::io::Result<TlsConnector> {
+        let mut roots = rustls::RootCertStore::empty();
+        if self.root_certificates.is_empty() {
+            roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+        }
+        for der in &self.root_certificates {
+            roots.add(&rustls::Certificate(der.clone())).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("invalid root certificate, detail error is {:?}", e),
+                )
+            })?;
+        }
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+
+    // the addresses of one record type and their shortest ttl, sent over
+    // sender
+    async fn query(
+        &self,
+        sender: &mut hyper::client::conn::SendRequest<Body>,
+        host: &str,
+        record_type: u16,
+    ) -> std::io::Result<(Vec<IpAddr>, Option<u32>)> {
+        let request = Request::post(self.uri.clone())
+            .header(HOST, self.uri.authority().map_or("", |a| a.as_str()))
+            .header(CONTENT_TYPE, DNS_MESSAGE)
+            .header(ACCEPT, DNS_MESSAGE)
+            .body(Body::from(encode_query(host, record_type)?))
+            .map_err(other)?;
+        std::future::poll_fn(|cx| sender.poll_ready(cx))
+            .await
+            .map_err(other)?;
+        let response = sender.send_request(request).await.map_err(other)?;
+        if !response.status().is_success() {
+            return Err(std::io::Error::other(format!(
+                "doh server answered {}",
+                response.status()
+            )));
+        }
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(other)?;
+        decode_answer(&body, record_type)
+    }
+}
+
+#[async_trait]
+impl Resolver for DohResolver {
+    async fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        Ok(self.resolve_with_ttl(host, port).await?.0)
+    }
+
+    async fn resolve_with_ttl(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> std::io::Result<(Vec<SocketAddr>, Option<Duration>)> {
+        let tls = self.tls()?;
+        let tcp = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let name = rustls::ServerName::try_from(self.host.as_str()).map_err(other)?;
+        let tls = tls.connect(name, tcp).await?;
+        let (mut sender, connection) = hyper::client::conn::handshake(tls).await.map_err(other)?;
+        // ends once sender is dropped
+        tokio::spawn(connection);
+
+        let (mut addrs, mut ttl) = (Vec::new(), None::<u32>);
+        for record_type in [TYPE_A, TYPE_AAAA] {
+            let (ips, record_ttl) = self.query(&mut sender, host, record_type).await?;
+            addrs.extend(ips.into_iter().map(|ip| SocketAddr::new(ip, port)));
+            ttl = match (ttl, record_ttl) {
+                (Some(ttl), Some(record_ttl)) => Some(ttl.min(record_ttl)),
+                (ttl, record_ttl) => ttl.or(record_ttl),
+            };
+        }
+        Ok((addrs, ttl.map(|ttl| Duration::from_secs(ttl.into()))))
+    }
+}
+
+impl fmt::Debug for DohResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DohResolver")
+            .field("uri", &self.uri)
+            .finish_non_exhaustive()
+    }
+}
+
+fn other(e: impl fmt::Display) -> std::io::Error {
+    std::io::Error::other(format!("doh lookup fails, detail error is {}", e))
+}
+
+// a recursive query for one record type of host, with id 0 as RFC 8484 asks
+// so responses can be cached
+fn encode_query(host: &str, record_type: u16) -> std::io::Result<Vec<u8>> {
+    let invalid = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid domain {:?}", host),
+        )
+    };
+    let mut query = vec![0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid());
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    // the name may take at most 255 bytes
+    if query.len() - 12 > 255 {
+        return Err(invalid());
+    }
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+// the addresses of record_type in a response and the shortest ttl among
+// them, CNAMEs and others in the answer are skipped
+fn decode_answer(message: &[u8], record_type: u16) -> std::io::Result<(Vec<IpAddr>, Option<u32>)> {
+    let malformed =
+        || std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed dns response");
+    let u16_at = |pos: usize| {
+        message
+            .get(pos..pos + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(malformed)
+    };
+    match u16_at(2)? & 0x000f {
+        0 => {}
+        3 => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no such domain",
+            ))
+        }
+        rcode => {
+            return Err(std::io::Error::other(format!(
+                "dns lookup fails with rcode {}",
+                rcode
+            )))
+        }
+    }
+    let (questions, answers) = (u16_at(4)?, u16_at(6)?);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(message, pos).ok_or_else(malformed)? + 4;
+    }
+    let (mut ips, mut ttl) = (Vec::new(), None::<u32>);
+    for _ in 0..answers {
+        pos = skip_name(message, pos).ok_or_else(malformed)?;
+        let (kind, len) = (u16_at(pos)?, u16_at(pos + 8)? as usize);
+        let record_ttl = u32::from(u16_at(pos + 4)?) << 16 | u32::from(u16_at(pos + 6)?);
+        let data = message
+            .get(pos + 10..pos + 10 + len)
+            .ok_or_else(malformed)?;
+        pos += 10 + len;
+        match (kind, data.len()) {
+            (TYPE_A, 4) if kind == record_type => {
+                ips.push(IpAddr::V4(Ipv4Addr::new(
+                    data[0], data[1], data[2], data[3],
+                )));
+                ttl = Some(ttl.map_or(record_ttl, |ttl| ttl.min(record_ttl)));
+            }
+            (TYPE_AAAA, 16) if kind == record_type => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                ips.push(IpAddr::V6(Ipv6Addr::from(octets)));
+                ttl = Some(ttl.map_or(record_ttl, |ttl| ttl.min(record_ttl)));
+            }
+            _ => {}
+        }
+    }
+    Ok((ips, ttl))
+}
+
+// the position after the name at pos, which ends in a zero length label or
+// a pointer to an earlier name
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            len if len & 0xc0 == 0xc0 => return Some(pos + 2),
+            len => pos += 1 + len as usize,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use crate::connection::{MessageTooBig, CLOSE_HANDSHAKE_TIMEOUT, CLOSE_MESSAGE_TOO_BIG};
+use crate::http;
+use crate::server::Protocol;
+use crate::socks4;
+use crate::socks5::{connect_error_reply, socks5_reply, Socks5Error, METHOD_NO_ACCEPTABLE};
+
+// why a tunnel ended before relaying anything
+pub(crate) enum ProxyError {
+    // no tunnel slot freed up in time
+    TooManyTunnels,
+    HandshakeTimeout,
+    // the websocket closed, failed or sent a non binary frame while we waited
+    // for or sent this message
+    WebSocket(&'static str),
+    // a message over the size limit
+    MessageTooBig,
+    BadMethodSelection,
+    NoAcceptableMethod(Vec<u8>),
+    AuthFailed(Protocol),
+    BadRequest(Socks5Error),
+    BadSocks4Request,
+    // the status to answer with
+    BadHttpRequest(u16),
+    UnsupportedCommand(Protocol, u8),
+    // the abuse classifier flagged the target, closed without a reply
+    Abuse(String),
+    // the target is unresolvable or off limits, with the socks5 reply code
+    Rejected(Protocol, u8),
+    ConnectFailed {
+        protocol: Protocol,
+        target: String,
+        error: std::io::Error,
+    },
+    // listening for or accepting the connection of a socks5 BIND failed
+    BindFailed {
+        target: String,
+        error: std::io::Error,
+    },
+}
+
+impl ProxyError {
+    // what the client is told, None where the protocol has no answer for it
+    pub(crate) fn reply(&self) -> Option<Vec<u8>> {
+        let unspecified = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        match self {
+            ProxyError::HandshakeTimeout
+            | ProxyError::WebSocket(_)
+            | ProxyError::MessageTooBig
+            | ProxyError::BadMethodSelection
+            | ProxyError::Abuse(_) => None,
+            // the client waits for a method selection, refusing every method
+            // is the one failure it understands at this point
+            ProxyError::TooManyTunnels | ProxyError::NoAcceptableMethod(_) => {
+                Some(vec![0x05, METHOD_NO_ACCEPTABLE])
+            }
+            ProxyError::AuthFailed(Protocol::Socks5) => Some(b"\x01\x01".to_vec()),
+            ProxyError::AuthFailed(Protocol::Http) => Some(http::response(407)),
+            ProxyError::AuthFailed(Protocol::Socks4) | ProxyError::BadSocks4Request => {
+                Some(socks4::reply(false, unspecified))
+            }
+            ProxyError::BadRequest(e) => e.reply().map(|rep| socks5_reply(rep, unspecified)),
+            ProxyError::BadHttpRequest(status) => Some(http::response(*status)),
+            ProxyError::UnsupportedCommand(protocol, _) => Some(protocol.reply(0x07, unspecified)),
+            ProxyError::Rejected(protocol, rep) => Some(protocol.reply(*rep, unspecified)),
+            ProxyError::ConnectFailed {
+                protocol, error, ..
+            } => Some(protocol.reply(connect_error_reply(error), unspecified)),
+            ProxyError::BindFailed { error, .. } => {
+                Some(socks5_reply(connect_error_reply(error), unspecified))
+            }
+        }
+    }
+
+    // the websocket close code and reason telling the client why, None once
+    // the websocket is gone
+    pub(crate) fn close_code(&self) -> Option<(u16, &'static str)> {
+        match self {
+            ProxyError::WebSocket(_) => None,
+            // try again later
+            ProxyError::TooManyTunnels => Some((1013, "too many tunnels")),
+            ProxyError::HandshakeTimeout => Some((CLOSE_HANDSHAKE_TIMEOUT, "handshake timed out")),
+            ProxyError::MessageTooBig => Some((CLOSE_MESSAGE_TOO_BIG, "message too big")),
+            // protocol error
+            ProxyError::BadMethodSelection
+            | ProxyError::BadRequest(_)
+            | ProxyError::BadSocks4Request
+            | ProxyError::BadHttpRequest(_) => Some((1002, "malformed request")),
+            ProxyError::UnsupportedCommand(..) => Some((1002, "unsupported command")),
+            // policy violation
+            ProxyError::NoAcceptableMethod(_) | ProxyError::AuthFailed(_) => {
+                Some((1008, "authentication failed"))
+            }
+            // 0x02 is the connection not allowed by the ruleset
+            ProxyError::Abuse(_) | ProxyError::Rejected(_, 0x02) => {
+                Some((1008, "target not allowed"))
+            }
+            // internal error, clients do not all know 1014, bad gateway
+            ProxyError::Rejected(..)
+            | ProxyError::ConnectFailed { .. }
+            | ProxyError::BindFailed { .. } => Some((1011, "target unreachable")),
+        }
+    }
+
+    // failures on the target's side, as opposed to a misbehaving client
+    pub(crate) fn is_target_error(&self) -> bool {
+        matches!(
+            self,
+            ProxyError::Rejected(..)
+                | ProxyError::ConnectFailed { .. }
+                | ProxyError::BindFailed { .. }
+        )
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::TooManyTunnels => f.write_str("too many tunnels"),
+            ProxyError::HandshakeTimeout => f.write_str("handshake timed out"),
+            ProxyError::WebSocket(message) => write!(f, "websocket closed at the {}", message),
+            ProxyError::MessageTooBig => MessageTooBig.fmt(f),
+            ProxyError::BadMethodSelection => f.write_str("malformed method selection message"),
+            ProxyError::NoAcceptableMethod(methods) => {
+                write!(f, "no acceptable auth method in {:?}", methods)
+            }
+            ProxyError::AuthFailed(_) => f.write_str("authentication failed"),
+            ProxyError::BadRequest(e) => e.fmt(f),
+            ProxyError::BadSocks4Request => f.write_str("malformed socks4 request"),
+            ProxyError::BadHttpRequest(status) => {
+                write!(f, "malformed http request, answered {}", status)
+            }
+            ProxyError::UnsupportedCommand(_, cmd) => write!(f, "unsupported command {}", cmd),
+            ProxyError::Abuse(target) => write!(f, "target {} flagged as abuse", target),
+            ProxyError::Rejected(_, rep) => write!(f, "target rejected with reply code {}", rep),
+            ProxyError::ConnectFailed { target, error, .. } => {
+                write!(f, "connect to {} fails, detail error is {}", target, error)
+            }
+            ProxyError::BindFailed { target, error } => {
+                write!(f, "bind for {} fails, detail error is {}", target, error)
+            }
+        }
+    }
+}
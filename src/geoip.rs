@@ -0,0 +1,368 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+// the metadata section starts after the last copy of this marker
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+// nesting deeper than this is refused, so a crafted file can not loop
+// through pointers or blow the stack
+const MAX_DEPTH: usize = 32;
+
+/// Admits clients by the country of their IP address, looked up in a MaxMind
+/// database such as GeoLite2 Country. Upgrades from other countries get 403.
+///
+/// ```no_run
+/// let geoip = wssocks::GeoIp::allow("/var/lib/GeoIP/GeoLite2-Country.mmdb", ["DE", "NL"]);
+/// let config = wssocks::ProxyConfig::default().geoip(Some(geoip));
+/// ```
+///
+/// The database is read on the first upgrade, off the runtime's workers, and
+/// kept for the life of the process, restart to pick up a new one. Upgrades
+/// arriving while it loads wait for it. Client addresses are only known
+/// when the router is served with connect info. An allow list refuses
+/// clients whose country is unknown, a deny list admits them, and a database
+/// that fails to load refuses every client.
+#[derive(Clone)]
+pub struct GeoIp {
+    path: PathBuf,
+    // upper case ISO 3166 codes
+    countries: HashSet<String>,
+    allow: bool,
+    reader: Arc<OnceCell<Option<Reader>>>,
+}
+
+impl GeoIp {
+    /// Admits only clients from these countries, by their ISO 3166 codes
+    /// such as `"US"`, in the database at `path`.
+    pub fn allow<I, S>(path: impl Into<PathBuf>, countries: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self::new(path.into(), countries, true)
+    }
+
+    /// Refuses clients from these countries, by their ISO 3166 codes, in the
+    /// database at `path`.
+    pub fn deny<I, S>(path: impl Into<PathBuf>, countries: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self::new(path.into(), countries, false)
+    }
+
+    fn new<I, S>(path: PathBuf, countries: I, allow: bool) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            path,
+            countries: countries
+                .into_iter()
+                .map(|country| country.as_ref().to_ascii_uppercase())
+                .collect(),
+            allow,
+            reader: Arc::default(),
+        }
+    }
+
+    pub(crate) async fn admits(&self, peer: Option<SocketAddr>) -> bool {
+        let Some(reader) = self.reader().await else {
+            return false;
+        };
+        let country = peer.and_then(|peer| reader.country(peer.ip()));
+        match country {
+            Some(country) => self.countries.contains(&country) == self.allow,
+            None => !self.allow,
+        }
+    }
+
+    // the database, loaded on first use on the blocking pool, as it may take
+    // tens of megabytes
+    async fn reader(&self) -> Option<&Reader> {
+        self.reader
+            .get_or_init(|| async {
+                let path = self.path.clone();
+                let loaded = tokio::task::spawn_blocking(move || {
+                    std::fs::read(path)
+                        .map_err(|e| e.to_string())
+                        .and_then(Reader::new)
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+                match loaded {
+                    Ok(reader) => Some(reader),
+                    Err(e) => {
+                        warn!(path = %self.path.display(), error = %e, "geoip database fails to load");
+                        None
+                    }
+                }
+            })
+            .await
+            .as_ref()
+    }
+}
+
+impl fmt::Debug for GeoIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeoIp")
+            .field("path", &self.path)
+            .field("countries", &self.countries)
+            .field("allow", &self.allow)
+            .finish_non_exhaustive()
+    }
+}
+
+// a MaxMind DB file: a binary search tree over the address bits whose leaves
+// point into a section of values, each a map holding country.iso_code in the
+// country databases
+struct Reader {
+    buf: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ipv6: bool,
+    // where the data section starts and ends in buf
+    data: (usize, usize),
+    // the node IPv4 addresses start at, ::/96 in an IPv6 tree
+    ipv4_start: usize,
+}
+
+impl Reader {
+    fn new(buf: Vec<u8>) -> Result<Self, String> {
+        let marker = buf
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .ok_or("no maxmind db metadata")?;
+        let metadata = Decoder::new(&buf[marker + METADATA_MARKER.len()..], 0).value()?;
+        let field = |name| metadata.get(name).and_then(Value::as_uint);
+        let (Some(node_count), Some(record_size), Some(ip_version)) = (
+            field("node_count"),
+            field("record_size"),
+            field("ip_version"),
+        ) else {
+            return Err("incomplete maxmind db metadata".to_string());
+        };
+        if ![24, 28, 32].contains(&record_size) || ![4, 6].contains(&ip_version) {
+            return Err(format!(
+                "unsupported record size {} or ip version {}",
+                record_size, ip_version
+            ));
+        }
+        let (node_count, record_size) = (node_count as usize, record_size as usize);
+        // 16 zero bytes separate the tree from the data section
+        let tree_size = node_count
+            .checked_mul(record_size / 4)
+            .filter(|size| size + 16 <= marker)
+            .ok_or("maxmind db search tree larger than the file")?;
+
+        let mut reader = Self {
+            buf,
+            node_count,
+            record_size,
+            ipv6: ip_version == 6,
+            data: (tree_size + 16, marker),
+            ipv4_start: 0,
+        };
+        if reader.ipv6 {
+            for _ in 0..96 {
+                if reader.ipv4_start >= node_count {
+                    break;
+                }
+                reader.ipv4_start = reader.record(reader.ipv4_start, 0);
+            }
+        }
+        Ok(reader)
+    }
+
+    // the ISO code of the country ip is in, falling back to the one it is
+    // registered in
+    fn country(&self, ip: IpAddr) -> Option<String> {
+        let offset = self.lookup(ip)?;
+        let (start, end) = self.data;
+        let record = Decoder::new(&self.buf[start..end], offset).value().ok()?;
+        ["country", "registered_country"].iter().find_map(|key| {
+            match record.get(key)?.get("iso_code")? {
+                Value::String(code) => Some(code.to_ascii_uppercase()),
+                _ => None,
+            }
+        })
+    }
+
+    // the offset of ip's record in the data section
+    fn lookup(&self, ip: IpAddr) -> Option<usize> {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            v4 => v4,
+        };
+        let (bits, len, mut node) = match ip {
+            IpAddr::V4(v4) => (u128::from(u32::from(v4)), 32, self.ipv4_start),
+            IpAddr::V6(_) if !self.ipv6 => return None,
+            IpAddr::V6(v6) => (u128::from(v6), 128, 0),
+        };
+        for i in (0..len).rev() {
+            if node >= self.node_count {
+                break;
+            }
+            node = self.record(node, ((bits >> i) & 1) as usize);
+        }
+        // node_count itself marks an address without a record
+        node.checked_sub(self.node_count + 16)
+    }
+
+    // the left or right record of a node, 0 and so no record when the file
+    // is cut short
+    fn record(&self, node: usize, right: usize) -> usize {
+        let be = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .fold(0usize, |value, byte| value << 8 | *byte as usize)
+        };
+        let size = self.record_size / 4;
+        let Some(bytes) = self.buf.get(node * size..node * size + size) else {
+            return 0;
+        };
+        match self.record_size {
+            24 | 32 => be(&bytes[right * size / 2..(right + 1) * size / 2]),
+            // the middle byte holds the high nibbles of both records
+            _ if right == 0 => (bytes[3] as usize & 0xf0) << 20 | be(&bytes[..3]),
+            _ => (bytes[3] as usize & 0x0f) << 24 | be(&bytes[4..]),
+        }
+    }
+}
+
+// the values of the data section that matter here, everything else is Other
+enum Value {
+    String(String),
+    Uint(u64),
+    Map(Vec<(String, Value)>),
+    Other,
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn as_uint(&self) -> Option<u64> {
+        match self {
+            Value::Uint(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+// reads values from one section, pointers being offsets into it
+struct Decoder<'a> {
+    section: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(section: &'a [u8], pos: usize) -> Self {
+        Self {
+            section,
+            pos,
+            depth: 0,
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .section
+            .get(self.pos..self.pos.saturating_add(n))
+            .ok_or("maxmind db value runs past its section")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn uint(&mut self, n: usize) -> Result<u64, String> {
+        if n > 8 {
+            return Err("maxmind db integer wider than 64 bits".to_string());
+        }
+        Ok(self
+            .take(n)?
+            .iter()
+            .fold(0, |value, byte| value << 8 | u64::from(*byte)))
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err("maxmind db values nested too deep".to_string());
+        }
+        let ctrl = self.take(1)?[0];
+        let kind = match ctrl >> 5 {
+            1 => {
+                // the offset's size is in two bits, its high bits in the rest
+                let high = u64::from(ctrl & 0x07);
+                let offset = match (ctrl >> 3) & 0x03 {
+                    0 => high << 8 | self.uint(1)?,
+                    1 => (high << 16 | self.uint(2)?) + 2048,
+                    2 => (high << 24 | self.uint(3)?) + 526_336,
+                    _ => self.uint(4)?,
+                };
+                let mut pointed = Decoder {
+                    section: self.section,
+                    pos: offset as usize,
+                    depth: self.depth + 1,
+                };
+                return pointed.value();
+            }
+            // extended types are numbered in the next byte
+            0 => 7 + self.take(1)?[0],
+            kind => kind,
+        };
+        let size = match ctrl & 0x1f {
+            29 => 29 + self.uint(1)?,
+            30 => 285 + self.uint(2)?,
+            31 => 65_821 + self.uint(3)?,
+            size => u64::from(size),
+        } as usize;
+
+        self.depth += 1;
+        let value = match kind {
+            2 => {
+                let bytes = self.take(size)?;
+                let string =
+                    std::str::from_utf8(bytes).map_err(|_| "maxmind db string not utf-8")?;
+                Value::String(string.to_string())
+            }
+            5 | 6 | 9 => Value::Uint(self.uint(size)?),
+            7 => {
+                let mut entries = Vec::new();
+                for _ in 0..size {
+                    let Value::String(key) = self.value()? else {
+                        return Err("maxmind db map key not a string".to_string());
+                    };
+                    entries.push((key, self.value()?));
+                }
+                Value::Map(entries)
+            }
+            11 => {
+                for _ in 0..size {
+                    self.value()?;
+                }
+                Value::Other
+            }
+            // double and float have a fixed size, a boolean is its size
+            3 => self.take(8).map(|_| Value::Other)?,
+            15 => self.take(4).map(|_| Value::Other)?,
+            14 => Value::Other,
+            4 | 8 | 10 => self.take(size).map(|_| Value::Other)?,
+            _ => return Err(format!("unknown maxmind db data type {}", kind)),
+        };
+        self.depth -= 1;
+        Ok(value)
+    }
+}
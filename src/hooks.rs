@@ -0,0 +1,138 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::connection::{CLOSE_IDLE_TIMEOUT, CLOSE_KILLED, CLOSE_MAX_LIFETIME, CLOSE_NORMAL};
+use crate::socks5::Target;
+
+/// A tunnel about to be connected, as seen by [`ProxyConfig::on_connect`].
+///
+/// [`ProxyConfig::on_connect`]: crate::ProxyConfig::on_connect
+#[derive(Clone, Debug)]
+pub struct TunnelRequest {
+    /// The client's address, `None` when the router is served without
+    /// connect info.
+    pub peer: Option<SocketAddr>,
+    /// Who authenticated, if credentials are required.
+    pub user: Option<String>,
+    /// The target the client asked for, or the one the
+    /// [`rewrite_target`](crate::ProxyConfig::rewrite_target) hook returned.
+    pub target: Target,
+    /// The subject of the client's TLS certificate, as in
+    /// `CN=alice,O=Example`, when served by `serve_tls` and the client
+    /// presented one.
+    pub client_subject: Option<String>,
+}
+
+/// Why a relayed tunnel closed, up being from the client to the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Either side closed its end.
+    Closed,
+    /// Nothing was relayed for the idle timeout.
+    Idle,
+    /// The tunnel was open for the maximum lifetime.
+    MaxLifetime,
+    /// Reading from the client or writing to the target failed.
+    UpError,
+    /// Reading from the target or writing to the client failed.
+    DownError,
+    /// An operator ended it through the admin endpoint.
+    Killed,
+}
+
+impl CloseReason {
+    /// The snake case name, as in the access log.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CloseReason::Closed => "closed",
+            CloseReason::Idle => "idle",
+            CloseReason::MaxLifetime => "max_lifetime",
+            CloseReason::UpError => "up_error",
+            CloseReason::DownError => "down_error",
+            CloseReason::Killed => "killed",
+        }
+    }
+
+    // the websocket close code and reason telling the client why
+    pub(crate) fn close_code(&self) -> (u16, &'static str) {
+        match self {
+            CloseReason::Closed => (CLOSE_NORMAL, ""),
+            CloseReason::Idle => (CLOSE_IDLE_TIMEOUT, "idle timeout"),
+            CloseReason::MaxLifetime => (CLOSE_MAX_LIFETIME, "max lifetime"),
+            // internal error
+            CloseReason::UpError | CloseReason::DownError => (1011, "relay failed"),
+            CloseReason::Killed => (CLOSE_KILLED, "killed by an operator"),
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A closed tunnel, as seen by [`ProxyConfig::on_close`].
+///
+/// [`ProxyConfig::on_close`]: crate::ProxyConfig::on_close
+#[derive(Clone, Debug)]
+pub struct TunnelStats {
+    /// The id the tunnel's log lines carry, sent to the client in
+    /// [`CONN_ID_HEADER`].
+    ///
+    /// [`CONN_ID_HEADER`]: crate::CONN_ID_HEADER
+    pub conn_id: u64,
+    /// The client's address, `None` when the router is served without
+    /// connect info.
+    pub peer: Option<SocketAddr>,
+    /// Who authenticated, if credentials are required.
+    pub user: Option<String>,
+    /// The `host:port` the client asked for.
+    pub target: String,
+    /// Bytes relayed from the client to the target.
+    pub bytes_up: u64,
+    /// Bytes relayed from the target to the client.
+    pub bytes_down: u64,
+    /// How long the tunnel was open, handshake included.
+    pub duration: Duration,
+    /// Why it closed.
+    pub reason: CloseReason,
+}
+
+/// What an abuse classifier makes of a target host, see
+/// [`ProxyConfig::abuse_classifier`].
+///
+/// [`ProxyConfig::abuse_classifier`]: crate::ProxyConfig::abuse_classifier
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Carry on with the tunnel.
+    Allow,
+    /// Close the connection and report the client to
+    /// [`ProxyConfig::on_abuse`].
+    ///
+    /// [`ProxyConfig::on_abuse`]: crate::ProxyConfig::on_abuse
+    BlockAndFlag,
+}
+
+pub(crate) type AbuseClassifier = dyn Fn(&str) -> Verdict + Send + Sync;
+pub(crate) type AbuseHook = dyn Fn(&TunnelRequest) + Send + Sync;
+pub(crate) type ConnectHook = dyn Fn(&TunnelRequest) -> bool + Send + Sync;
+pub(crate) type CloseHook = dyn Fn(&TunnelStats) + Send + Sync;
+pub(crate) type RewriteHook = dyn Fn(&Target) -> Option<Target> + Send + Sync;
+
+// a configured callback, wrapped to keep the config Debug
+pub(crate) struct Hook<F: ?Sized>(pub(crate) Arc<F>);
+
+impl<F: ?Sized> Clone for Hook<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Hook<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Hook")
+    }
+}
@@ -0,0 +1,96 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::socks5::Target;
+
+// a request head larger than this is refused
+pub(crate) const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+pub(crate) struct ConnectRequest {
+    pub(crate) target: Target,
+    // username and password from Proxy-Authorization
+    pub(crate) credentials: Option<(String, String)>,
+}
+
+pub(crate) fn is_connect(buf: &[u8]) -> bool {
+    buf.starts_with(b"CONNECT ")
+}
+
+// the length of the request head, blank line included, None until all of it
+// arrived
+pub(crate) fn head_len(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+// parse a complete CONNECT request head, fails with the status to answer
+pub(crate) fn parse_connect(head: &[u8]) -> Result<ConnectRequest, u16> {
+    let head = std::str::from_utf8(head).map_err(|_| 400u16)?;
+    let head = head.strip_suffix("\r\n\r\n").ok_or(400u16)?;
+    let mut lines = head.split("\r\n");
+
+    // CONNECT host:port HTTP/1.1
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some("CONNECT"), Some(target), Some(version), None) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Err(400);
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(400);
+    }
+    let target = target.parse::<Target>().map_err(|_| 400u16)?;
+
+    let mut credentials = None;
+    for line in lines {
+        let (name, value) = line.split_once(':').ok_or(400u16)?;
+        if name.eq_ignore_ascii_case("proxy-authorization") {
+            credentials = parse_basic(value.trim());
+        }
+    }
+    Ok(ConnectRequest {
+        target,
+        credentials,
+    })
+}
+
+// Basic base64(username:password)
+fn parse_basic(value: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+// the response head for a status, 200 opens the tunnel
+pub(crate) fn response(status: u16) -> Vec<u8> {
+    let reason = match status {
+        200 => "Connection Established",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        407 => "Proxy Authentication Required",
+        431 => "Request Header Fields Too Large",
+        _ => "Bad Gateway",
+    };
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason);
+    if status == 407 {
+        head.push_str("Proxy-Authenticate: Basic realm=\"wssocks\"\r\n");
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
+
+// the status matching a socks5 reply code
+pub(crate) fn status(rep: u8) -> u16 {
+    match rep {
+        0x00 => 200,
+        0x02 => 403,
+        _ => 502,
+    }
+}
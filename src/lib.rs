@@ -1,279 +1,210 @@
 use std::net::SocketAddr;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use std::{pin::Pin, task::Poll};
+use std::sync::Arc;
 
 use axum::{
-    extract::ws::{Message, WebSocket, WebSocketUpgrade},
-    response::IntoResponse,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
     routing::get,
-    Router,
+    Extension, Router,
 };
-use byteorder::{BigEndian, ByteOrder};
-use futures::{Sink, Stream};
-use pin_project::pin_project;
 use sync_wrapper::SyncWrapper;
-use tokio::{
-    io::{copy_bidirectional, AsyncRead, AsyncWrite},
-    net::TcpStream,
+
+use crate::proxy_protocol::ProxiedAccept;
+
+#[cfg(feature = "access-log")]
+mod access_log;
+mod acl;
+#[cfg(feature = "admin")]
+mod admin;
+mod client;
+mod config;
+mod config_env;
+#[cfg(feature = "config-file")]
+mod config_file;
+mod connection;
+mod connector;
+mod datagram;
+#[cfg(feature = "doh")]
+mod doh;
+mod error;
+#[cfg(feature = "geoip")]
+mod geoip;
+mod hooks;
+mod http;
+#[cfg(all(feature = "netns", target_os = "linux"))]
+mod netns;
+mod policy;
+mod pool;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+mod proxy_protocol;
+mod rate_limit;
+mod relay;
+mod resolver;
+mod retry;
+mod server;
+mod shutdown;
+mod socks4;
+mod socks5;
+#[cfg(feature = "mtls")]
+mod tls;
+mod upstream;
+
+#[cfg(feature = "access-log")]
+pub use access_log::AccessLog;
+pub use acl::{ParseRuleError, TargetRule};
+pub use client::{run_local_proxy, ClientConnection, WsSocksClient};
+pub use config::{
+    AddressFamily, ConfigError, ProxyConfig, DEFAULT_BLOCKED_PORTS, DEFAULT_BUFFER_SIZE,
+    DEFAULT_CONNECT_TIMEOUT, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_IDLE_TIMEOUT,
+    DEFAULT_MAX_MESSAGE_SIZE,
+};
+pub use connection::{
+    TunnelMessage, WebSocketConnection, CLOSE_HANDSHAKE_TIMEOUT, CLOSE_IDLE_TIMEOUT, CLOSE_KILLED,
+    CLOSE_MAX_LIFETIME, CONN_ID_HEADER, DEFAULT_MAX_FRAME_SIZE, SUBPROTOCOL,
+};
+pub use connector::{AsyncReadWrite, OutboundConnector};
+pub use datagram::WebSocketDatagram;
+#[cfg(feature = "doh")]
+pub use doh::DohResolver;
+#[cfg(feature = "geoip")]
+pub use geoip::GeoIp;
+pub use hooks::{CloseReason, TunnelRequest, TunnelStats, Verdict};
+pub use policy::UserPolicy;
+pub use pool::{PooledConnector, DEFAULT_MAX_IDLE_PER_KEY, DEFAULT_POOL_IDLE_TIMEOUT};
+pub use rate_limit::RateLimit;
+pub use resolver::{
+    CachingResolver, Resolver, SystemResolver, DEFAULT_DNS_CACHE_SIZE, DEFAULT_MAX_TTL,
+    DEFAULT_MIN_TTL,
 };
+pub use retry::{Retry, DEFAULT_INITIAL_BACKOFF, DEFAULT_MAX_BACKOFF};
+pub use shutdown::Shutdown;
+pub use socks5::{parse_socks5_request, ParseTargetError, Socks5Error, Socks5Request, Target};
+#[cfg(feature = "mtls")]
+pub use tls::{serve_tls, ServerTls};
+pub use upstream::UpstreamSocks5Connector;
 
 #[shuttle_service::main]
 async fn axum() -> shuttle_service::ShuttleAxum {
-    let router = Router::new()
-        .route("/", get(root))
-        .route("/ws", get(handler));
-    let sync_wrapper = SyncWrapper::new(router);
+    let config = ProxyConfig::from_env().map_err(std::io::Error::other)?;
+    let sync_wrapper = SyncWrapper::new(router(config));
 
     Ok(sync_wrapper)
 }
 
-async fn root() -> &'static str {
-    "Hello, World!"
-}
-
-async fn handler(ws: WebSocketUpgrade) -> impl IntoResponse {
-    ws.on_upgrade(handle_socket)
-}
-
-async fn handle_socket(mut socket: WebSocket) {
-    // first msg for socks5 request
-    let buf = match socket.recv().await {
-        Some(res) => match res {
-            Ok(msg) => match msg {
-                Message::Binary(data) => data,
-                _ => return,
-            },
-            _ => return,
-        },
-        None => return,
-    };
-
-    // valid socks5 version and data length
-    if 1 + 1 + (buf[1] as usize) != buf.len() || buf[0] != b'\x05' {
-        return;
+/// Builds the proxy: the SOCKS5 over WebSocket endpoint on the configured path
+/// (`/ws` by default) next to a plain text page on `/` and the health and
+/// readiness checks. The router can be served on its own or nested and merged
+/// into another axum app.
+///
+/// `tower` layers added with `.layer()`, such as tracing, CORS or auth, see
+/// the upgrade request of every tunnel and can refuse it, while the tunnel
+/// itself runs outside of them once upgraded. Layers that can fail, like
+/// tower's timeouts and rate limits, need axum's `HandleErrorLayer` in front:
+///
+/// ```no_run
+/// let app = wssocks::router(wssocks::ProxyConfig::default())
+///     .layer(tower_http::trace::TraceLayer::new_for_http());
+/// ```
+pub fn router(config: ProxyConfig) -> Router {
+    let mut router = Router::new().route(&config.ws_path, get(server::handler));
+    if config.root {
+        router = router.route("/", get(root));
     }
-
-    // send first resp with no auth
-    if socket
-        .send(Message::Binary(b"\x05\x00".to_vec()))
-        .await
-        .is_err()
-    {
-        return;
+    if let Some(path) = &config.health_path {
+        router = router.route(path, get(healthz));
     }
-
-    // second msg from socks with target address
-    let buf = match socket.recv().await {
-        Some(res) => match res {
-            Ok(msg) => match msg {
-                Message::Binary(data) => data,
-                _ => return,
-            },
-            _ => return,
-        },
-        None => return,
-    };
-
-    // valid msg
-    if buf.len() < 4 {
-        return;
+    if let Some(path) = &config.ready_path {
+        router = router.route(path, get(readyz));
     }
-
-    // parse socks command
-    let ver = buf[0];
-    let cmd = buf[1];
-    let atyp = buf[3];
-
-    // valid socks version
-    if ver != b'\x05' {
-        return;
+    #[cfg(feature = "prometheus")]
+    if let Some(path) = &config.metrics_path {
+        prometheus::handle();
+        router = router.route(path, get(prometheus::render));
     }
-
-    // only support connect command
-    if cmd != 1 {
-        let _ = socket
-            .send(Message::Binary(
-                b"\x05\x07\x00\x01\x00\x00\x00\x00\x00\x00".to_vec(),
-            ))
-            .await;
-        return;
+    #[cfg(feature = "admin")]
+    if let Some(path) = &config.admin_path {
+        router = router.route(path, get(admin::tunnels)).route(
+            &format!("{}/:conn_id", path),
+            axum::routing::delete(admin::kill),
+        );
     }
-
-    // parse target address
-    let addr;
-    match atyp {
-        1 => {
-            // ipv4
-            if buf.len() != 10 {
-                return;
-            }
-            let dst_addr = IpAddr::V4(Ipv4Addr::new(buf[4], buf[5], buf[6], buf[7]));
-            let dst_port = BigEndian::read_u16(&buf[8..]);
-            addr = SocketAddr::new(dst_addr, dst_port).to_string();
-        }
-        3 => {
-            // domain
-            let offset = 4 + 1 + (buf[4] as usize);
-            if offset + 2 != buf.len() {
-                return;
-            }
-            let dst_port = BigEndian::read_u16(&buf[offset..]);
-            let mut dst_addr = std::str::from_utf8(&buf[5..offset]).unwrap().to_string();
-            dst_addr.push_str(":");
-            dst_addr.push_str(&dst_port.to_string());
-            addr = dst_addr;
-        }
-        4 => {
-            // ipv6
-            if buf.len() != 22 {
-                return;
-            }
-            let dst_addr = IpAddr::V6(Ipv6Addr::new(
-                ((buf[4] as u16) << 8) | buf[5] as u16,
-                ((buf[6] as u16) << 8) | buf[7] as u16,
-                ((buf[8] as u16) << 8) | buf[9] as u16,
-                ((buf[10] as u16) << 8) | buf[11] as u16,
-                ((buf[12] as u16) << 8) | buf[13] as u16,
-                ((buf[14] as u16) << 8) | buf[15] as u16,
-                ((buf[16] as u16) << 8) | buf[17] as u16,
-                ((buf[18] as u16) << 8) | buf[19] as u16,
-            ));
-            let dst_port = BigEndian::read_u16(&buf[20..]);
-            addr = SocketAddr::new(dst_addr, dst_port).to_string();
-        }
-        _ => {
-            let _ = socket
-                .send(Message::Binary(
-                    b"\x05\x08\x00\x01\x00\x00\x00\x00\x00\x00".to_vec(),
-                ))
-                .await;
-            return;
-        }
-    }
-
-    // connect to target
-    let mut outbound = match TcpStream::connect(addr).await {
-        Ok(s) => s,
-        _ => {
-            let _ = socket
-                .send(Message::Binary(
-                    b"\x05\x01\x00\x01\x00\x00\x00\x00\x00\x00".to_vec(),
-                ))
-                .await;
-            return;
-        }
-    };
-
-    // send sencode resp ok
-    if socket
-        .send(Message::Binary(
-            b"\x05\x00\x00\x01\x00\x00\x00\x00\x00\x00".to_vec(),
-        ))
-        .await
-        .is_err()
-    {
-        return;
-    }
-
-    // copy
-    let mut inbound = WebSocketConnection(socket);
-    let _ = copy_bidirectional(&mut inbound, &mut outbound).await;
+    router.layer(Extension(Arc::new(config)))
 }
 
-#[pin_project]
-pub struct WebSocketConnection(#[pin] WebSocket);
+/// Builds a router with nothing but the WebSocket endpoint on the config's
+/// path, for serving further endpoints with their own auth, allow lists and
+/// timeouts next to a [`router`]:
+///
+/// ```no_run
+/// use wssocks::{ProxyConfig, TargetRule};
+///
+/// let public = ProxyConfig::default().auth_token(Some("secret".to_string()));
+/// let internal = ProxyConfig::default()
+///     .ws_path("/internal")
+///     .block_private_addresses(false)
+///     .allow(["10.0.0.0/8".parse::<TargetRule>().unwrap()]);
+/// let app = wssocks::router(public).merge(wssocks::tunnel_router(internal));
+/// ```
+///
+/// Each endpoint keeps its own tunnel limit and shutdown handle, while the
+/// readiness check only looks at the config given to [`router`]. Give the
+/// configs the same [`Shutdown`] to drain them together.
+///
+/// # Panics
+///
+/// Merging panics if two endpoints share a path.
+pub fn tunnel_router(config: ProxyConfig) -> Router {
+    Router::new()
+        .route(&config.ws_path, get(server::handler))
+        .layer(Extension(Arc::new(config)))
+}
 
-impl AsyncRead for WebSocketConnection {
-    fn poll_read(
-        self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-        buf: &mut tokio::io::ReadBuf<'_>,
-    ) -> std::task::Poll<std::io::Result<()>> {
-        self.project()
-            .0
-            .as_mut()
-            .poll_next(cx)
-            .map(|item| match item {
-                Some(item) => match item {
-                    Ok(msg) => match msg {
-                        Message::Binary(data) => {
-                            buf.put_slice(&data);
-                            Ok(())
-                        }
-                        _ => Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            "message type not support",
-                        )),
-                    },
-                    Err(e) => Err(std::io::Error::new(
-                        std::io::ErrorKind::ConnectionAborted,
-                        format!(
-                            "get data from websocket connection error, detail is {:?}",
-                            e
-                        ),
-                    )),
-                },
-                None => Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "websocket stream poll read fails".to_string(),
-                )),
-            })
+/// Serves [`router`] on `addr` until the server fails, with each client's
+/// address known to logs and the [`RateLimit`]. Under shuttle, or when
+/// serving the router some other way, use `into_make_service_with_connect_info`
+/// for the same. Behind a load balancer, see
+/// [`ProxyConfig::accept_proxy_protocol`].
+pub async fn serve(addr: SocketAddr, config: ProxyConfig) -> std::io::Result<()> {
+    let proxied = config.accept_proxy_protocol;
+    let header_timeout = config.handshake_timeout;
+    let app = router(config).into_make_service_with_connect_info::<SocketAddr>();
+    if proxied {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        return axum::Server::builder(ProxiedAccept::new(listener, header_timeout))
+            .serve(app)
+            .await
+            .map_err(std::io::Error::other);
     }
+    axum::Server::try_bind(&addr)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::AddrInUse, e))?
+        .serve(app)
+        .await
+        .map_err(std::io::Error::other)
 }
 
-impl AsyncWrite for WebSocketConnection {
-    fn poll_write(
-        self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-        buf: &[u8],
-    ) -> Poll<Result<usize, std::io::Error>> {
-        let mut this = self.project();
-        this.0.as_mut().poll_ready(cx).map(|item| match item {
-            Ok(_) => match this.0.as_mut().start_send(Message::Binary(buf.to_vec())) {
-                Ok(_) => Ok(buf.len()),
-                Err(e) => Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("websocket stream start send fails, detail error is {:?}", e),
-                )),
-            },
-            Err(e) => Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("websocket stream poll ready fails, detail error is {:?}", e),
-            )),
-        })
+async fn root(Extension(config): Extension<Arc<ProxyConfig>>) -> Response {
+    match &config.root_page {
+        Some((content_type, body)) => {
+            ([(CONTENT_TYPE, content_type.clone())], body.clone()).into_response()
+        }
+        None => "Hello, World!".into_response(),
     }
+}
 
-    fn poll_flush(
-        self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> Poll<Result<(), std::io::Error>> {
-        self.project()
-            .0
-            .as_mut()
-            .poll_flush(cx)
-            .map(|item| match item {
-                Ok(_) => Ok(()),
-                Err(e) => Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("websocket stream poll flush fails, detail error is {:?}", e),
-                )),
-            })
-    }
+async fn healthz() -> &'static str {
+    "ok"
+}
 
-    fn poll_shutdown(
-        self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> Poll<Result<(), std::io::Error>> {
-        self.project()
-            .0
-            .as_mut()
-            .poll_close(cx)
-            .map(|item| match item {
-                Ok(_) => Ok(()),
-                Err(e) => Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("websocket stream close fails, detail error is {:?}", e),
-                )),
-            })
+// ready while new tunnels would be admitted
+async fn readyz(Extension(config): Extension<Arc<ProxyConfig>>) -> (StatusCode, &'static str) {
+    let full = config
+        .tunnel_limit
+        .as_ref()
+        .is_some_and(|limit| limit.available_permits() == 0);
+    if config.shutdown.is_draining() || full || config.under_pressure() {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    } else {
+        (StatusCode::OK, "ready")
     }
 }
@@ -0,0 +1,103 @@
+use std::fmt;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::sync::{mpsc, OnceLock};
+
+use socket2::{Domain, Socket, Type};
+use tokio::sync::oneshot;
+use tracing::warn;
+
+// where `ip netns add` places its namespaces
+const NETNS_DIR: &str = "/run/netns";
+
+type Job = (Domain, Type, oneshot::Sender<std::io::Result<Socket>>);
+
+// a network namespace outbound sockets are opened in, by a thread that
+// entered it and does nothing else, as entering one moves the whole thread
+pub(crate) struct NetNs {
+    path: PathBuf,
+    // the thread's queue, or why it could not enter the namespace
+    worker: OnceLock<Result<mpsc::Sender<Job>, String>>,
+}
+
+impl NetNs {
+    // a name of `ip netns`, or the path of a namespace file
+    pub(crate) fn new(netns: &str) -> Self {
+        let path = if netns.contains('/') {
+            PathBuf::from(netns)
+        } else {
+            PathBuf::from(NETNS_DIR).join(netns)
+        };
+        Self {
+            path,
+            worker: OnceLock::new(),
+        }
+    }
+
+    // a socket created inside the namespace
+    pub(crate) async fn socket(&self, domain: Domain, ty: Type) -> std::io::Result<Socket> {
+        let worker = self.worker.get_or_init(|| self.start()).as_ref();
+        let worker = worker.map_err(|e| std::io::Error::other(e.clone()))?;
+        let (tx, rx) = oneshot::channel();
+        worker
+            .send((domain, ty, tx))
+            .map_err(|_| std::io::Error::other("netns thread is gone"))?;
+        let socket = rx
+            .await
+            .map_err(|_| std::io::Error::other("netns thread is gone"))??;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+
+    // the thread lives until the config holding it is dropped
+    fn start(&self) -> Result<mpsc::Sender<Job>, String> {
+        let path = self.path.clone();
+        let (entered_tx, entered_rx) = mpsc::sync_channel(1);
+        let (tx, rx) = mpsc::channel::<Job>();
+        let spawned = std::thread::Builder::new()
+            .name("wssocks-netns".to_string())
+            .spawn(move || {
+                let entered = File::open(&path).and_then(|file| {
+                    // only this thread moves, setns leaves the others where
+                    // they are
+                    match unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } {
+                        0 => Ok(()),
+                        _ => Err(std::io::Error::last_os_error()),
+                    }
+                });
+                let failed = entered.is_err();
+                let _ = entered_tx.send(entered);
+                if failed {
+                    return;
+                }
+                for (domain, ty, reply) in rx {
+                    let _ = reply.send(Socket::new(domain, ty, None));
+                }
+            });
+        let entered = match spawned {
+            Ok(_) => entered_rx
+                .recv()
+                .unwrap_or_else(|_| Err(std::io::Error::other("netns thread panicked"))),
+            Err(e) => Err(e),
+        };
+        match entered {
+            Ok(()) => Ok(tx),
+            Err(e) => {
+                let e = format!(
+                    "entering netns {} fails, detail error is {}",
+                    self.path.display(),
+                    e
+                );
+                warn!("{}", e);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for NetNs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("NetNs").field(&self.path).finish()
+    }
+}
@@ -0,0 +1,63 @@
+use std::net::IpAddr;
+
+use crate::acl::{Acl, TargetRule};
+
+/// Egress rules for the tunnels of one authenticated user, see
+/// [`ProxyConfig::user_policy`].
+///
+/// The rules narrow the server wide ones: a destination must pass both the
+/// config's allow and deny lists and the policy's.
+///
+/// [`ProxyConfig::user_policy`]: crate::ProxyConfig::user_policy
+#[derive(Clone, Debug, Default)]
+pub struct UserPolicy {
+    pub(crate) acl: Acl,
+    pub(crate) outbound_bind: Option<IpAddr>,
+    pub(crate) outbound_source_port: Option<u16>,
+    pub(crate) bandwidth_limit: Option<u64>,
+}
+
+impl UserPolicy {
+    /// A policy adding no restrictions of its own.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only lets the user reach destinations matching one of these rules. An
+    /// empty list, the default, allows every destination that is not denied.
+    pub fn allow(mut self, rules: impl IntoIterator<Item = TargetRule>) -> Self {
+        self.acl.allow = rules.into_iter().collect();
+        self
+    }
+
+    /// Refuses the user destinations matching any of these rules.
+    pub fn deny(mut self, rules: impl IntoIterator<Item = TargetRule>) -> Self {
+        self.acl.deny = rules.into_iter().collect();
+        self
+    }
+
+    /// Connects the user's tunnels from this local address instead of the
+    /// config's. `None`, the default, keeps the config's.
+    pub fn outbound_bind(mut self, addr: Option<IpAddr>) -> Self {
+        self.outbound_bind = addr;
+        self
+    }
+
+    /// Connects the user's tunnels from this local port instead of the
+    /// config's, see [`ProxyConfig::outbound_source_port`]. `None`, the
+    /// default, keeps the config's.
+    ///
+    /// [`ProxyConfig::outbound_source_port`]: crate::ProxyConfig::outbound_source_port
+    pub fn outbound_source_port(mut self, port: Option<u16>) -> Self {
+        self.outbound_source_port = port;
+        self
+    }
+
+    /// Caps each direction of the user's tunnels at this many bytes per
+    /// second instead of the config's limit. `None`, the default, keeps the
+    /// config's.
+    pub fn bandwidth_limit(mut self, bytes_per_second: Option<u64>) -> Self {
+        self.bandwidth_limit = bytes_per_second;
+        self
+    }
+}
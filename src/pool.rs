@@ -0,0 +1,167 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::time::{sleep, timeout, Instant};
+
+use crate::connector::{AsyncReadWrite, OutboundConnector};
+use crate::socks5::Target;
+
+/// How many spare connections a [`PooledConnector`] keeps per target by
+/// default.
+pub const DEFAULT_MAX_IDLE_PER_KEY: usize = 2;
+
+/// How long a [`PooledConnector`] keeps an unused spare connection by default.
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An [`OutboundConnector`] keeping connections to recently used targets open
+/// ahead of time, so a tunnel to one of them starts without waiting for the
+/// connect. Meant for a small set of targets used over and over, such as
+/// those reached through an [`UpstreamSocks5Connector`].
+///
+/// A relayed byte stream has no boundary a later tunnel could pick up after,
+/// so connections are never handed out twice. Instead, once a target was
+/// connected to, the pool opens spare connections to it through the wrapped
+/// connector and gives each to one later tunnel. Spares unused for the idle
+/// timeout are closed. Targets see these connections before any client asked
+/// for them, so only pool targets that tolerate idle connections, with an
+/// idle timeout below their own.
+///
+/// ```no_run
+/// let upstream = wssocks::UpstreamSocks5Connector::new("10.0.0.1:1080");
+/// let config = wssocks::ProxyConfig::default()
+///     .connector(wssocks::PooledConnector::new(upstream).max_idle_per_key(4));
+/// ```
+///
+/// [`UpstreamSocks5Connector`]: crate::UpstreamSocks5Connector
+pub struct PooledConnector<C> {
+    inner: Arc<C>,
+    pool: Arc<Mutex<HashMap<Target, Spares>>>,
+    max_idle_per_key: usize,
+    idle_timeout: Duration,
+}
+
+// the spare connections to one target, oldest first
+#[derive(Default)]
+struct Spares {
+    idle: VecDeque<(Instant, Box<dyn AsyncReadWrite>)>,
+    // spares still connecting
+    connecting: usize,
+}
+
+impl Spares {
+    fn evict(&mut self, idle_timeout: Duration) {
+        while self
+            .idle
+            .front()
+            .is_some_and(|(since, _)| since.elapsed() >= idle_timeout)
+        {
+            self.idle.pop_front();
+        }
+    }
+}
+
+impl<C: OutboundConnector + 'static> PooledConnector<C> {
+    /// Pools the connections `inner` opens.
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            pool: Arc::default(),
+            max_idle_per_key: DEFAULT_MAX_IDLE_PER_KEY,
+            idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+        }
+    }
+
+    /// Keeps at most this many spare connections per target, zero turns the
+    /// pool off. Defaults to [`DEFAULT_MAX_IDLE_PER_KEY`].
+    pub fn max_idle_per_key(mut self, max: usize) -> Self {
+        self.max_idle_per_key = max;
+        self
+    }
+
+    /// Closes spare connections unused for this long, which also bounds how
+    /// long opening one may take. Defaults to [`DEFAULT_POOL_IDLE_TIMEOUT`].
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    // the newest spare to target, if one is left
+    fn take(&self, target: &Target) -> Option<Box<dyn AsyncReadWrite>> {
+        let mut pool = self.pool.lock().unwrap();
+        let spares = pool.get_mut(target)?;
+        spares.evict(self.idle_timeout);
+        spares.idle.pop_back().map(|(_, stream)| stream)
+    }
+
+    // open spares to target until there are max_idle_per_key of them, each
+    // closed again once it idled for too long
+    fn refill(&self, target: &Target) {
+        if self.max_idle_per_key == 0 {
+            return;
+        }
+        let missing = {
+            let mut pool = self.pool.lock().unwrap();
+            let spares = pool.entry(target.clone()).or_default();
+            let missing = self
+                .max_idle_per_key
+                .saturating_sub(spares.idle.len() + spares.connecting);
+            spares.connecting += missing;
+            missing
+        };
+
+        for _ in 0..missing {
+            let inner = self.inner.clone();
+            let pool = self.pool.clone();
+            let target = target.clone();
+            let idle_timeout = self.idle_timeout;
+            tokio::spawn(async move {
+                let connected = timeout(idle_timeout, inner.connect(&target)).await;
+                {
+                    let mut pool = pool.lock().unwrap();
+                    let spares = pool.entry(target.clone()).or_default();
+                    spares.connecting -= 1;
+                    if let Ok(Ok(stream)) = connected {
+                        spares.idle.push_back((Instant::now(), stream));
+                    }
+                }
+
+                sleep(idle_timeout).await;
+                let mut pool = pool.lock().unwrap();
+                if let Some(spares) = pool.get_mut(&target) {
+                    spares.evict(idle_timeout);
+                    if spares.idle.is_empty() && spares.connecting == 0 {
+                        pool.remove(&target);
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl<C: OutboundConnector + 'static> OutboundConnector for PooledConnector<C> {
+    async fn connect(&self, target: &Target) -> std::io::Result<Box<dyn AsyncReadWrite>> {
+        let spare = self.take(target);
+        self.refill(target);
+        match spare {
+            Some(stream) => Ok(stream),
+            None => self.inner.connect(target).await,
+        }
+    }
+
+    fn resolves_remotely(&self) -> bool {
+        self.inner.resolves_remotely()
+    }
+}
+
+impl<C> fmt::Debug for PooledConnector<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledConnector")
+            .field("max_idle_per_key", &self.max_idle_per_key)
+            .field("idle_timeout", &self.idle_timeout)
+            .finish()
+    }
+}
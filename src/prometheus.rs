@@ -0,0 +1,33 @@
+use std::sync::OnceLock;
+
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tracing::warn;
+
+// buckets for tunnel lifetimes, from short requests to long lived streams
+const DURATION_BUCKETS: &[f64] = &[
+    0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0, 14400.0,
+];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+// install the prometheus recorder the first time a router asks for it
+pub(crate) fn handle() -> &'static PrometheusHandle {
+    HANDLE.get_or_init(|| {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(
+                Matcher::Full("wssocks_connection_duration_seconds".to_string()),
+                DURATION_BUCKETS,
+            )
+            .expect("duration buckets are not empty")
+            .build_recorder();
+        let handle = recorder.handle();
+        if metrics::set_global_recorder(recorder).is_err() {
+            warn!("a metrics recorder is installed already, the metrics endpoint stays empty");
+        }
+        handle
+    })
+}
+
+pub(crate) async fn render() -> String {
+    handle().render()
+}
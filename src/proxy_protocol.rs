@@ -0,0 +1,274 @@
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::extract::connect_info::Connected;
+use futures::{stream::FuturesUnordered, StreamExt};
+use hyper::server::accept::Accept;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tracing::debug;
+
+// the 12 bytes every v2 header starts with
+pub(crate) const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+// a v1 header is a line of at most this many bytes, CRLF included
+const MAX_V1_LEN: usize = 107;
+
+// version 2 in the high nibble, the command in the low one
+const LOCAL: u8 = 0x20;
+const PROXY: u8 = 0x21;
+
+// address family in the high nibble, STREAM in the low one
+const TCP4: u8 = 0x11;
+const TCP6: u8 = 0x21;
+
+// a PROXY protocol v2 header telling the target that src connected to dst,
+// LOCAL when the client's address is unknown, so the target uses the
+// connection's own, a v4 address next to a v6 one is sent v4-mapped
+pub(crate) fn header_v2(src: Option<SocketAddr>, dst: SocketAddr) -> Vec<u8> {
+    let mut buf = SIGNATURE.to_vec();
+    let src = match src {
+        Some(src) => src,
+        None => {
+            buf.extend_from_slice(&[LOCAL, 0x00, 0x00, 0x00]);
+            return buf;
+        }
+    };
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            buf.extend_from_slice(&[PROXY, TCP4]);
+            buf.extend_from_slice(&12u16.to_be_bytes());
+            buf.extend_from_slice(&src_ip.octets());
+            buf.extend_from_slice(&dst_ip.octets());
+        }
+        (src_ip, dst_ip) => {
+            buf.extend_from_slice(&[PROXY, TCP6]);
+            buf.extend_from_slice(&36u16.to_be_bytes());
+            buf.extend_from_slice(&to_v6(src_ip).octets());
+            buf.extend_from_slice(&to_v6(dst_ip).octets());
+        }
+    }
+    buf.extend_from_slice(&src.port().to_be_bytes());
+    buf.extend_from_slice(&dst.port().to_be_bytes());
+    buf
+}
+
+fn to_v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+// read the v1 or v2 header a load balancer starts the connection with and
+// nothing after it, returns the client it names, None when it names none
+// such as for its own health checks
+pub(crate) async fn read_header(stream: &mut TcpStream) -> std::io::Result<Option<SocketAddr>> {
+    let mut start = [0u8; 5];
+    stream.read_exact(&mut start).await?;
+    if &start == b"PROXY" {
+        read_v1(stream).await
+    } else if start == SIGNATURE[..5] {
+        read_v2(stream).await
+    } else {
+        Err(invalid("no proxy protocol header"))
+    }
+}
+
+// PROXY TCP4|TCP6 SRC DST SPORT DPORT, or PROXY UNKNOWN and anything, up to
+// the CRLF, "PROXY" is read already
+async fn read_v1(stream: &mut TcpStream) -> std::io::Result<Option<SocketAddr>> {
+    // byte by byte, a buffered read could take what comes after the line
+    let mut line = b"PROXY".to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= MAX_V1_LEN {
+            return Err(invalid("proxy protocol v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("proxy protocol v1 header not ascii"))?;
+
+    let mut fields = line.split(' ').skip(1);
+    let proto = fields.next();
+    if proto == Some("UNKNOWN") {
+        return Ok(None);
+    }
+    let (Some(src), Some(_dst), Some(sport), Some(_dport), None) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    ) else {
+        return Err(invalid("malformed proxy protocol v1 header"));
+    };
+    let ip = match proto {
+        Some("TCP4") => src.parse::<Ipv4Addr>().ok().map(IpAddr::V4),
+        Some("TCP6") => src.parse::<Ipv6Addr>().ok().map(IpAddr::V6),
+        _ => None,
+    };
+    match (ip, sport.parse::<u16>()) {
+        (Some(ip), Ok(port)) => Ok(Some(SocketAddr::new(ip, port))),
+        _ => Err(invalid("malformed proxy protocol v1 header")),
+    }
+}
+
+// the rest of the 16 byte v2 header, then the addresses and any TLVs, the
+// first 5 bytes of the signature are read already
+async fn read_v2(stream: &mut TcpStream) -> std::io::Result<Option<SocketAddr>> {
+    let mut head = [0u8; 11];
+    stream.read_exact(&mut head).await?;
+    if head[..7] != SIGNATURE[5..] {
+        return Err(invalid("bad proxy protocol v2 signature"));
+    }
+    let (ver_cmd, family) = (head[7], head[8]);
+    let len = u16::from_be_bytes([head[9], head[10]]) as usize;
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).await?;
+
+    match ver_cmd {
+        LOCAL => return Ok(None),
+        PROXY => {}
+        _ => return Err(invalid("unsupported proxy protocol v2 version or command")),
+    }
+    // TCP or UDP over IPv4 or IPv6, SRC DST SPORT DPORT, unix sockets and
+    // unspecified families name no client we could use
+    let src = match family >> 4 {
+        0x1 if len >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([body[8], body[9]]))
+        }
+        0x2 if len >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&body[..16]);
+            let ip = Ipv6Addr::from(octets);
+            SocketAddr::new(IpAddr::V6(ip), u16::from_be_bytes([body[32], body[33]]))
+        }
+        0x1 | 0x2 => return Err(invalid("truncated proxy protocol v2 addresses")),
+        _ => return Ok(None),
+    };
+    Ok(Some(src))
+}
+
+fn invalid(message: &'static str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+// a connection whose PROXY header was read, peer is the client it named or
+// the load balancer's own address
+pub(crate) struct ProxiedStream {
+    inner: TcpStream,
+    peer: SocketAddr,
+}
+
+impl Connected<&ProxiedStream> for SocketAddr {
+    fn connect_info(target: &ProxiedStream) -> Self {
+        target.peer
+    }
+}
+
+impl AsyncRead for ProxiedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxiedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+type PendingHeader = Pin<Box<dyn Future<Output = Option<ProxiedStream>> + Send>>;
+
+// accepts connections and hands them to hyper once their header was read, a
+// connection without a valid one within header_timeout is dropped
+pub(crate) struct ProxiedAccept {
+    listener: TcpListener,
+    header_timeout: Duration,
+    pending: FuturesUnordered<PendingHeader>,
+}
+
+impl ProxiedAccept {
+    pub(crate) fn new(listener: TcpListener, header_timeout: Duration) -> Self {
+        Self {
+            listener,
+            header_timeout,
+            pending: FuturesUnordered::new(),
+        }
+    }
+}
+
+impl Accept for ProxiedAccept {
+    type Conn = ProxiedStream;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        loop {
+            match self.listener.poll_accept(cx) {
+                Poll::Ready(Ok((mut stream, from))) => {
+                    let header_timeout = self.header_timeout;
+                    self.pending.push(Box::pin(async move {
+                        match timeout(header_timeout, read_header(&mut stream)).await {
+                            Ok(Ok(peer)) => Some(ProxiedStream {
+                                inner: stream,
+                                peer: peer.unwrap_or(from),
+                            }),
+                            Ok(Err(e)) => {
+                                debug!(%from, error = %e, "proxy protocol header refused");
+                                None
+                            }
+                            Err(_) => {
+                                debug!(%from, "proxy protocol header timed out");
+                                None
+                            }
+                        }
+                    }));
+                }
+                // a client giving up before we accepted it is no reason to stop
+                Poll::Ready(Err(e))
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::ConnectionReset
+                    ) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => break,
+            }
+        }
+
+        loop {
+            match self.pending.poll_next_unpin(cx) {
+                Poll::Ready(Some(Some(stream))) => return Poll::Ready(Some(Ok(stream))),
+                Poll::Ready(Some(None)) => {}
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
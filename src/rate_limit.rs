@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::http::HeaderMap;
+
+// buckets kept before full ones are dropped again
+const PRUNE_THRESHOLD: usize = 1024;
+
+type KeyFn = dyn Fn(Option<SocketAddr>, &HeaderMap) -> Option<String> + Send + Sync;
+
+/// Limits how often a client may open a tunnel, with one token bucket per
+/// client. Upgrades over the limit get 429.
+///
+/// Clients are told apart by their IP address, which is only known when the
+/// router is served with connect info. [`key_by`](Self::key_by) swaps in
+/// another key, e.g. the auth token or a header set by a reverse proxy.
+#[derive(Clone)]
+pub struct RateLimit {
+    // tokens per second and bucket size
+    rate: f64,
+    burst: f64,
+    key: Arc<KeyFn>,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    /// Allows `per_minute` new tunnels a minute per client, with bursts of up
+    /// to `burst` tunnels at once.
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            rate: f64::from(per_minute) / 60.0,
+            burst: f64::from(burst.max(1)),
+            key: Arc::new(|peer: Option<SocketAddr>, _: &HeaderMap| {
+                peer.map(|peer| peer.ip().to_string())
+            }),
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Keys the buckets by what `key` returns for the peer address, if known,
+    /// and the upgrade request headers. Requests keyed `None` are not limited.
+    pub fn key_by<F>(mut self, key: F) -> Self
+    where
+        F: Fn(Option<SocketAddr>, &HeaderMap) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    // take a token from the client's bucket, or tell how long until one is free
+    pub(crate) fn check(
+        &self,
+        peer: Option<SocketAddr>,
+        headers: &HeaderMap,
+    ) -> Result<(), Duration> {
+        let key = match (self.key)(peer, headers) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_THRESHOLD {
+            // a bucket that refilled completely is the same as no bucket
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if self.rate > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+impl fmt::Debug for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("per_minute", &(self.rate * 60.0))
+            .field("burst", &self.burst)
+            .finish_non_exhaustive()
+    }
+}
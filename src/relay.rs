@@ -0,0 +1,384 @@
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::ready;
+use futures::{Sink, Stream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep, sleep_until, Instant, Sleep},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::connection::{TunnelMessage, WebSocketConnection};
+
+// how much of its rate a throttled direction may send at once
+const BURST: Duration = Duration::from_millis(100);
+
+// how a relay ended, up is from the client to the target and down the way back
+pub(crate) struct Relayed {
+    pub(crate) up: Transfer,
+    pub(crate) down: Transfer,
+    // neither side sent anything for the idle timeout
+    pub(crate) idle: bool,
+    // the deadline passed first
+    pub(crate) expired: bool,
+    // the cancel token was cancelled first
+    pub(crate) killed: bool,
+}
+
+pub(crate) struct Transfer {
+    pub(crate) bytes: u64,
+    // why the direction stopped early, None after a clean EOF or while it was
+    // still open when the other direction ended the relay
+    pub(crate) error: Option<std::io::Error>,
+}
+
+// a writer taking its bytes straight from a reader, so the relay does not
+// copy them through its own buffer first
+pub(crate) trait WriteFrom: AsyncWrite {
+    // at most limit bytes read from reader and written, 0 once it is at EOF
+    fn poll_write_from<R: AsyncRead>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        reader: Pin<&mut R>,
+        limit: usize,
+    ) -> Poll<std::io::Result<usize>>;
+}
+
+// the bytes from the target are read into the frame that carries them
+impl<S, M, E> WriteFrom for WebSocketConnection<S>
+where
+    S: Stream<Item = Result<M, E>> + Sink<M>,
+    <S as Sink<M>>::Error: std::fmt::Debug,
+    M: TunnelMessage,
+{
+    fn poll_write_from<R: AsyncRead>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        reader: Pin<&mut R>,
+        limit: usize,
+    ) -> Poll<std::io::Result<usize>> {
+        WebSocketConnection::poll_write_from(self, cx, reader, limit)
+    }
+}
+
+impl<T: WriteFrom + Unpin + ?Sized> WriteFrom for &mut T {
+    fn poll_write_from<R: AsyncRead>(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        reader: Pin<&mut R>,
+        limit: usize,
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut **self.get_mut()).poll_write_from(cx, reader, limit)
+    }
+}
+
+// copy both directions until both reach EOF, either fails, the tunnel idles,
+// the deadline passes whatever is relayed or cancel is cancelled, each side
+// is shut down for writing once the other reached EOF and both close when
+// they are dropped at the end, rate caps each direction in bytes per second
+// and buffer_size is how much each reads at once, b's bytes are read
+// straight into a's writes
+pub(crate) async fn relay<A, B>(
+    mut a: A,
+    mut b: B,
+    idle_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    rate: Option<u64>,
+    buffer_size: usize,
+    cancel: Option<&CancellationToken>,
+) -> Relayed
+where
+    A: AsyncRead + WriteFrom + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let throttle = || rate.map(|rate| Throttle::new(rate, buffer_size));
+    let mut up = Copy::new(buffer_size, throttle(), false);
+    let mut down = Copy::new(buffer_size, throttle(), true);
+    let mut idle_timer = idle_timeout.map(|timeout| (timeout, Box::pin(sleep(timeout))));
+    let mut last_active = Instant::now();
+    let mut last_total = 0;
+    let mut idle = false;
+    let mut deadline = deadline.map(|deadline| Box::pin(sleep_until(deadline)));
+    let mut expired = false;
+    let mut cancelled = cancel.map(|cancel| Box::pin(cancel.cancelled()));
+    let mut killed = false;
+
+    poll_fn(|cx| {
+        up.poll(cx, Pin::new(&mut a), Pin::new(&mut b));
+        down.poll_direct(cx, Pin::new(&mut b), Pin::new(&mut a));
+        if (up.done && down.done) || up.error.is_some() || down.error.is_some() {
+            return Poll::Ready(());
+        }
+
+        if let Some(cancelled) = &mut cancelled {
+            if cancelled.as_mut().poll(cx).is_ready() {
+                killed = true;
+                return Poll::Ready(());
+            }
+        }
+        if let Some(deadline) = &mut deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                expired = true;
+                return Poll::Ready(());
+            }
+        }
+        if let Some((timeout, timer)) = &mut idle_timer {
+            let total = up.bytes + down.bytes;
+            if total != last_total {
+                last_total = total;
+                last_active = Instant::now();
+            }
+            while timer.as_mut().poll(cx).is_ready() {
+                if last_active.elapsed() >= *timeout {
+                    idle = true;
+                    return Poll::Ready(());
+                }
+                timer.as_mut().reset(last_active + *timeout);
+            }
+        }
+        Poll::Pending
+    })
+    .await;
+
+    Relayed {
+        up: up.into_transfer(),
+        down: down.into_transfer(),
+        idle,
+        expired,
+        killed,
+    }
+}
+
+// one direction of the relay
+struct Copy {
+    // empty when the writer reads for itself
+    buf: Box<[u8]>,
+    read_size: usize,
+    pos: usize,
+    cap: usize,
+    bytes: u64,
+    read_done: bool,
+    // written bytes may sit in the writer until it is flushed
+    need_flush: bool,
+    done: bool,
+    error: Option<std::io::Error>,
+    throttle: Option<Throttle>,
+}
+
+impl Copy {
+    fn new(buffer_size: usize, throttle: Option<Throttle>, direct: bool) -> Self {
+        let buf = if direct { 0 } else { buffer_size };
+        Self {
+            buf: vec![0; buf].into_boxed_slice(),
+            read_size: buffer_size,
+            pos: 0,
+            cap: 0,
+            bytes: 0,
+            read_done: false,
+            need_flush: false,
+            done: false,
+            error: None,
+            throttle,
+        }
+    }
+
+    fn poll<R, W>(&mut self, cx: &mut Context<'_>, reader: Pin<&mut R>, writer: Pin<&mut W>)
+    where
+        R: AsyncRead,
+        W: AsyncWrite,
+    {
+        if self.done || self.error.is_some() {
+            return;
+        }
+        match self.poll_copy(cx, reader, writer) {
+            Poll::Ready(Ok(())) => self.done = true,
+            Poll::Ready(Err(e)) => self.error = Some(e),
+            Poll::Pending => {}
+        }
+    }
+
+    fn poll_direct<R, W>(&mut self, cx: &mut Context<'_>, reader: Pin<&mut R>, writer: Pin<&mut W>)
+    where
+        R: AsyncRead,
+        W: WriteFrom,
+    {
+        if self.done || self.error.is_some() {
+            return;
+        }
+        match self.poll_copy_direct(cx, reader, writer) {
+            Poll::Ready(Ok(())) => self.done = true,
+            Poll::Ready(Err(e)) => self.error = Some(e),
+            Poll::Pending => {}
+        }
+    }
+
+    // poll_copy without the buffer, the writer reads its bytes itself
+    fn poll_copy_direct<R, W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<std::io::Result<()>>
+    where
+        R: AsyncRead,
+        W: WriteFrom,
+    {
+        while !self.read_done {
+            let throttled = match &mut self.throttle {
+                Some(throttle) => throttle.poll_ready(cx).is_pending(),
+                None => false,
+            };
+            let written = if throttled {
+                Poll::Pending
+            } else {
+                writer
+                    .as_mut()
+                    .poll_write_from(cx, reader.as_mut(), self.read_size)
+            };
+            match written {
+                Poll::Ready(Ok(0)) => self.read_done = true,
+                Poll::Ready(Ok(n)) => {
+                    self.bytes += n as u64;
+                    self.need_flush = true;
+                    if let Some(throttle) = &mut self.throttle {
+                        throttle.consume(n);
+                    }
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => {
+                    if self.need_flush {
+                        ready!(writer.as_mut().poll_flush(cx))?;
+                        self.need_flush = false;
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+        ready!(writer.as_mut().poll_shutdown(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_copy<R, W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<std::io::Result<()>>
+    where
+        R: AsyncRead,
+        W: AsyncWrite,
+    {
+        loop {
+            if self.pos == self.cap && !self.read_done {
+                let mut buf = ReadBuf::new(&mut self.buf);
+                let throttled = match &mut self.throttle {
+                    Some(throttle) => throttle.poll_ready(cx).is_pending(),
+                    None => false,
+                };
+                let read = if throttled {
+                    Poll::Pending
+                } else {
+                    reader.as_mut().poll_read(cx, &mut buf)
+                };
+                match read {
+                    Poll::Ready(Ok(())) => {
+                        let n = buf.filled().len();
+                        if n == 0 {
+                            self.read_done = true;
+                        } else {
+                            self.pos = 0;
+                            self.cap = n;
+                            if let Some(throttle) = &mut self.throttle {
+                                throttle.consume(n);
+                            }
+                        }
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => {
+                        // nothing more to send for now, push out what was written
+                        if self.need_flush {
+                            ready!(writer.as_mut().poll_flush(cx))?;
+                            self.need_flush = false;
+                        }
+                        return Poll::Pending;
+                    }
+                }
+            }
+
+            while self.pos < self.cap {
+                let n = ready!(writer
+                    .as_mut()
+                    .poll_write(cx, &self.buf[self.pos..self.cap]))?;
+                if n == 0 {
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::WriteZero,
+                        "write zero byte into writer",
+                    )));
+                }
+                self.pos += n;
+                self.bytes += n as u64;
+                self.need_flush = true;
+            }
+
+            if self.read_done {
+                // shutdown flushes before it closes
+                ready!(writer.as_mut().poll_shutdown(cx))?;
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    fn into_transfer(self) -> Transfer {
+        Transfer {
+            bytes: self.bytes,
+            error: self.error,
+        }
+    }
+}
+
+// a token bucket of bytes, a read may take more than is left and the
+// direction then sleeps until the debt is paid off
+struct Throttle {
+    // bytes per second and bucket size
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+    delay: Pin<Box<Sleep>>,
+}
+
+impl Throttle {
+    // the bucket holds at least one full read
+    fn new(rate: u64, buffer_size: usize) -> Self {
+        let rate = rate.max(1) as f64;
+        let burst = (rate * BURST.as_secs_f64()).max(buffer_size as f64);
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            updated: Instant::now(),
+            delay: Box::pin(sleep(Duration::ZERO)),
+        }
+    }
+
+    // ready once there are tokens left to read with
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+        if self.tokens > 0.0 {
+            return Poll::Ready(());
+        }
+        let wait = Duration::from_secs_f64(-self.tokens / self.rate);
+        self.delay.as_mut().reset(now + wait);
+        self.delay.as_mut().poll(cx)
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.tokens -= n as f64;
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::net::lookup_host;
+
+/// Resolves the domains clients ask for in place of the system resolver, e.g.
+/// to use a private or split-horizon DNS. Set one with
+/// [`ProxyConfig::resolver`].
+///
+/// The addresses returned are checked against the allow and deny lists like
+/// those of the system resolver, and the whole call is bounded by the connect
+/// timeout. An error, or no address at all, is answered with host
+/// unreachable.
+///
+/// [`ProxyConfig::resolver`]: crate::ProxyConfig::resolver
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    /// The addresses of `host`, each with `port`.
+    async fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>>;
+
+    /// Like [`resolve`](Self::resolve), also telling how long the addresses
+    /// may be cached by a [`CachingResolver`]. `None`, the default, leaves
+    /// that to the cache.
+    async fn resolve_with_ttl(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> std::io::Result<(Vec<SocketAddr>, Option<Duration>)> {
+        Ok((self.resolve(host, port).await?, None))
+    }
+}
+
+/// The system resolver, through `getaddrinfo`, used by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        Ok(lookup_host((host, port)).await?.collect())
+    }
+}
+
+/// How many hosts a [`CachingResolver`] keeps by default.
+pub const DEFAULT_DNS_CACHE_SIZE: usize = 1024;
+
+/// How long a [`CachingResolver`] keeps addresses at least by default.
+pub const DEFAULT_MIN_TTL: Duration = Duration::from_secs(30);
+
+/// How long a [`CachingResolver`] keeps addresses at most by default.
+pub const DEFAULT_MAX_TTL: Duration = Duration::from_secs(300);
+
+/// A [`Resolver`] remembering the addresses `inner` resolved, so tunnels to a
+/// host opened in a burst share one lookup.
+///
+/// Addresses are kept for the TTL `inner` reports, at least the minimum TTL,
+/// which is all they get from resolvers that report none such as the
+/// [`SystemResolver`], and at most the maximum TTL. Failed lookups, and those
+/// answered with no address, are not kept. Cached addresses still
+/// go through the allow and deny lists on every connect, so a cache can not
+/// let a rebound address through.
+///
+/// ```no_run
+/// let resolver = wssocks::CachingResolver::new(wssocks::SystemResolver).max_entries(10_000);
+/// let config = wssocks::ProxyConfig::default().resolver(resolver);
+/// ```
+pub struct CachingResolver<R> {
+    inner: R,
+    // addresses and when they expire, by host
+    cache: Mutex<HashMap<String, (Instant, Vec<IpAddr>)>>,
+    max_entries: usize,
+    min_ttl: Duration,
+    max_ttl: Duration,
+}
+
+impl<R: Resolver> CachingResolver<R> {
+    /// Caches the addresses `inner` resolves.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            cache: Mutex::default(),
+            max_entries: DEFAULT_DNS_CACHE_SIZE,
+            min_ttl: DEFAULT_MIN_TTL,
+            max_ttl: DEFAULT_MAX_TTL,
+        }
+    }
+
+    /// Keeps the addresses of at most this many hosts, dropping those that
+    /// expire first to make room. Defaults to [`DEFAULT_DNS_CACHE_SIZE`].
+    pub fn max_entries(mut self, max: usize) -> Self {
+        self.max_entries = max;
+        self
+    }
+
+    /// Keeps addresses for at least this long, however short their TTL.
+    /// Defaults to [`DEFAULT_MIN_TTL`].
+    pub fn min_ttl(mut self, ttl: Duration) -> Self {
+        self.min_ttl = ttl;
+        self
+    }
+
+    /// Keeps addresses for at most this long, however long their TTL, so a
+    /// bogus one can not pin an address for days. The minimum TTL wins over
+    /// a shorter maximum. Defaults to [`DEFAULT_MAX_TTL`].
+    pub fn max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = ttl;
+        self
+    }
+
+    fn cached(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(host) {
+            Some((expires, ips)) if *expires > Instant::now() => Some(ips.clone()),
+            Some(_) => {
+                cache.remove(host);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, host: &str, ips: Vec<IpAddr>, ttl: Duration) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= self.max_entries && !cache.contains_key(host) {
+            cache.retain(|_, (expires, _)| *expires > now);
+        }
+        if cache.len() >= self.max_entries && !cache.contains_key(host) {
+            let first = cache
+                .iter()
+                .min_by_key(|(_, (expires, _))| *expires)
+                .map(|(host, _)| host.clone());
+            if let Some(first) = first {
+                cache.remove(&first);
+            }
+        }
+        cache.insert(host.to_string(), (now + ttl, ips));
+    }
+}
+
+#[async_trait]
+impl<R: Resolver> Resolver for CachingResolver<R> {
+    async fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        // names differ in case only, as far as DNS goes
+        let key = host.to_ascii_lowercase();
+        let ips = match self.cached(&key) {
+            Some(ips) => ips,
+            None => {
+                let (addrs, ttl) = self.inner.resolve_with_ttl(host, port).await?;
+                let ips = addrs.iter().map(|addr| addr.ip()).collect::<Vec<_>>();
+                let ttl = ttl.map_or(self.min_ttl, |ttl| ttl.min(self.max_ttl).max(self.min_ttl));
+                // an empty answer is as good as a failed one
+                if !ips.is_empty() {
+                    self.insert(&key, ips.clone(), ttl);
+                }
+                ips
+            }
+        };
+        Ok(ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+}
+
+impl<R> fmt::Debug for CachingResolver<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachingResolver")
+            .field("max_entries", &self.max_entries)
+            .field("min_ttl", &self.min_ttl)
+            .field("max_ttl", &self.max_ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+// the configured resolver, wrapped to keep the config Debug
+#[derive(Clone)]
+pub(crate) struct ConfiguredResolver(pub(crate) Arc<dyn Resolver>);
+
+impl Default for ConfiguredResolver {
+    fn default() -> Self {
+        Self(Arc::new(SystemResolver))
+    }
+}
+
+impl fmt::Debug for ConfiguredResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Resolver")
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use tokio_tungstenite::tungstenite::Error;
+
+/// Default wait before the first retry, 100 ms.
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Default upper bound of the wait between two attempts, 5 seconds.
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// How a [`WsSocksClient`] retries a websocket connect that failed on the way
+/// to the server, such as one refused while the server restarts.
+///
+/// The wait doubles from one attempt to the next up to a maximum, and each is
+/// picked at random from its upper half, so clients that failed together do
+/// not come back together. Only transient failures are retried: I/O errors
+/// and `429` or `5xx` answers to the upgrade, not a refused login or an
+/// invalid url.
+///
+/// [`WsSocksClient`]: crate::WsSocksClient
+#[derive(Clone, Debug)]
+pub struct Retry {
+    attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    deadline: Option<Duration>,
+}
+
+impl Retry {
+    /// Connects up to `attempts` times in all, the first try included.
+    pub fn new(attempts: u32) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            deadline: None,
+        }
+    }
+
+    /// Sets the wait before the first retry. Defaults to
+    /// [`DEFAULT_INITIAL_BACKOFF`].
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the upper bound of the wait between two attempts. Defaults to
+    /// [`DEFAULT_MAX_BACKOFF`].
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Gives up once another wait would end this long after the first
+    /// attempt, however many attempts are left. `None`, the default, only
+    /// counts attempts.
+    pub fn deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    // the wait before the attempt after `failed` ones, None to give up
+    pub(crate) fn backoff(&self, failed: u32, elapsed: Duration) -> Option<Duration> {
+        if failed >= self.attempts {
+            return None;
+        }
+        let ceiling = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(failed - 1))
+            .min(self.max_backoff);
+        let backoff = ceiling / 2 + jitter(ceiling / 2);
+        match self.deadline {
+            Some(deadline) if elapsed + backoff > deadline => None,
+            _ => Some(backoff),
+        }
+    }
+}
+
+// whether a failed connect may succeed when tried again
+pub(crate) fn transient(e: &Error) -> bool {
+    match e {
+        Error::Io(_) => true,
+        Error::Http(response) => {
+            let status = response.status();
+            status.is_server_error() || status.as_u16() == 429
+        }
+        _ => false,
+    }
+}
+
+// a random duration up to max, seeded by the hasher keys std draws per
+// RandomState
+fn jitter(max: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    max.mul_f64((random >> 11) as f64 / (1u64 << 53) as f64)
+}
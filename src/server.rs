@@ -0,0 +1,1608 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo,
+    },
+    http::{
+        header::{AUTHORIZATION, ORIGIN, RETRY_AFTER, SEC_WEBSOCKET_PROTOCOL, WWW_AUTHENTICATE},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Extension,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpSocket, TcpStream, UdpSocket},
+    time::{sleep, timeout, Instant},
+};
+use tracing::{debug, info, instrument, warn};
+
+#[cfg(feature = "access-log")]
+use crate::access_log::Record;
+use crate::acl::is_private;
+#[cfg(feature = "compression")]
+use crate::connection::COMPRESSION_HEADER;
+use crate::connection::{
+    MessageTooBig, CLOSE_IDLE_TIMEOUT, CLOSE_MESSAGE_TOO_BIG, CLOSE_NORMAL, CONN_ID_HEADER,
+    HALF_CLOSE_HEADER, SUBPROTOCOL, TEXT_FRAMES_HEADER,
+};
+use crate::connector::AsyncReadWrite;
+use crate::datagram::WebSocketDatagram;
+use crate::error::ProxyError;
+use crate::hooks::{CloseReason, TunnelRequest, TunnelStats, Verdict};
+use crate::http;
+use crate::policy::UserPolicy;
+use crate::proxy_protocol;
+use crate::relay::relay;
+use crate::socks4;
+use crate::socks5::{
+    encode_address, methods_len, parse_methods, parse_socks5_request, parse_target, parse_userpass,
+    request_len, select_method, socks5_reply, userpass_len, Target, CMD_BIND, CMD_CONNECT,
+    CMD_RESOLVE, CMD_UDP_ASSOCIATE, METHOD_NO_ACCEPTABLE, METHOD_USERPASS,
+};
+use crate::{ProxyConfig, WebSocketConnection};
+
+// ids correlating the log lines of one tunnel
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+
+pub(crate) async fn handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    #[cfg(feature = "mtls")] subject: Option<Extension<crate::tls::ClientSubject>>,
+    Extension(config): Extension<Arc<ProxyConfig>>,
+) -> Response {
+    // a v4 client of a dual-stack listener shows up v4-mapped, the rate limit,
+    // geoip, hooks and logs know it by its v4 address
+    let peer = connect_info
+        .map(|ConnectInfo(peer)| SocketAddr::new(peer.ip().to_canonical(), peer.port()));
+    if let Some(limit) = &config.rate_limit {
+        if let Err(retry_after) = limit.check(peer, &headers) {
+            warn!(?peer, "rate limited");
+            // round up, a client retrying early is refused again
+            let retry_after = retry_after.as_secs_f64().ceil().min(u32::MAX as f64) as u32;
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.to_string())],
+            )
+                .into_response();
+        }
+    }
+    #[cfg(feature = "geoip")]
+    if let Some(geoip) = &config.geoip {
+        if !geoip.admits(peer).await {
+            warn!(?peer, "country not allowed");
+            return StatusCode::FORBIDDEN.into_response();
+        }
+    }
+    // the certificate was verified in the handshake, only its absence is left
+    #[cfg(feature = "mtls")]
+    let client_subject = subject.map(|Extension(subject)| subject.0);
+    #[cfg(not(feature = "mtls"))]
+    let client_subject: Option<String> = None;
+    #[cfg(feature = "mtls")]
+    if config.require_client_certificate && client_subject.is_none() {
+        warn!(?peer, "no client certificate");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if !authorized(&config, &headers) {
+        warn!("missing or wrong authorization token");
+        return (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, "Bearer")]).into_response();
+    }
+    if let Some(origin) = headers.get(ORIGIN) {
+        let allowed = origin
+            .to_str()
+            .is_ok_and(|origin| config.origin_allowed(origin));
+        if !allowed {
+            warn!(?origin, "origin not allowed");
+            return StatusCode::FORBIDDEN.into_response();
+        }
+    }
+
+    if config.strict_subprotocol && !subprotocol_offered(&headers) {
+        warn!(protocols = ?headers.get(SEC_WEBSOCKET_PROTOCOL), "unknown websocket subprotocol");
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    if config.shutdown.is_draining() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    // open tunnels are left alone, new ones wait until some closed
+    if config.under_pressure() {
+        warn!(
+            ?peer,
+            active = config.shutdown.active(),
+            "under pressure, upgrade refused"
+        );
+        #[cfg(feature = "metrics")]
+        metrics::counter!("wssocks_rejected_connections_total", "reason" => "pressure")
+            .increment(1);
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    let compress = compression_agreed(&config, &headers);
+    // clients that know the end of stream frame ask for it, others keep
+    // closing the whole tunnel
+    let half_close = headers
+        .get(HALF_CLOSE_HEADER)
+        .is_some_and(|value| value == "1");
+    // for networks that let no binary frame through, handshake included
+    let text_frames = config.text_frames
+        && headers
+            .get(TEXT_FRAMES_HEADER)
+            .is_some_and(|value| value == "base64");
+    let conn_id = NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed);
+    // counted from here, so draining does not miss a tunnel being upgraded
+    let tunnel = config.shutdown.track();
+    let mut response = ws
+        .protocols([SUBPROTOCOL])
+        .max_message_size(config.max_message_size)
+        .max_frame_size(config.max_message_size)
+        .on_upgrade(move |socket| async move {
+            let shutdown = config.shutdown.clone();
+            tokio::select! {
+                _ = handle_socket(
+                    HandshakeSocket { socket, text_frames },
+                    config,
+                    conn_id,
+                    peer,
+                    client_subject,
+                    compress,
+                    half_close,
+                ) => {}
+                _ = shutdown.closing() => info!(conn_id, "tunnel closed by shutdown"),
+            }
+            drop(tunnel);
+        });
+    #[cfg(feature = "compression")]
+    if compress {
+        response.headers_mut().insert(
+            COMPRESSION_HEADER,
+            axum::http::HeaderValue::from_static("deflate"),
+        );
+    }
+    if half_close {
+        response
+            .headers_mut()
+            .insert(HALF_CLOSE_HEADER, axum::http::HeaderValue::from_static("1"));
+    }
+    if text_frames {
+        response.headers_mut().insert(
+            TEXT_FRAMES_HEADER,
+            axum::http::HeaderValue::from_static("base64"),
+        );
+    }
+    response
+        .headers_mut()
+        .insert(CONN_ID_HEADER, axum::http::HeaderValue::from(conn_id));
+    response
+}
+
+// no Sec-WebSocket-Protocol header at all, or one offering ours
+fn subprotocol_offered(headers: &HeaderMap) -> bool {
+    let offered = match headers.get(SEC_WEBSOCKET_PROTOCOL) {
+        Some(offered) => offered,
+        None => return true,
+    };
+    offered.to_str().is_ok_and(|offered| {
+        offered
+            .split(',')
+            .any(|protocol| protocol.trim() == SUBPROTOCOL)
+    })
+}
+
+// compress the tunnel when it is enabled and the client asked for it
+#[cfg(feature = "compression")]
+fn compression_agreed(config: &ProxyConfig, headers: &HeaderMap) -> bool {
+    config.compression
+        && headers
+            .get(COMPRESSION_HEADER)
+            .is_some_and(|value| value == "deflate")
+}
+
+#[cfg(not(feature = "compression"))]
+fn compression_agreed(_: &ProxyConfig, _: &HeaderMap) -> bool {
+    false
+}
+
+// the upgrade must carry the bearer token when one is configured
+fn authorized(config: &ProxyConfig, headers: &HeaderMap) -> bool {
+    let token = match &config.auth_token {
+        Some(token) => token,
+        None => return true,
+    };
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        == Some(token.as_str())
+}
+
+// peer is None when the router is served without connect info, as under shuttle
+#[instrument(name = "tunnel", skip(socket, config))]
+async fn handle_socket(
+    mut socket: HandshakeSocket,
+    config: Arc<ProxyConfig>,
+    conn_id: u64,
+    peer: Option<SocketAddr>,
+    client_subject: Option<String>,
+    compress: bool,
+    half_close: bool,
+) {
+    let started = Instant::now();
+    // hold a slot for the whole tunnel, the active gauge below only counts
+    // admitted tunnels
+    let _permit = match &config.tunnel_limit {
+        Some(limit) => match timeout(TUNNEL_QUEUE_TIMEOUT, limit.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Some(permit),
+            _ => {
+                #[cfg(feature = "metrics")]
+                metrics::counter!("wssocks_rejected_connections_total", "reason" => "limit")
+                    .increment(1);
+                refuse(&mut socket, &config, ProxyError::TooManyTunnels).await;
+                return;
+            }
+        },
+        None => None,
+    };
+    #[cfg(feature = "metrics")]
+    let _active = ActiveConnection::new();
+
+    let (addr, user, policy, outbound, early) =
+        match open(&mut socket, &config, peer, client_subject).await {
+            Ok(Opened::Tcp {
+                addr,
+                user,
+                policy,
+                outbound,
+                early,
+            }) => (addr, user, policy, outbound, early),
+            // the address is where the client will send from, but its datagrams
+            // arrive over this websocket
+            Ok(Opened::Udp { policy }) => {
+                udp_associate(socket, &config, policy).await;
+                return;
+            }
+            Ok(Opened::Resolved) => {
+                let frame = CloseFrame {
+                    code: CLOSE_NORMAL,
+                    reason: "resolved".into(),
+                };
+                let _ = timeout(CLOSE_TIMEOUT, socket.send(Message::Close(Some(frame)))).await;
+                return;
+            }
+            Err(e) => {
+                refuse(&mut socket, &config, e).await;
+                return;
+            }
+        };
+    // listed on the admin endpoint until this returns, whichever way
+    #[cfg(feature = "admin")]
+    let (listed, outbound) = match &config.admin_path {
+        Some(_) => {
+            let entry = config
+                .admin
+                .register(conn_id, peer, user.clone(), addr.clone(), early);
+            let outbound: Box<dyn AsyncReadWrite> = Box::new(entry.count(outbound));
+            (Some(entry), outbound)
+        }
+        None => (None, outbound),
+    };
+
+    // copy
+    // the relay flushes whenever the reader stalls, so coalescing
+    // its chunks into full frames never holds back interactive traffic
+    let mut inbound = WebSocketConnection::new(socket.socket)
+        .text_frames(socket.text_frames)
+        .coalesce(true)
+        .ping_interval(config.ping_interval)
+        .half_close(half_close);
+    #[cfg(feature = "compression")]
+    {
+        inbound = inbound.compression(compress);
+    }
+    let rate = policy
+        .and_then(|policy| policy.bandwidth_limit)
+        .or(config.bandwidth_limit);
+    #[cfg(feature = "admin")]
+    let cancel = listed.as_ref().map(|entry| entry.cancel());
+    #[cfg(not(feature = "admin"))]
+    let cancel = None;
+    let relayed = relay(
+        &mut inbound,
+        outbound,
+        config.idle_timeout,
+        config.max_lifetime.map(|lifetime| started + lifetime),
+        rate,
+        config.buffer_size,
+        cancel,
+    )
+    .await;
+    let reason = if relayed.killed {
+        CloseReason::Killed
+    } else if relayed.expired {
+        CloseReason::MaxLifetime
+    } else if relayed.idle {
+        CloseReason::Idle
+    } else if relayed.up.error.is_some() {
+        CloseReason::UpError
+    } else if relayed.down.error.is_some() {
+        CloseReason::DownError
+    } else {
+        CloseReason::Closed
+    };
+    // after a half-close both directions only sent their end of stream
+    // frame, a timed out or failed relay sent none, the close frame is ours
+    // to send with the reason's code, a no-op when the relay sent one
+    let (code, close_reason) = match &relayed.up.error {
+        Some(e) if MessageTooBig::is(e) => (CLOSE_MESSAGE_TOO_BIG, "message too big"),
+        _ => reason.close_code(),
+    };
+    let _ = timeout(CLOSE_TIMEOUT, inbound.close_with(code, close_reason)).await;
+    let (up, down) = (early + relayed.up.bytes, relayed.down.bytes);
+
+    let duration = started.elapsed();
+    if let Some(e) = &relayed.up.error {
+        info!(target = %addr, error = %e, "client to target failed");
+    }
+    if let Some(e) = &relayed.down.error {
+        info!(target = %addr, error = %e, "target to client failed");
+    }
+    info!(target = %addr, user, up, down, %reason, ?duration, "tunnel closed");
+    let stats = TunnelStats {
+        conn_id,
+        peer,
+        user,
+        target: addr,
+        bytes_up: up,
+        bytes_down: down,
+        duration,
+        reason,
+    };
+    #[cfg(feature = "access-log")]
+    if let Some(log) = &config.access_log {
+        log.record(&Record::new(&stats));
+    }
+    if let Some(hook) = &config.on_close {
+        (hook.0)(&stats);
+    }
+    #[cfg(feature = "metrics")]
+    {
+        metrics::counter!("wssocks_bytes_total", "direction" => "up").increment(up);
+        metrics::counter!("wssocks_bytes_total", "direction" => "down").increment(down);
+        metrics::histogram!("wssocks_connection_duration_seconds").record(duration.as_secs_f64());
+        metrics::counter!("wssocks_closed_connections_total", "reason" => reason.as_str())
+            .increment(1);
+    }
+}
+
+// the upgraded websocket until the handshake is done, whose binary messages
+// travel as base64 text ones on a text frames tunnel
+struct HandshakeSocket {
+    socket: WebSocket,
+    text_frames: bool,
+}
+
+impl HandshakeSocket {
+    async fn send(&mut self, message: Message) -> Result<(), axum::Error> {
+        let message = match message {
+            Message::Binary(data) if self.text_frames => Message::Text(STANDARD.encode(data)),
+            message => message,
+        };
+        self.socket.send(message).await
+    }
+
+    // text that is no base64 is handed on as is, to be refused like any
+    // other unexpected message
+    async fn recv(&mut self) -> Option<Result<Message, axum::Error>> {
+        match self.socket.recv().await {
+            Some(Ok(Message::Text(text))) if self.text_frames => match STANDARD.decode(&text) {
+                Ok(data) => Some(Ok(Message::Binary(data))),
+                Err(_) => Some(Ok(Message::Text(text))),
+            },
+            message => message,
+        }
+    }
+}
+
+// log why a tunnel failed and tell the client, if its protocol has a reply
+// and the config does not keep quiet
+async fn refuse(socket: &mut HandshakeSocket, config: &ProxyConfig, e: ProxyError) {
+    if e.is_target_error() {
+        info!(error = %e, "tunnel failed");
+    } else {
+        warn!(error = %e, "tunnel refused");
+    }
+    if !config.error_replies {
+        return;
+    }
+    if let Some(reply) = e.reply() {
+        let _ = socket.send(Message::Binary(reply)).await;
+    }
+    if let Some((code, reason)) = e.close_code() {
+        let frame = CloseFrame {
+            code,
+            reason: reason.into(),
+        };
+        let _ = timeout(CLOSE_TIMEOUT, socket.send(Message::Close(Some(frame)))).await;
+    }
+}
+
+// a tunnel ready to relay
+enum Opened<'a> {
+    Tcp {
+        addr: String,
+        // who authenticated, if credentials are required
+        user: Option<String>,
+        policy: Option<&'a UserPolicy>,
+        outbound: Box<dyn AsyncReadWrite>,
+        // bytes the client pipelined, already written to outbound
+        early: u64,
+    },
+    Udp {
+        policy: Option<&'a UserPolicy>,
+    },
+    // a RESOLVE answered, nothing is relayed
+    Resolved,
+}
+
+// run the handshake and connect to the target, everything up to the
+// successful reply
+async fn open<'a>(
+    socket: &mut HandshakeSocket,
+    config: &'a ProxyConfig,
+    peer: Option<SocketAddr>,
+    client_subject: Option<String>,
+) -> Result<Opened<'a>, ProxyError> {
+    // the client gets a limited time for the whole negotiation
+    let Handshake {
+        protocol,
+        request,
+        user,
+        early,
+    } = timeout(config.handshake_timeout, handshake(socket, config))
+        .await
+        .map_err(|_| ProxyError::HandshakeTimeout)??;
+    let policy = user.as_deref().and_then(|user| config.policies.get(user));
+    let target = match request {
+        Request::Connect(target) => rewrite_target(config, target),
+        Request::Bind(target) => {
+            let request = TunnelRequest {
+                peer,
+                user,
+                target: target.clone(),
+                client_subject,
+            };
+            check_abuse(config, &request)?;
+            check_connect_hook(config, &request, protocol)?;
+            let TunnelRequest { user, .. } = request;
+            let addr = target.to_string();
+            let mut outbound = bind(socket, config, policy, &target).await?;
+            // the peer is told of both replies already, only a relay error
+            // is left to report
+            if let Err(error) = write_early(&mut outbound, &early).await {
+                return Err(ProxyError::BindFailed {
+                    target: addr,
+                    error,
+                });
+            }
+            return Ok(Opened::Tcp {
+                addr,
+                user,
+                policy,
+                outbound,
+                early: early.len() as u64,
+            });
+        }
+        // each datagram comes in a frame of its own
+        Request::UdpAssociate => {
+            if !early.is_empty() {
+                debug!(
+                    len = early.len(),
+                    "bytes after the udp associate request dropped"
+                );
+            }
+            return Ok(Opened::Udp { policy });
+        }
+        Request::Resolve(target) => {
+            let target = rewrite_target(config, target);
+            let request = TunnelRequest {
+                peer,
+                user,
+                target: target.clone(),
+                client_subject,
+            };
+            check_abuse(config, &request)?;
+            check_connect_hook(config, &request, protocol)?;
+            // the rules a connection would face, but for the port
+            let allowed = resolve_checked(config, policy, &target)
+                .await
+                .map_err(|rep| ProxyError::Rejected(protocol, rep))?;
+            // the address goes in BND.ADDR, Tor leaves the port 0
+            let resolved = SocketAddr::new(allowed[0].ip(), 0);
+            socket
+                .send(Message::Binary(socks5_reply(0x00, resolved)))
+                .await
+                .map_err(|_| ProxyError::WebSocket("resolve reply"))?;
+            debug!(target = %target, resolved = %resolved.ip(), "resolved");
+            return Ok(Opened::Resolved);
+        }
+    };
+
+    let request = TunnelRequest {
+        peer,
+        user,
+        target: target.clone(),
+        client_subject,
+    };
+    check_abuse(config, &request)?;
+    // resolve the target ourselves, so the address we check is the one we
+    // connect to, a unix socket has no address to check and a connector
+    // may resolve the domains it is handed remotely
+    let unix = unix_socket_path(config, &target);
+    let allowed = match (unix, &config.connector, &target) {
+        (Some(path), _, _) if !unix_socket_allowed(config, path) => {
+            info!(target = %target, "unix socket not allowed");
+            // connection not allowed by ruleset
+            return Err(ProxyError::Rejected(protocol, 0x02));
+        }
+        (Some(_), _, _) => Vec::new(),
+        (None, Some(connector), Target::Domain { host, .. }) if connector.0.resolves_remotely() => {
+            domain_allowed(config, policy, &target, host)
+                .map_err(|rep| ProxyError::Rejected(protocol, rep))?;
+            Vec::new()
+        }
+        _ => resolve_allowed(config, policy, &target)
+            .await
+            .map_err(|rep| ProxyError::Rejected(protocol, rep))?,
+    };
+    check_connect_hook(config, &request, protocol)?;
+    let TunnelRequest { user, .. } = request;
+    let addr = target.to_string();
+
+    // connect to target, the reply carries the address the outbound socket is
+    // bound to, unknown behind a custom connector as is the address it reached
+    let unspecified = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+    let connected = match (unix, &config.connector) {
+        (Some(path), _) => connect_unix(config, path)
+            .await
+            .map(|stream| (stream, unspecified, unspecified)),
+        (None, Some(connector)) => {
+            let reached = match &target {
+                Target::Ip(addr) => *addr,
+                Target::Domain { port, .. } => SocketAddr::new(unspecified.ip(), *port),
+            };
+            match timeout(config.connect_timeout, connector.0.connect(&target)).await {
+                Ok(res) => res.map(|stream| (stream, unspecified, reached)),
+                Err(_) => Err(connect_timed_out()),
+            }
+        }
+        (None, None) => connect_direct(config, policy, allowed).await.map(|stream| {
+            let bind_addr = stream.local_addr().unwrap_or(unspecified);
+            let reached = stream.peer_addr().unwrap_or(unspecified);
+            (
+                Box::new(stream) as Box<dyn AsyncReadWrite>,
+                bind_addr,
+                reached,
+            )
+        }),
+    };
+    let connected = match connected {
+        Ok((mut outbound, bind_addr, reached)) => {
+            let mut head = proxy_header(config, peer, &target, reached);
+            head.extend_from_slice(&early);
+            write_early(&mut outbound, &head)
+                .await
+                .map(|_| (outbound, bind_addr))
+        }
+        Err(e) => Err(e),
+    };
+    let (outbound, bind_addr) = match connected {
+        Ok(connected) => connected,
+        Err(error) => {
+            return Err(ProxyError::ConnectFailed {
+                protocol,
+                target: addr,
+                error,
+            })
+        }
+    };
+
+    socket
+        .send(Message::Binary(protocol.reply(0x00, bind_addr)))
+        .await
+        .map_err(|_| ProxyError::WebSocket("reply"))?;
+    Ok(Opened::Tcp {
+        addr,
+        user,
+        policy,
+        outbound,
+        early: early.len() as u64,
+    })
+}
+
+// the target the rewrite hook returns in place of the client's, if any
+fn rewrite_target(config: &ProxyConfig, target: Target) -> Target {
+    let rewritten = config
+        .rewrite_target
+        .as_ref()
+        .and_then(|hook| (hook.0)(&target));
+    match rewritten {
+        Some(rewritten) => {
+            debug!(from = %target, to = %rewritten, "target rewritten");
+            rewritten
+        }
+        None => target,
+    }
+}
+
+// the socket path of a `unix:/path` domain, when unix sockets are enabled
+#[cfg(unix)]
+fn unix_socket_path<'t>(config: &ProxyConfig, target: &'t Target) -> Option<&'t str> {
+    match target {
+        Target::Domain { host, .. } if !config.unix_sockets.is_empty() => {
+            host.strip_prefix("unix:")
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn unix_socket_path<'t>(_: &ProxyConfig, _: &'t Target) -> Option<&'t str> {
+    None
+}
+
+// whether the socket at path is one the config lets tunnels reach
+#[cfg(unix)]
+fn unix_socket_allowed(config: &ProxyConfig, path: &str) -> bool {
+    config
+        .unix_sockets
+        .iter()
+        .any(|allowed| allowed == std::path::Path::new(path))
+}
+
+#[cfg(not(unix))]
+fn unix_socket_allowed(_: &ProxyConfig, _: &str) -> bool {
+    unreachable!("no unix socket path off unix")
+}
+
+#[cfg(unix)]
+async fn connect_unix(
+    config: &ProxyConfig,
+    path: &str,
+) -> std::io::Result<Box<dyn AsyncReadWrite>> {
+    match timeout(
+        config.connect_timeout,
+        tokio::net::UnixStream::connect(path),
+    )
+    .await
+    {
+        Ok(stream) => Ok(Box::new(stream?)),
+        Err(_) => Err(connect_timed_out()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(_: &ProxyConfig, _: &str) -> std::io::Result<Box<dyn AsyncReadWrite>> {
+    unreachable!("no unix socket path off unix")
+}
+
+// hand the target what the client pipelined after its request
+async fn write_early(outbound: &mut Box<dyn AsyncReadWrite>, early: &[u8]) -> std::io::Result<()> {
+    if early.is_empty() {
+        return Ok(());
+    }
+    outbound.write_all(early).await?;
+    outbound.flush().await
+}
+
+// the PROXY protocol header to start a connection to target with, which
+// reached dst, empty unless the config asks for one
+fn proxy_header(
+    config: &ProxyConfig,
+    peer: Option<SocketAddr>,
+    target: &Target,
+    dst: SocketAddr,
+) -> Vec<u8> {
+    let host = match target {
+        Target::Domain { host, .. } => Some(host.as_str()),
+        Target::Ip(_) => None,
+    };
+    match &config.proxy_protocol {
+        Some(targets) if targets.allows(host, dst.ip()) => proxy_protocol::header_v2(peer, dst),
+        _ => Vec::new(),
+    }
+}
+
+// let the abuse classifier flag the target host, reporting the client when
+// it does
+fn check_abuse(config: &ProxyConfig, request: &TunnelRequest) -> Result<(), ProxyError> {
+    let classifier = match &config.abuse_classifier {
+        Some(classifier) => classifier,
+        None => return Ok(()),
+    };
+    let ip;
+    let host = match &request.target {
+        Target::Domain { host, .. } => host.as_str(),
+        Target::Ip(addr) => {
+            ip = addr.ip().to_string();
+            ip.as_str()
+        }
+    };
+    if (classifier.0)(host) == Verdict::Allow {
+        return Ok(());
+    }
+    if let Some(hook) = &config.on_abuse {
+        (hook.0)(request);
+    }
+    Err(ProxyError::Abuse(request.target.to_string()))
+}
+
+// let the connect hook veto a tunnel
+fn check_connect_hook(
+    config: &ProxyConfig,
+    request: &TunnelRequest,
+    protocol: Protocol,
+) -> Result<(), ProxyError> {
+    let hook = match &config.on_connect {
+        Some(hook) => hook,
+        None => return Ok(()),
+    };
+    if !(hook.0)(request) {
+        info!(target = %request.target, "target vetoed by the connect hook");
+        // connection not allowed by ruleset
+        return Err(ProxyError::Rejected(protocol, 0x02));
+    }
+    Ok(())
+}
+
+// socks5 BIND: listen for the one inbound connection the client expects from
+// addr, with a reply once listening and one once it arrived
+async fn bind(
+    socket: &mut HandshakeSocket,
+    config: &ProxyConfig,
+    policy: Option<&UserPolicy>,
+    target: &Target,
+) -> Result<Box<dyn AsyncReadWrite>, ProxyError> {
+    let failed = |error| ProxyError::BindFailed {
+        target: target.to_string(),
+        error,
+    };
+
+    // the peer may come from any of these, or from anywhere when the client
+    // sent an unspecified address
+    let resolved = match target {
+        Target::Ip(addr) => Ok(Ok(vec![*addr])),
+        Target::Domain { host, port } => {
+            timeout(
+                config.connect_timeout,
+                config.resolver.0.resolve(host, *port),
+            )
+            .await
+        }
+    };
+    let expected = match resolved {
+        Ok(Ok(addrs)) => addrs
+            .into_iter()
+            .map(|addr| addr.ip().to_canonical())
+            .filter(|ip| !ip.is_unspecified())
+            .collect::<Vec<_>>(),
+        Ok(Err(e)) => {
+            info!(target = %target, error = %e, "resolve failed");
+            return Err(ProxyError::Rejected(Protocol::Socks5, 0x04));
+        }
+        Err(_) => {
+            info!(target = %target, "resolve timed out");
+            return Err(ProxyError::Rejected(Protocol::Socks5, 0x04));
+        }
+    };
+
+    let ip = match (outbound_bind(config, policy), expected.first()) {
+        (Some(ip), _) => ip,
+        (None, Some(IpAddr::V6(_))) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        (None, _) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+    let listener = listen_tcp(config, SocketAddr::new(ip, 0))
+        .await
+        .map_err(failed)?;
+    let mut bound = listener.local_addr().map_err(failed)?;
+    // tell the client the address the peer will reach us on, an unspecified
+    // one means the proxy's own
+    if let (true, Some(peer)) = (bound.ip().is_unspecified(), expected.first()) {
+        if let Some(ip) = local_ip_towards(config, *peer).await {
+            bound.set_ip(ip);
+        }
+    }
+    socket
+        .send(Message::Binary(socks5_reply(0x00, bound)))
+        .await
+        .map_err(|_| ProxyError::WebSocket("first bind reply"))?;
+    debug!(target = %target, %bound, "listening for bind");
+
+    let accept = async {
+        loop {
+            let (stream, from) = listener.accept().await?;
+            if (expected.is_empty() || expected.contains(&from.ip().to_canonical()))
+                && ip_allowed(config, policy, None, from.ip())
+            {
+                return Ok::<_, std::io::Error>((stream, from));
+            }
+            info!(target = %target, peer = %from, "unexpected bind connection dropped");
+        }
+    };
+    // nothing is relayed while waiting, so the idle timeout applies
+    let accepted = match config.idle_timeout {
+        Some(wait) => timeout(wait, accept)
+            .await
+            .unwrap_or_else(|_| Err(connect_timed_out())),
+        None => accept.await,
+    };
+    let (stream, from) = accepted.map_err(failed)?;
+    let _ = stream.set_nodelay(config.tcp_nodelay);
+    socket
+        .send(Message::Binary(socks5_reply(0x00, from)))
+        .await
+        .map_err(|_| ProxyError::WebSocket("second bind reply"))?;
+    Ok(Box::new(stream))
+}
+
+// the local address the OS would send to ip from, no packet is sent
+async fn local_ip_towards(config: &ProxyConfig, ip: IpAddr) -> Option<IpAddr> {
+    let unspecified = match ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let udp = bind_udp(config, SocketAddr::new(unspecified, 0))
+        .await
+        .ok()?;
+    udp.connect((ip, 9)).await.ok()?;
+    udp.local_addr().ok().map(|addr| addr.ip())
+}
+
+// a request the socks5 handshake ended with
+enum Request {
+    Connect(Target),
+    Bind(Target),
+    UdpAssociate,
+    Resolve(Target),
+}
+
+// read the request in whichever protocol the client speaks
+// what the client asked for
+struct Handshake {
+    protocol: Protocol,
+    request: Request,
+    // who logged in
+    user: Option<String>,
+    // tunnel bytes a pipelining client sent right after its request, without
+    // waiting for the reply
+    early: Vec<u8>,
+}
+
+async fn handshake(
+    socket: &mut HandshakeSocket,
+    config: &ProxyConfig,
+) -> Result<Handshake, ProxyError> {
+    // first msg, the method selection for socks5 or the whole request for socks4
+    // and http
+    let buf = match socket.recv().await {
+        Some(Ok(Message::Binary(data))) => data,
+        other => return Err(recv_failed(other, "method selection message")),
+    };
+
+    if buf.first() == Some(&0x04) {
+        let (buf, early) = read_message(socket, buf, socks4::request_len, "socks4 request").await?;
+        let addr = socks4_request(config, &buf)?;
+        Ok(Handshake {
+            protocol: Protocol::Socks4,
+            request: Request::Connect(addr),
+            user: None,
+            early,
+        })
+    } else if http::is_connect(&buf) {
+        let (addr, user, early) = http_connect(socket, config, buf).await?;
+        Ok(Handshake {
+            protocol: Protocol::Http,
+            request: Request::Connect(addr),
+            user,
+            early,
+        })
+    } else {
+        let (request, user, early) = socks5_handshake(socket, config, buf).await?;
+        Ok(Handshake {
+            protocol: Protocol::Socks5,
+            request,
+            user,
+            early,
+        })
+    }
+}
+
+// why a handshake message did not arrive, a client sending more than the
+// size limit is told so
+fn recv_failed(
+    received: Option<Result<Message, axum::Error>>,
+    message: &'static str,
+) -> ProxyError {
+    match received {
+        Some(Err(e)) if MessageTooBig::caused(&e) => ProxyError::MessageTooBig,
+        _ => ProxyError::WebSocket(message),
+    }
+}
+
+// keep reading frames until buf holds a whole message, as told by len, since
+// a client may split one message over several frames, also returns the bytes
+// that came after it
+async fn read_message(
+    socket: &mut HandshakeSocket,
+    mut buf: Vec<u8>,
+    len: fn(&[u8]) -> Option<usize>,
+    message: &'static str,
+) -> Result<(Vec<u8>, Vec<u8>), ProxyError> {
+    let len = loop {
+        match len(&buf) {
+            Some(len) if buf.len() >= len => break len,
+            _ => match socket.recv().await {
+                Some(Ok(Message::Binary(data))) => buf.extend_from_slice(&data),
+                other => return Err(recv_failed(other, message)),
+            },
+        }
+    };
+    let rest = buf.split_off(len);
+    Ok((buf, rest))
+}
+
+// negotiate the auth method, authenticate and read the request, buf is the
+// first frame of the method selection message, also returns the user who
+// logged in
+async fn socks5_handshake(
+    socket: &mut HandshakeSocket,
+    config: &ProxyConfig,
+    buf: Vec<u8>,
+) -> Result<(Request, Option<String>, Vec<u8>), ProxyError> {
+    // a client may send each message without waiting for our answer to the
+    // one before, rest carries what it sent ahead
+    let (buf, mut rest) =
+        read_message(socket, buf, methods_len, "method selection message").await?;
+
+    // valid socks5 version and data length
+    let methods = parse_methods(&buf).ok_or(ProxyError::BadMethodSelection)?;
+
+    // answer with the method we picked, the client hangs up when none fits
+    let method = select_method(methods, !config.credentials.is_empty());
+    if method == METHOD_NO_ACCEPTABLE {
+        return Err(ProxyError::NoAcceptableMethod(methods.to_vec()));
+    }
+    socket
+        .send(Message::Binary(vec![0x05, method]))
+        .await
+        .map_err(|_| ProxyError::WebSocket("method selection reply"))?;
+
+    let mut user = None;
+    if method == METHOD_USERPASS {
+        // username/password sub-negotiation
+        let buf;
+        (buf, rest) = read_message(socket, rest, userpass_len, "username/password message").await?;
+        user = match parse_userpass(&buf) {
+            Some((username, password)) if config.verify(username, password) => {
+                Some(username.to_string())
+            }
+            _ => return Err(ProxyError::AuthFailed(Protocol::Socks5)),
+        };
+        socket
+            .send(Message::Binary(b"\x01\x00".to_vec()))
+            .await
+            .map_err(|_| ProxyError::WebSocket("username/password reply"))?;
+    }
+
+    // second msg from socks with target address
+    let (buf, early) = read_message(socket, rest, request_len, "request message").await?;
+    let request = parse_socks5_request(&buf).map_err(ProxyError::BadRequest)?;
+    let (cmd, target) = (request.cmd, request.target);
+
+    debug!(cmd, target = %target, "request");
+    match cmd {
+        CMD_CONNECT => Ok((Request::Connect(target), user, early)),
+        CMD_BIND if config.bind_command => Ok((Request::Bind(target), user, early)),
+        CMD_UDP_ASSOCIATE => Ok((Request::UdpAssociate, user, early)),
+        CMD_RESOLVE if config.resolve_command => Ok((Request::Resolve(target), user, early)),
+        _ => Err(ProxyError::UnsupportedCommand(Protocol::Socks5, cmd)),
+    }
+}
+
+// check a socks4 or socks4a request, buf is the whole request
+fn socks4_request(config: &ProxyConfig, buf: &[u8]) -> Result<Target, ProxyError> {
+    let (cmd, addr) = socks4::parse_request(buf).ok_or(ProxyError::BadSocks4Request)?;
+
+    // socks4 has no passwords, so it is off limits once credentials are set
+    if !config.credentials.is_empty() {
+        return Err(ProxyError::AuthFailed(Protocol::Socks4));
+    }
+
+    if cmd != CMD_CONNECT {
+        return Err(ProxyError::UnsupportedCommand(Protocol::Socks4, cmd));
+    }
+    debug!(cmd, target = %addr, "socks4 request");
+    Ok(addr)
+}
+
+// read an http CONNECT request, buf is its first frame, also returns the user
+// who logged in and the bytes after the head
+async fn http_connect(
+    socket: &mut HandshakeSocket,
+    config: &ProxyConfig,
+    mut buf: Vec<u8>,
+) -> Result<(Target, Option<String>, Vec<u8>), ProxyError> {
+    // the head may span several frames
+    let len = loop {
+        if let Some(len) = http::head_len(&buf) {
+            break len;
+        }
+        if buf.len() > http::MAX_HEAD_SIZE {
+            return Err(ProxyError::BadHttpRequest(431));
+        }
+        match socket.recv().await {
+            Some(Ok(Message::Binary(data))) => buf.extend_from_slice(&data),
+            other => return Err(recv_failed(other, "http request")),
+        }
+    };
+    let early = buf.split_off(len);
+
+    let request = http::parse_connect(&buf).map_err(ProxyError::BadHttpRequest)?;
+
+    let mut user = None;
+    if !config.credentials.is_empty() {
+        user = match request.credentials {
+            Some((username, password)) if config.verify(&username, &password) => Some(username),
+            _ => return Err(ProxyError::AuthFailed(Protocol::Http)),
+        };
+    }
+    debug!(target = %request.target, "http request");
+    Ok((request.target, user, early))
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Protocol {
+    Socks4,
+    Socks5,
+    Http,
+}
+
+impl Protocol {
+    // the reply to a connect, rep is a socks5 reply code, socks4 only tells
+    // success from failure and http maps it to a status
+    pub(crate) fn reply(self, rep: u8, addr: SocketAddr) -> Vec<u8> {
+        match self {
+            Protocol::Socks4 => socks4::reply(rep == 0x00, addr),
+            Protocol::Socks5 => socks5_reply(rep, addr),
+            Protocol::Http => http::response(http::status(rep)),
+        }
+    }
+}
+
+// resolve a host:port target to the addresses the config and the user's policy
+// let us connect to, fails with the socks5 reply code to send
+async fn resolve_allowed(
+    config: &ProxyConfig,
+    policy: Option<&UserPolicy>,
+    target: &Target,
+) -> Result<Vec<SocketAddr>, u8> {
+    // refused before resolving, a spam run should not cost lookups either
+    if !config.port_allowed(target.port()) {
+        info!(target = %target, "target port not allowed");
+        // connection not allowed by ruleset
+        return Err(0x02);
+    }
+    resolve_checked(config, policy, target).await
+}
+
+// check a domain target a connector resolves remotely by its name alone, the rules
+// naming an address and the private address block cannot apply to it
+fn domain_allowed(
+    config: &ProxyConfig,
+    policy: Option<&UserPolicy>,
+    target: &Target,
+    host: &str,
+) -> Result<(), u8> {
+    if !config.port_allowed(target.port()) {
+        info!(target = %target, "target port not allowed");
+        // connection not allowed by ruleset
+        return Err(0x02);
+    }
+    let allowed = config.acl.allows_domain(host)
+        && policy.is_none_or(|policy| policy.acl.allows_domain(host));
+    if !allowed {
+        info!(target = %target, "target not allowed");
+        // connection not allowed by ruleset
+        return Err(0x02);
+    }
+    Ok(())
+}
+
+// resolve the target and keep the addresses the rules allow, whatever its
+// port, Err is the reply
+async fn resolve_checked(
+    config: &ProxyConfig,
+    policy: Option<&UserPolicy>,
+    target: &Target,
+) -> Result<Vec<SocketAddr>, u8> {
+    // rules naming a domain apply to everything it resolves to
+    let (host, mut resolved) = match target {
+        Target::Ip(addr) => (None, vec![*addr]),
+        Target::Domain { host, port } => {
+            let resolved = match timeout(
+                config.connect_timeout,
+                config.resolver.0.resolve(host, *port),
+            )
+            .await
+            {
+                // told apart from a target refusing the connection, these
+                // are all down to dns
+                Ok(Ok(addrs)) if addrs.is_empty() => {
+                    info!(target = %target, "domain resolved to no address");
+                    // host unreachable
+                    return Err(0x04);
+                }
+                Ok(Ok(addrs)) => addrs,
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                    info!(target = %target, error = %e, "no such domain");
+                    return Err(0x04);
+                }
+                Ok(Err(e)) => {
+                    info!(target = %target, error = %e, "resolve failed");
+                    return Err(0x04);
+                }
+                Err(_) => {
+                    info!(target = %target, "resolve timed out");
+                    return Err(0x04);
+                }
+            };
+            let count = resolved.len();
+            let resolved = config.address_family.apply(resolved);
+            if resolved.is_empty() {
+                info!(
+                    target = %target,
+                    resolved = count,
+                    family = ?config.address_family,
+                    "domain resolved to no address of the configured family"
+                );
+                return Err(0x04);
+            }
+            (Some(host.as_str()), resolved)
+        }
+    };
+    // a socket bound to a source address only reaches its own family
+    if let Some(bind) = outbound_bind(config, policy) {
+        resolved.retain(|addr| addr.is_ipv4() == bind.is_ipv4());
+        if resolved.is_empty() {
+            info!(target = %target, bind = %bind, "no address of the bind address family");
+            // network unreachable
+            return Err(0x03);
+        }
+    }
+    let allowed = resolved
+        .into_iter()
+        .filter(|addr| ip_allowed(config, policy, host, addr.ip()))
+        .collect::<Vec<_>>();
+    if allowed.is_empty() {
+        info!(target = %target, "target not allowed");
+        // connection not allowed by ruleset
+        return Err(0x02);
+    }
+    Ok(allowed
+        .into_iter()
+        .map(|addr| with_scope_id(config, addr))
+        .collect())
+}
+
+// link-local v6 addresses need an interface to be reachable, a socks request
+// has no way to carry one
+fn with_scope_id(config: &ProxyConfig, target: SocketAddr) -> SocketAddr {
+    match (target, config.ipv6_scope_id) {
+        (SocketAddr::V6(mut v6), Some(scope_id))
+            if v6.scope_id() == 0 && (v6.ip().segments()[0] & 0xffc0) == 0xfe80 =>
+        {
+            v6.set_scope_id(scope_id);
+            SocketAddr::V6(v6)
+        }
+        _ => target,
+    }
+}
+
+// whether the rules let a user's tunnel reach ip, host is the domain it was
+// resolved from
+fn ip_allowed(
+    config: &ProxyConfig,
+    policy: Option<&UserPolicy>,
+    host: Option<&str>,
+    ip: IpAddr,
+) -> bool {
+    config.acl.allows(host, ip)
+        && policy.is_none_or(|policy| policy.acl.allows(host, ip))
+        && !(config.block_private && is_private(ip))
+}
+
+// the source address of a user's tunnels
+fn outbound_bind(config: &ProxyConfig, policy: Option<&UserPolicy>) -> Option<IpAddr> {
+    policy
+        .and_then(|policy| policy.outbound_bind)
+        .or(config.outbound_bind)
+}
+
+// the source port of a user's tunnels
+fn outbound_source_port(config: &ProxyConfig, policy: Option<&UserPolicy>) -> Option<u16> {
+    policy
+        .and_then(|policy| policy.outbound_source_port)
+        .or(config.outbound_source_port)
+}
+
+// open a connection to the target from the configured source address and
+// port, from an ephemeral port when that one is taken
+async fn connect_tcp(
+    config: &ProxyConfig,
+    policy: Option<&UserPolicy>,
+    addr: SocketAddr,
+) -> std::io::Result<TcpStream> {
+    let Some(port) = outbound_source_port(config, policy) else {
+        return connect_from(config, policy, addr, 0).await;
+    };
+    match connect_from(config, policy, addr, port).await {
+        // taken by a listener, or by a tunnel to the same target
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::AddrInUse | std::io::ErrorKind::AddrNotAvailable
+            ) =>
+        {
+            info!(target = %addr, port, error = %e, "source port taken, connecting from an ephemeral one");
+            connect_from(config, policy, addr, 0).await
+        }
+        connected => connected,
+    }
+}
+
+async fn connect_from(
+    config: &ProxyConfig,
+    policy: Option<&UserPolicy>,
+    addr: SocketAddr,
+    port: u16,
+) -> std::io::Result<TcpStream> {
+    let socket = tcp_socket(config, addr).await?;
+    set_mark(config, &socket)?;
+    let bind = outbound_bind(config, policy);
+    if port != 0 {
+        // tunnels to different targets share the port
+        socket.set_reuseaddr(true)?;
+        let unspecified = match addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        socket.bind(SocketAddr::new(bind.unwrap_or(unspecified), port))?;
+    } else if let Some(bind) = bind {
+        socket.bind(SocketAddr::new(bind, 0))?;
+    }
+    let stream = socket.connect(addr).await?;
+    stream.set_nodelay(config.tcp_nodelay)?;
+    if let Some((idle, interval)) = config.tcp_keepalive {
+        let keepalive = socket2::TcpKeepalive::new().with_time(idle);
+        // the probe interval is not tunable everywhere
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+        let keepalive = keepalive.with_interval(interval);
+        #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+        let _ = interval;
+        socket2::SockRef::from(&stream).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(stream)
+}
+
+// a socket of addr's family, in the configured network namespace
+async fn tcp_socket(config: &ProxyConfig, addr: SocketAddr) -> std::io::Result<TcpSocket> {
+    #[cfg(all(feature = "netns", target_os = "linux"))]
+    if let Some(netns) = &config.netns {
+        let domain = socket2::Domain::for_address(addr);
+        let socket = netns.socket(domain, socket2::Type::STREAM).await?;
+        return Ok(TcpSocket::from_std_stream(socket.into()));
+    }
+    let _ = config;
+    if addr.is_ipv4() {
+        TcpSocket::new_v4()
+    } else {
+        TcpSocket::new_v6()
+    }
+}
+
+// a udp socket bound to addr, in the configured network namespace
+async fn bind_udp(config: &ProxyConfig, addr: SocketAddr) -> std::io::Result<UdpSocket> {
+    #[cfg(all(feature = "netns", target_os = "linux"))]
+    if let Some(netns) = &config.netns {
+        let domain = socket2::Domain::for_address(addr);
+        let socket = netns.socket(domain, socket2::Type::DGRAM).await?;
+        socket.bind(&addr.into())?;
+        return UdpSocket::from_std(socket.into());
+    }
+    let _ = config;
+    UdpSocket::bind(addr).await
+}
+
+// a listener on addr, in the configured network namespace
+async fn listen_tcp(config: &ProxyConfig, addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = tcp_socket(config, addr).await?;
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+// tag the outbound socket with the configured fwmark for policy routing
+#[cfg(all(feature = "fwmark", target_os = "linux"))]
+fn set_mark(config: &ProxyConfig, socket: &impl std::os::fd::AsFd) -> std::io::Result<()> {
+    match config.fwmark {
+        Some(mark) => socket2::SockRef::from(socket).set_mark(mark),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(all(feature = "fwmark", target_os = "linux")))]
+fn set_mark<S>(_: &ProxyConfig, _: &S) -> std::io::Result<()> {
+    Ok(())
+}
+
+// connect to the allowed addresses of a target ourselves
+async fn connect_direct(
+    config: &ProxyConfig,
+    policy: Option<&UserPolicy>,
+    allowed: Vec<SocketAddr>,
+) -> std::io::Result<TcpStream> {
+    if config.happy_eyeballs {
+        connect_happy_eyeballs(config, policy, interleave_families(allowed)).await
+    } else {
+        match timeout(
+            config.connect_timeout,
+            connect_any(config, policy, &allowed),
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(_) => Err(connect_timed_out()),
+        }
+    }
+}
+
+// connect to the first address accepting the connection
+async fn connect_any(
+    config: &ProxyConfig,
+    policy: Option<&UserPolicy>,
+    addrs: &[SocketAddr],
+) -> std::io::Result<TcpStream> {
+    let mut last_err = None;
+    for &addr in addrs {
+        match connect_tcp(config, policy, addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address to connect to")
+    }))
+}
+
+// how long a tunnel over the limit waits for a slot before it is refused
+const TUNNEL_QUEUE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// how long closing a half-close tunnel may wait on a slow client
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// how long an attempt may go unanswered before the next address is tried too
+const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
+
+// race the addresses, starting the next attempt whenever the previous one
+// failed or did not succeed within the delay, the first connection wins and
+// the others are dropped
+async fn connect_happy_eyeballs(
+    config: &ProxyConfig,
+    policy: Option<&UserPolicy>,
+    addrs: Vec<SocketAddr>,
+) -> std::io::Result<TcpStream> {
+    let attempt = |addr: SocketAddr| async move {
+        match timeout(config.connect_timeout, connect_tcp(config, policy, addr)).await {
+            Ok(res) => res,
+            Err(_) => Err(connect_timed_out()),
+        }
+    };
+
+    let mut addrs = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+    loop {
+        if attempts.is_empty() {
+            match addrs.next() {
+                Some(addr) => attempts.push(attempt(addr)),
+                None => break,
+            }
+        }
+        tokio::select! {
+            Some(res) = attempts.next() => match res {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    last_err = Some(e);
+                    if let Some(addr) = addrs.next() {
+                        attempts.push(attempt(addr));
+                    }
+                }
+            },
+            _ = sleep(HAPPY_EYEBALLS_DELAY), if addrs.len() > 0 => {
+                if let Some(addr) = addrs.next() {
+                    attempts.push(attempt(addr));
+                }
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address to connect to")
+    }))
+}
+
+// alternate between the families, keeping the order within each and starting
+// with the family of the first address
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let len = addrs.len();
+    let (first, second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    let mut interleaved = Vec::with_capacity(len);
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+fn connect_timed_out() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::TimedOut, "connect timed out")
+}
+
+// counts the tunnel as active until it is dropped, on every return path
+#[cfg(feature = "metrics")]
+struct ActiveConnection;
+
+#[cfg(feature = "metrics")]
+impl ActiveConnection {
+    fn new() -> Self {
+        metrics::counter!("wssocks_connections_total").increment(1);
+        metrics::gauge!("wssocks_active_connections").increment(1.0);
+        Self
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        metrics::gauge!("wssocks_active_connections").decrement(1.0);
+    }
+}
+
+// relay datagrams between the websocket and a udp socket bound for this
+// association, each binary frame holds one socks5 udp request with its header
+async fn udp_associate(socket: HandshakeSocket, config: &ProxyConfig, policy: Option<&UserPolicy>) {
+    // every frame from here on holds one datagram
+    let mut socket = WebSocketDatagram::new(socket.socket).text_frames(socket.text_frames);
+    // one dual-stack socket serves both families, fall back to v4 only
+    let bound = match outbound_bind(config, policy) {
+        Some(bind) => bind_udp(config, SocketAddr::new(bind, 0)).await,
+        None => match bind_udp(config, (Ipv6Addr::UNSPECIFIED, 0).into()).await {
+            Ok(udp) => Ok(udp),
+            Err(_) => bind_udp(config, (Ipv4Addr::UNSPECIFIED, 0).into()).await,
+        },
+    };
+    let udp = match bound.and_then(|udp| set_mark(config, &udp).map(|_| udp)) {
+        Ok(udp) => udp,
+        Err(e) => {
+            warn!(error = %e, "udp bind failed");
+            if !config.error_replies {
+                return;
+            }
+            let _ = socket
+                .send(b"\x05\x01\x00\x01\x00\x00\x00\x00\x00\x00".to_vec())
+                .await;
+            return;
+        }
+    };
+    let bind_addr = match udp.local_addr() {
+        Ok(addr) => addr,
+        Err(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+    };
+    let dual_stack = bind_addr.is_ipv6() && bind_addr.ip().is_unspecified();
+    if socket.send(socks5_reply(0x00, bind_addr)).await.is_err() {
+        return;
+    }
+
+    // the association idles out like a tunnel, a datagram either way resets
+    // the timer
+    let mut idle = Box::pin(sleep(config.idle_timeout.unwrap_or_default()));
+    let mut buf = vec![0u8; 65535];
+    loop {
+        tokio::select! {
+            _ = &mut idle, if config.idle_timeout.is_some() => {
+                debug!("udp association idle");
+                let frame = CloseFrame {
+                    code: CLOSE_IDLE_TIMEOUT,
+                    reason: "idle timeout".into(),
+                };
+                let mut socket = socket.into_inner();
+                let _ = timeout(CLOSE_TIMEOUT, socket.send(Message::Close(Some(frame)))).await;
+                return;
+            }
+            msg = socket.recv() => {
+                let data = match msg {
+                    Ok(Some(data)) => data,
+                    Err(e) if MessageTooBig::is(&e) => {
+                        let frame = CloseFrame {
+                            code: CLOSE_MESSAGE_TOO_BIG,
+                            reason: "message too big".into(),
+                        };
+                        let mut socket = socket.into_inner();
+                        let _ = timeout(CLOSE_TIMEOUT, socket.send(Message::Close(Some(frame)))).await;
+                        return;
+                    }
+                    // the association ends with the websocket
+                    _ => return,
+                };
+                if let Some(wait) = config.idle_timeout {
+                    idle.as_mut().reset(Instant::now() + wait);
+                }
+
+                // RSV RSV FRAG, fragmented datagrams are not supported
+                let [_, _, 0, _, ..] = data[..] else {
+                    continue;
+                };
+                let (target, len) = match parse_target(&data[3..]) {
+                    Some(parsed) => parsed,
+                    None => continue,
+                };
+                let target = match resolve_allowed(config, policy, &target).await {
+                    Ok(allowed) => allowed[0],
+                    Err(_) => continue,
+                };
+                let target = match target {
+                    SocketAddr::V4(v4) if dual_stack => {
+                        SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port())
+                    }
+                    target => target,
+                };
+                let _ = udp.send_to(&data[3 + len..], target).await;
+            }
+            res = udp.recv_from(&mut buf) => {
+                let (n, from) = match res {
+                    Ok(res) => res,
+                    Err(_) => continue,
+                };
+                if let Some(wait) = config.idle_timeout {
+                    idle.as_mut().reset(Instant::now() + wait);
+                }
+                let from = match from {
+                    SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+                        Some(ip) => SocketAddr::new(IpAddr::V4(ip), v6.port()),
+                        None => from,
+                    },
+                    from => from,
+                };
+
+                let mut datagram = vec![0x00, 0x00, 0x00];
+                encode_address(&mut datagram, from);
+                datagram.extend_from_slice(&buf[..n]);
+                if socket.send(datagram).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
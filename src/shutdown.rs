@@ -0,0 +1,113 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use tokio::{sync::Notify, time::timeout};
+use tracing::{info, warn};
+
+/// Stops a proxy without cutting its tunnels short: pass a clone to
+/// [`ProxyConfig::shutdown`](crate::ProxyConfig::shutdown) and call
+/// [`drain`](Self::drain) when the server should go away, e.g. on SIGTERM.
+///
+/// ```no_run
+/// # async fn run() {
+/// use std::time::Duration;
+///
+/// let shutdown = wssocks::Shutdown::new();
+/// let app = wssocks::router(wssocks::ProxyConfig::default().shutdown(shutdown.clone()));
+/// axum::Server::bind(&"0.0.0.0:8000".parse().unwrap())
+///     .serve(app.into_make_service())
+///     .with_graceful_shutdown(async { tokio::signal::ctrl_c().await.unwrap() })
+///     .await
+///     .unwrap();
+/// shutdown.drain(Duration::from_secs(30)).await;
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    draining: AtomicBool,
+    closing: AtomicBool,
+    // wakes the tunnels once they have to close
+    close: Notify,
+    active: AtomicUsize,
+    // wakes drain once the last tunnel is gone
+    idle: Notify,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuses new upgrades with 503 and waits for the open tunnels to finish,
+    /// tunnels still open after `grace` are closed. Returns once no tunnel is
+    /// left.
+    pub async fn drain(&self, grace: Duration) {
+        self.inner.draining.store(true, Ordering::SeqCst);
+        info!(active = self.active(), "draining tunnels");
+        if timeout(grace, self.idle()).await.is_err() {
+            warn!(active = self.active(), "grace period over, closing tunnels");
+            self.inner.closing.store(true, Ordering::SeqCst);
+            self.inner.close.notify_waiters();
+            self.idle().await;
+        }
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.inner.draining.load(Ordering::SeqCst)
+    }
+
+    // counts a tunnel as open until the guard is dropped
+    pub(crate) fn track(&self) -> Tunnel {
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        Tunnel {
+            inner: self.inner.clone(),
+        }
+    }
+
+    // resolves once the grace period is over
+    pub(crate) async fn closing(&self) {
+        loop {
+            // register before checking, so a notification in between is not lost
+            let notified = self.inner.close.notified();
+            if self.inner.closing.load(Ordering::SeqCst) {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    async fn idle(&self) {
+        loop {
+            let notified = self.inner.idle.notified();
+            if self.active() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    // the tunnels open, those still being upgraded included
+    pub(crate) fn active(&self) -> usize {
+        self.inner.active.load(Ordering::SeqCst)
+    }
+}
+
+pub(crate) struct Tunnel {
+    inner: Arc<Inner>,
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        if self.inner.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.idle.notify_waiters();
+        }
+    }
+}
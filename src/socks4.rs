@@ -0,0 +1,82 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use crate::socks5::Target;
+
+const REPLY_GRANTED: u8 = 0x5a;
+const REPLY_REJECTED: u8 = 0x5b;
+
+// a request without its NUL terminators by now is refused, no USERID or
+// domain fills this
+const MAX_REQUEST_SIZE: usize = 8 + 2 * 256;
+
+// the length of a socks4 or socks4a request at the start of buf, None until
+// its NUL terminators arrived, bytes after them are the client's payload
+pub(crate) fn request_len(buf: &[u8]) -> Option<usize> {
+    // let the parser refuse a request without its terminators in the limit
+    let refuse = (buf.len() >= MAX_REQUEST_SIZE).then_some(buf.len());
+    let [0x04, _, _, _, a, b, c, d, ref rest @ ..] = buf[..buf.len().min(MAX_REQUEST_SIZE)] else {
+        return refuse;
+    };
+    let Some(userid) = rest.iter().position(|&byte| byte == 0) else {
+        return refuse;
+    };
+    let userid = userid + 1;
+    if a == 0 && b == 0 && c == 0 && d != 0 {
+        let Some(domain) = rest[userid..].iter().position(|&byte| byte == 0) else {
+            return refuse;
+        };
+        Some(8 + userid + domain + 1)
+    } else {
+        Some(8 + userid)
+    }
+}
+
+// parse a socks4 or socks4a request, returns CD and the target, the
+// USERID is not used
+pub(crate) fn parse_request(buf: &[u8]) -> Option<(u8, Target)> {
+    if buf.len() > MAX_REQUEST_SIZE {
+        return None;
+    }
+    let [0x04, cd, p1, p2, a, b, c, d, ref rest @ ..] = buf[..] else {
+        return None;
+    };
+    let port = u16::from_be_bytes([p1, p2]);
+
+    // USERID, NUL terminated
+    let nul = rest.iter().position(|&byte| byte == 0)?;
+    let rest = &rest[nul + 1..];
+
+    let target = if a == 0 && b == 0 && c == 0 && d != 0 {
+        // socks4a, DSTIP 0.0.0.x is followed by the NUL terminated domain
+        let nul = rest.iter().position(|&byte| byte == 0)?;
+        if nul == 0 || nul + 1 != rest.len() {
+            return None;
+        }
+        let host = std::str::from_utf8(&rest[..nul]).ok()?.to_string();
+        Target::Domain { host, port }
+    } else {
+        if !rest.is_empty() {
+            return None;
+        }
+        Target::Ip(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(a, b, c, d)), port))
+    };
+    Some((cd, target))
+}
+
+// the 8 byte reply, socks4 only knows ipv4 so other addresses are zeroed
+pub(crate) fn reply(granted: bool, addr: SocketAddr) -> Vec<u8> {
+    let rep = if granted {
+        REPLY_GRANTED
+    } else {
+        REPLY_REJECTED
+    };
+    let mut buf = vec![0x00, rep];
+    match addr {
+        SocketAddr::V4(addr) => {
+            buf.extend_from_slice(&addr.port().to_be_bytes());
+            buf.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(_) => buf.extend_from_slice(&[0; 6]),
+    }
+    buf
+}
@@ -0,0 +1,345 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use byteorder::{BigEndian, ByteOrder};
+
+pub(crate) const CMD_CONNECT: u8 = 0x01;
+pub(crate) const CMD_BIND: u8 = 0x02;
+pub(crate) const CMD_UDP_ASSOCIATE: u8 = 0x03;
+// Tor's extension, resolving the domain without connecting
+pub(crate) const CMD_RESOLVE: u8 = 0xf0;
+
+pub(crate) const METHOD_NO_AUTH: u8 = 0x00;
+pub(crate) const METHOD_USERPASS: u8 = 0x02;
+pub(crate) const METHOD_NO_ACCEPTABLE: u8 = 0xff;
+
+// the full length of the message at the start of buf, None until enough of it
+// arrived to tell, the method selection is VER NMETHODS METHODS
+pub(crate) fn methods_len(buf: &[u8]) -> Option<usize> {
+    Some(2 + *buf.get(1)? as usize)
+}
+
+// VER ULEN UNAME PLEN PASSWD
+pub(crate) fn userpass_len(buf: &[u8]) -> Option<usize> {
+    let ulen = *buf.get(1)? as usize;
+    Some(3 + ulen + *buf.get(2 + ulen)? as usize)
+}
+
+// VER CMD RSV ATYP DST.ADDR DST.PORT, an unknown address type is complete
+// after ATYP so that it gets rejected right away
+pub(crate) fn request_len(buf: &[u8]) -> Option<usize> {
+    match *buf.get(3)? {
+        1 => Some(4 + 4 + 2),
+        3 => Some(4 + 1 + *buf.get(4)? as usize + 2),
+        4 => Some(4 + 16 + 2),
+        _ => Some(4),
+    }
+}
+
+// parse the method selection msg, returns the methods offered by the client
+pub(crate) fn parse_methods(buf: &[u8]) -> Option<&[u8]> {
+    if buf.first() != Some(&0x05) {
+        return None;
+    }
+    let nmethods = *buf.get(1)? as usize;
+    if 2 + nmethods != buf.len() {
+        return None;
+    }
+    Some(&buf[2..])
+}
+
+// pick the auth method, credentials are never optional once configured
+pub(crate) fn select_method(methods: &[u8], auth_required: bool) -> u8 {
+    let wanted = if auth_required {
+        METHOD_USERPASS
+    } else {
+        METHOD_NO_AUTH
+    };
+    if methods.contains(&wanted) {
+        wanted
+    } else {
+        METHOD_NO_ACCEPTABLE
+    }
+}
+
+// parse a RFC 1929 username/password request
+pub(crate) fn parse_userpass(buf: &[u8]) -> Option<(&str, &str)> {
+    if buf.first() != Some(&0x01) {
+        return None;
+    }
+    let ulen = *buf.get(1)? as usize;
+    let username = buf.get(2..2 + ulen)?;
+    let plen = *buf.get(2 + ulen)? as usize;
+    let password = buf.get(3 + ulen..3 + ulen + plen)?;
+    if 3 + ulen + plen != buf.len() {
+        return None;
+    }
+    Some((
+        std::str::from_utf8(username).ok()?,
+        std::str::from_utf8(password).ok()?,
+    ))
+}
+
+// encode a RFC 1929 username/password request, None when either is longer
+// than 255 bytes
+pub(crate) fn encode_userpass(username: &str, password: &str) -> Option<Vec<u8>> {
+    if username.len() > 255 || password.len() > 255 {
+        return None;
+    }
+    let mut buf = vec![0x01, username.len() as u8];
+    buf.extend_from_slice(username.as_bytes());
+    buf.push(password.len() as u8);
+    buf.extend_from_slice(password.as_bytes());
+    Some(buf)
+}
+
+// parse ATYP, DST.ADDR and DST.PORT at the start of buf, also returns how
+// many bytes were used
+pub(crate) fn parse_target(buf: &[u8]) -> Option<(Target, usize)> {
+    match *buf.first()? {
+        1 => {
+            // ipv4
+            let b = buf.get(1..7)?;
+            let dst_addr = IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3]));
+            let dst_port = BigEndian::read_u16(&b[4..]);
+            Some((Target::Ip(SocketAddr::new(dst_addr, dst_port)), 7))
+        }
+        3 => {
+            // domain
+            let offset = 1 + 1 + (*buf.get(1)? as usize);
+            let port = BigEndian::read_u16(buf.get(offset..offset + 2)?);
+            let host = std::str::from_utf8(&buf[2..offset]).ok()?.to_string();
+            Some((Target::Domain { host, port }, offset + 2))
+        }
+        4 => {
+            // ipv6
+            let b = buf.get(1..19)?;
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&b[..16]);
+            let dst_addr = IpAddr::V6(Ipv6Addr::from(octets));
+            let dst_port = BigEndian::read_u16(&b[16..]);
+            Some((Target::Ip(SocketAddr::new(dst_addr, dst_port)), 19))
+        }
+        _ => None,
+    }
+}
+
+/// Where a client asks to connect to, whichever protocol it speaks, displayed
+/// as `host:port` with IPv6 addresses in brackets. Parses back from that form.
+///
+/// ```
+/// use wssocks::Target;
+///
+/// let target: Target = "example.com:443".parse().unwrap();
+/// assert_eq!(target, Target::Domain { host: "example.com".into(), port: 443 });
+/// assert_eq!("[::1]:80".parse(), Ok(Target::Ip("[::1]:80".parse().unwrap())));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// An IP address, resolved by the client.
+    Ip(SocketAddr),
+    /// A domain left to the proxy to resolve.
+    Domain { host: String, port: u16 },
+}
+
+impl Target {
+    /// The port to connect to.
+    pub fn port(&self) -> u16 {
+        match self {
+            Target::Ip(addr) => addr.port(),
+            Target::Domain { port, .. } => *port,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Ip(addr) => addr.fmt(f),
+            Target::Domain { host, port } => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = ParseTargetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Target::Ip(addr));
+        }
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && !host.contains(':') => match port.parse() {
+                Ok(port) => Ok(Target::Domain {
+                    host: host.to_string(),
+                    port,
+                }),
+                Err(_) => Err(ParseTargetError(s.to_string())),
+            },
+            _ => Err(ParseTargetError(s.to_string())),
+        }
+    }
+}
+
+/// The error returned when a [`Target`] is not a `host:port` pair.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseTargetError(String);
+
+impl fmt::Display for ParseTargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid target `{}`, expected host:port", self.0)
+    }
+}
+
+impl std::error::Error for ParseTargetError {}
+
+/// A SOCKS5 request (RFC 1928): VER CMD RSV ATYP DST.ADDR DST.PORT.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Socks5Request {
+    /// `0x01` CONNECT, `0x02` BIND, `0x03` UDP ASSOCIATE or Tor's `0xF0`
+    /// RESOLVE.
+    pub cmd: u8,
+    pub target: Target,
+}
+
+/// Why [`parse_socks5_request`] rejected a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Socks5Error {
+    /// Shorter than the fixed four byte header.
+    TooShort,
+    /// VER is not 5.
+    BadVersion(u8),
+    /// CMD is none of the commands RFC 1928 defines.
+    UnsupportedCommand(u8),
+    /// ATYP is not an IPv4 address, domain or IPv6 address.
+    UnsupportedAddressType(u8),
+    /// The address is truncated, followed by extra bytes or a domain that is
+    /// not UTF-8.
+    MalformedAddress,
+}
+
+impl Socks5Error {
+    // the reply code telling the client, None where it gets no reply
+    pub(crate) fn reply(&self) -> Option<u8> {
+        match self {
+            Socks5Error::TooShort | Socks5Error::BadVersion(_) => None,
+            Socks5Error::UnsupportedCommand(_) => Some(0x07),
+            Socks5Error::UnsupportedAddressType(_) => Some(0x08),
+            Socks5Error::MalformedAddress => Some(0x01),
+        }
+    }
+}
+
+impl fmt::Display for Socks5Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Socks5Error::TooShort => f.write_str("request message too short"),
+            Socks5Error::BadVersion(ver) => write!(f, "unsupported socks version {}", ver),
+            Socks5Error::UnsupportedCommand(cmd) => write!(f, "unsupported command {}", cmd),
+            Socks5Error::UnsupportedAddressType(atyp) => {
+                write!(f, "unsupported address type {}", atyp)
+            }
+            Socks5Error::MalformedAddress => f.write_str("malformed target address"),
+        }
+    }
+}
+
+impl std::error::Error for Socks5Error {}
+
+/// Parses a whole SOCKS5 request message, checking its fields in order.
+///
+/// ```
+/// use wssocks::{parse_socks5_request, Target};
+///
+/// let request = parse_socks5_request(&[5, 1, 0, 1, 127, 0, 0, 1, 0, 80]).unwrap();
+/// assert_eq!(request.target, Target::Ip("127.0.0.1:80".parse().unwrap()));
+/// ```
+pub fn parse_socks5_request(buf: &[u8]) -> Result<Socks5Request, Socks5Error> {
+    let [ver, cmd, _, atyp, ..] = buf[..] else {
+        return Err(Socks5Error::TooShort);
+    };
+    if ver != 0x05 {
+        return Err(Socks5Error::BadVersion(ver));
+    }
+    if !matches!(
+        cmd,
+        CMD_CONNECT | CMD_BIND | CMD_UDP_ASSOCIATE | CMD_RESOLVE
+    ) {
+        return Err(Socks5Error::UnsupportedCommand(cmd));
+    }
+    if !matches!(atyp, 1 | 3 | 4) {
+        return Err(Socks5Error::UnsupportedAddressType(atyp));
+    }
+    match parse_target(&buf[3..]) {
+        Some((target, len)) if 3 + len == buf.len() => Ok(Socks5Request { cmd, target }),
+        _ => Err(Socks5Error::MalformedAddress),
+    }
+}
+
+// the io error matching a failed reply code, the inverse of connect_error_reply
+pub(crate) fn reply_error(rep: u8) -> std::io::Error {
+    let kind = match rep {
+        0x02 => std::io::ErrorKind::PermissionDenied,
+        0x03 => std::io::ErrorKind::NetworkUnreachable,
+        0x04 => std::io::ErrorKind::HostUnreachable,
+        0x05 => std::io::ErrorKind::ConnectionRefused,
+        0x06 => std::io::ErrorKind::TimedOut,
+        0x07 | 0x08 => std::io::ErrorKind::Unsupported,
+        _ => std::io::ErrorKind::Other,
+    };
+    std::io::Error::new(kind, format!("socks5 request fails, reply code is {}", rep))
+}
+
+// the socks5 reply code matching a failed connect
+pub(crate) fn connect_error_reply(e: &std::io::Error) -> u8 {
+    match e.kind() {
+        std::io::ErrorKind::PermissionDenied => 0x02,
+        std::io::ErrorKind::NetworkUnreachable => 0x03,
+        std::io::ErrorKind::HostUnreachable | std::io::ErrorKind::TimedOut => 0x04,
+        std::io::ErrorKind::ConnectionRefused => 0x05,
+        _ => 0x01,
+    }
+}
+
+// encode a socks5 reply carrying BND.ADDR and BND.PORT
+pub(crate) fn socks5_reply(rep: u8, addr: SocketAddr) -> Vec<u8> {
+    let mut buf = vec![0x05, rep, 0x00];
+    encode_address(&mut buf, addr);
+    buf
+}
+
+// append ATYP, ADDR and PORT for addr
+pub(crate) fn encode_address(buf: &mut Vec<u8>, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(0x01);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(0x04);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+// append ATYP, DST.ADDR and DST.PORT for a target, fails for a domain longer
+// than the 255 bytes socks5 has room for
+pub(crate) fn encode_target(buf: &mut Vec<u8>, target: &Target) -> Option<()> {
+    let (host, port) = match target {
+        Target::Ip(addr) => {
+            encode_address(buf, *addr);
+            return Some(());
+        }
+        Target::Domain { host, port } => (host, *port),
+    };
+    if host.is_empty() || host.len() > 255 {
+        return None;
+    }
+    buf.push(0x03);
+    buf.push(host.len() as u8);
+    buf.extend_from_slice(host.as_bytes());
+    buf.extend_from_slice(&port.to_be_bytes());
+    Some(())
+}
@@ -0,0 +1,310 @@
+use std::fmt::{self, Write as _};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::ConnectInfo;
+use hyper::service::{service_fn, Service};
+use hyper::{server::conn::Http, Body, Request};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tracing::debug;
+
+use crate::config::ProxyConfig;
+use crate::proxy_protocol::read_header;
+
+/// The certificate [`serve_tls`] presents, and the roots client certificates
+/// are verified against.
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// let tls = wssocks::ServerTls::new(vec![std::fs::read("server.der")?], std::fs::read("server.key")?)
+///     .client_root_certificate(std::fs::read("clients-ca.der")?);
+/// let config = wssocks::ProxyConfig::default().require_client_certificate(true);
+/// wssocks::serve_tls("0.0.0.0:443".parse().unwrap(), config, tls).await
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ServerTls {
+    cert_chain: Vec<Vec<u8>>,
+    key: Vec<u8>,
+    client_roots: Vec<Vec<u8>>,
+}
+
+impl ServerTls {
+    /// Presents the DER encoded certificate chain, the server's own first,
+    /// with its DER encoded PKCS#8, SEC1 or PKCS#1 private key. Clients are
+    /// not asked for a certificate until a root is trusted for them.
+    pub fn new(cert_chain: Vec<Vec<u8>>, key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            cert_chain,
+            key: key.into(),
+            client_roots: Vec::new(),
+        }
+    }
+
+    /// Asks clients for a certificate and trusts the DER encoded CA
+    /// certificate to have issued it. Can be called several times to trust
+    /// more than one. A client presenting a certificate no trusted root issued
+    /// fails the handshake with a TLS alert; one presenting none gets through,
+    /// see [`ProxyConfig::require_client_certificate`].
+    pub fn client_root_certificate(mut self, der: impl Into<Vec<u8>>) -> Self {
+        self.client_roots.push(der.into());
+        self
+    }
+
+    fn server_config(&self) -> std::io::Result<rustls::ServerConfig> {
+        let builder = rustls::ServerConfig::builder().with_safe_defaults();
+        let builder = if self.client_roots.is_empty() {
+            builder.with_no_client_auth()
+        } else {
+            let mut roots = rustls::RootCertStore::empty();
+            for der in &self.client_roots {
+                roots
+                    .add(&rustls::Certificate(der.clone()))
+                    .map_err(|e| invalid("client root certificate", e))?;
+            }
+            // a missing certificate is refused with 403 by the endpoints that
+            // require one, others such as the health check stay reachable
+            builder.with_client_cert_verifier(
+                rustls::server::AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+            )
+        };
+        let chain = self
+            .cert_chain
+            .iter()
+            .map(|der| rustls::Certificate(der.clone()))
+            .collect();
+        builder
+            .with_single_cert(chain, rustls::PrivateKey(self.key.clone()))
+            .map_err(|e| invalid("server certificate or key", e))
+    }
+}
+
+fn invalid(what: &str, e: impl fmt::Debug) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("invalid {}, detail error is {:?}", what, e),
+    )
+}
+
+// the subject of the verified certificate a client presented
+#[derive(Clone, Debug)]
+pub(crate) struct ClientSubject(pub(crate) String);
+
+/// Serves [`router`](crate::router) over TLS on `addr` until accepting
+/// fails, like [`serve`](crate::serve) does in plain text. The subject of a
+/// client certificate, as in `CN=alice,O=Example`, is handed to the hooks
+/// in [`TunnelRequest::client_subject`](crate::TunnelRequest::client_subject)
+/// and logged with the tunnel. A TLS handshake, after the PROXY header when
+/// [`ProxyConfig::accept_proxy_protocol`] is set, gets the handshake timeout.
+pub async fn serve_tls(
+    addr: SocketAddr,
+    config: ProxyConfig,
+    tls: ServerTls,
+) -> std::io::Result<()> {
+    let acceptor = TlsAcceptor::from(Arc::new(tls.server_config()?));
+    let proxied = config.accept_proxy_protocol;
+    let handshake_timeout = config.handshake_timeout;
+    let app = crate::router(config);
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (stream, from) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            // a client giving up before we accepted it is no reason to stop
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let accepted = timeout(handshake_timeout, accept(acceptor, stream, from, proxied));
+            let (stream, peer) = match accepted.await {
+                Ok(Ok(accepted)) => accepted,
+                Ok(Err(e)) => {
+                    debug!(%from, error = %e, "tls handshake failed");
+                    return;
+                }
+                Err(_) => {
+                    debug!(%from, "tls handshake timed out");
+                    return;
+                }
+            };
+            let subject = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|chain| chain.first())
+                .and_then(|cert| subject(&cert.0))
+                .map(ClientSubject);
+            // what into_make_service_with_connect_info would tell the
+            // handlers, and the certificate on top
+            let service = service_fn(move |mut request: Request<Body>| {
+                request.extensions_mut().insert(ConnectInfo(peer));
+                if let Some(subject) = &subject {
+                    request.extensions_mut().insert(subject.clone());
+                }
+                app.clone().call(request)
+            });
+            if let Err(e) = Http::new()
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await
+            {
+                debug!(%peer, error = %e, "tls connection failed");
+            }
+        });
+    }
+}
+
+// read the PROXY header if one is expected, then shake hands
+async fn accept(
+    acceptor: TlsAcceptor,
+    mut stream: TcpStream,
+    from: SocketAddr,
+    proxied: bool,
+) -> std::io::Result<(tokio_rustls::server::TlsStream<TcpStream>, SocketAddr)> {
+    let peer = match proxied {
+        true => read_header(&mut stream).await?.unwrap_or(from),
+        false => from,
+    };
+    Ok((acceptor.accept(stream).await?, peer))
+}
+
+// the subject of a DER encoded certificate in the RFC 4514 form, the last
+// relative name first; None when it does not parse
+fn subject(der: &[u8]) -> Option<String> {
+    let (_, cert, _) = read_tlv(der)?;
+    let (_, tbs, _) = read_tlv(cert)?;
+    let mut rest = tbs;
+    // the version is optional, then serial, signature, issuer and validity
+    if rest.first() == Some(&0xa0) {
+        rest = read_tlv(rest)?.2;
+    }
+    for _ in 0..4 {
+        rest = read_tlv(rest)?.2;
+    }
+    let (0x30, mut names, _) = read_tlv(rest)? else {
+        return None;
+    };
+
+    let mut rdns = Vec::new();
+    while !names.is_empty() {
+        let (0x31, mut set, next) = read_tlv(names)? else {
+            return None;
+        };
+        names = next;
+        let mut rdn = Vec::new();
+        while !set.is_empty() {
+            let (0x30, pair, next) = read_tlv(set)? else {
+                return None;
+            };
+            set = next;
+            let (0x06, oid, value) = read_tlv(pair)? else {
+                return None;
+            };
+            rdn.push(format!(
+                "{}={}",
+                attribute_name(oid),
+                attribute_value(value)?
+            ));
+        }
+        rdns.push(rdn.join("+"));
+    }
+    rdns.reverse();
+    Some(rdns.join(","))
+}
+
+// one DER tag, its content and what follows it
+fn read_tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let octets = usize::from(first & 0x7f);
+        if octets == 0 || octets > 4 || input.len() < octets {
+            return None;
+        }
+        let (len, rest) = input.split_at(octets);
+        input = rest;
+        len.iter().fold(0, |len, &b| len << 8 | usize::from(b))
+    };
+    if input.len() < len {
+        return None;
+    }
+    let (content, rest) = input.split_at(len);
+    Some((tag, content, rest))
+}
+
+// the short name RFC 4514 gives the attribute, or its dotted OID
+fn attribute_name(oid: &[u8]) -> String {
+    let name = match oid {
+        [0x55, 0x04, 0x03] => "CN",
+        [0x55, 0x04, 0x06] => "C",
+        [0x55, 0x04, 0x07] => "L",
+        [0x55, 0x04, 0x08] => "ST",
+        [0x55, 0x04, 0x09] => "STREET",
+        [0x55, 0x04, 0x0a] => "O",
+        [0x55, 0x04, 0x0b] => "OU",
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x01] => "UID",
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x19] => "DC",
+        _ => {
+            let mut arcs = Vec::new();
+            let mut arc = 0u64;
+            for &b in oid {
+                arc = arc << 7 | u64::from(b & 0x7f);
+                if b & 0x80 == 0 {
+                    arcs.push(arc);
+                    arc = 0;
+                }
+            }
+            // the first one packs the two top arcs
+            let mut dotted = match arcs.first() {
+                Some(&first) if first < 80 => format!("{}.{}", first / 40, first % 40),
+                Some(&first) => format!("2.{}", first - 80),
+                None => String::new(),
+            };
+            for arc in arcs.iter().skip(1) {
+                let _ = write!(dotted, ".{}", arc);
+            }
+            return dotted;
+        }
+    };
+    name.to_string()
+}
+
+// strings escaped as RFC 4514 asks, anything else as #hex of its encoding
+fn attribute_value(encoded: &[u8]) -> Option<String> {
+    let (tag, value, rest) = read_tlv(encoded)?;
+    // UTF8String, PrintableString, TeletexString and IA5String
+    let text = match tag {
+        0x0c | 0x13 | 0x14 | 0x16 => std::str::from_utf8(value).ok(),
+        _ => None,
+    };
+    let Some(text) = text else {
+        let mut hex = String::from("#");
+        for b in &encoded[..encoded.len() - rest.len()] {
+            let _ = write!(hex, "{:02x}", b);
+        }
+        return Some(hex);
+    };
+
+    let last = text.chars().count().saturating_sub(1);
+    let mut escaped = String::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        let edge = (i == 0 && (c == ' ' || c == '#')) || (i == last && c == ' ');
+        if edge || matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    Some(escaped)
+}
@@ -0,0 +1,128 @@
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::connector::{AsyncReadWrite, OutboundConnector};
+use crate::socks5::{
+    encode_target, encode_userpass, reply_error, Target, CMD_CONNECT, METHOD_NO_AUTH,
+    METHOD_USERPASS,
+};
+
+/// An [`OutboundConnector`] reaching targets through another SOCKS5 proxy, for
+/// chains like wssocks -> corporate proxy -> internet.
+///
+/// Targets are passed on as the client named them, so domains are resolved by
+/// the upstream proxy. A failure it reports is answered with the same reply
+/// code.
+///
+/// ```no_run
+/// let config = wssocks::ProxyConfig::default()
+///     .connector(wssocks::UpstreamSocks5Connector::new("10.0.0.1:1080"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct UpstreamSocks5Connector {
+    proxy_addr: String,
+    credentials: Option<(String, String)>,
+}
+
+impl UpstreamSocks5Connector {
+    /// Connects through the SOCKS5 proxy at `proxy_addr`, a `host:port` pair.
+    pub fn new(proxy_addr: impl Into<String>) -> Self {
+        Self {
+            proxy_addr: proxy_addr.into(),
+            credentials: None,
+        }
+    }
+
+    /// Logs in to the upstream proxy with this username and password
+    /// (RFC 1929). No authentication is offered by default.
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+}
+
+#[async_trait]
+impl OutboundConnector for UpstreamSocks5Connector {
+    async fn connect(&self, target: &Target) -> std::io::Result<Box<dyn AsyncReadWrite>> {
+        let mut request = vec![0x05, CMD_CONNECT, 0x00];
+        if encode_target(&mut request, target).is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "invalid target {}, the domain is too long for socks5",
+                    target
+                ),
+            ));
+        }
+
+        // not reaching the upstream is a general failure, not the target's
+        let mut stream = TcpStream::connect(&self.proxy_addr).await.map_err(|e| {
+            std::io::Error::other(format!(
+                "upstream proxy connect fails, detail error is {:?}",
+                e
+            ))
+        })?;
+        stream.set_nodelay(true)?;
+
+        // offer the one method we can do
+        let method = match self.credentials {
+            Some(_) => METHOD_USERPASS,
+            None => METHOD_NO_AUTH,
+        };
+        stream.write_all(&[0x05, 0x01, method]).await?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply != [0x05, method] {
+            return Err(std::io::Error::other(
+                "upstream proxy accepts no offered auth method",
+            ));
+        }
+
+        if let Some((username, password)) = &self.credentials {
+            // username/password sub-negotiation
+            let auth = match encode_userpass(username, password) {
+                Some(auth) => auth,
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "username and password must be at most 255 bytes",
+                    ))
+                }
+            };
+            stream.write_all(&auth).await?;
+            stream.read_exact(&mut reply).await?;
+            if reply != [0x01, 0x00] {
+                return Err(std::io::Error::other(
+                    "upstream proxy username/password authentication failed",
+                ));
+            }
+        }
+
+        // VER REP RSV ATYP, then BND.ADDR and BND.PORT, which we skip
+        stream.write_all(&request).await?;
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await?;
+        let addr_len = match head {
+            [0x05, 0x00, _, 0x01] => 4,
+            [0x05, 0x00, _, 0x04] => 16,
+            [0x05, 0x00, _, 0x03] => stream.read_u8().await? as usize,
+            [0x05, rep, ..] if rep != 0x00 => return Err(reply_error(rep)),
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "malformed socks5 reply",
+                ))
+            }
+        };
+        let mut bound = vec![0u8; addr_len + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(Box::new(stream))
+    }
+
+    fn resolves_remotely(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,150 @@
+#![cfg(feature = "admin")]
+
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use common::{spawn_echo, spawn_proxy};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use wssocks::{ProxyConfig, Shutdown, WsSocksClient, CLOSE_KILLED};
+
+fn admin_config() -> ProxyConfig {
+    ProxyConfig::default()
+        .block_private_addresses(false)
+        .admin_path(Some("/admin/tunnels".to_string()))
+        .admin_token(Some("admin-secret".to_string()))
+}
+
+// GET the admin endpoint with this bearer token, the status and body
+async fn get_tunnels(addr: SocketAddr, token: Option<&str>) -> (u16, String) {
+    admin_request(addr, "GET", "/admin/tunnels", token).await
+}
+
+async fn admin_request(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let auth = token
+        .map(|token| format!("Authorization: Bearer {token}\r\n"))
+        .unwrap_or_default();
+    let request = format!("{method} {path} HTTP/1.0\r\nHost: {addr}\r\n{auth}\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split("\r\n\r\n").nth(1).unwrap().to_string();
+    (status, body)
+}
+
+async fn listed(addr: SocketAddr) -> Vec<serde_json::Value> {
+    let (status, body) = get_tunnels(addr, Some("admin-secret")).await;
+    assert_eq!(status, 200);
+    serde_json::from_str(&body).unwrap()
+}
+
+// the tunnels once the list settled on n of them
+async fn wait_for(addr: SocketAddr, n: usize) -> Vec<serde_json::Value> {
+    for _ in 0..100 {
+        let tunnels = listed(addr).await;
+        if tunnels.len() == n {
+            return tunnels;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("never {n} tunnels listed");
+}
+
+#[tokio::test]
+async fn admin_endpoint_needs_the_token() {
+    let addr = spawn_proxy(admin_config());
+    assert_eq!(get_tunnels(addr, None).await.0, 401);
+    assert_eq!(get_tunnels(addr, Some("wrong")).await.0, 401);
+    assert_eq!(
+        get_tunnels(addr, Some("admin-secret")).await,
+        (200, "[]".to_string())
+    );
+
+    // without an admin token nobody gets in
+    let config = ProxyConfig::default().admin_path(Some("/admin/tunnels".to_string()));
+    let addr = spawn_proxy(config);
+    assert_eq!(get_tunnels(addr, None).await.0, 401);
+}
+
+#[tokio::test]
+async fn active_tunnels_are_listed_until_they_close() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(admin_config());
+    let client = WsSocksClient::new(format!("ws://{addr}/ws"));
+
+    let mut stream = client.connect(&echo.to_string()).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    let mut echoed = [0u8; 5];
+    stream.read_exact(&mut echoed).await.unwrap();
+
+    let tunnels = wait_for(addr, 1).await;
+    let tunnel = &tunnels[0];
+    assert_eq!(tunnel["conn_id"].to_string(), stream.conn_id().unwrap());
+    assert_eq!(tunnel["target"], echo.to_string());
+    assert_eq!(tunnel["bytes_up"], 5);
+    assert_eq!(tunnel["bytes_down"], 5);
+    assert!(tunnel["age_secs"].as_f64().unwrap() >= 0.0);
+
+    drop(stream);
+    wait_for(addr, 0).await;
+}
+
+#[tokio::test]
+async fn tunnels_ended_by_shutdown_are_unlisted() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let shutdown = Shutdown::new();
+    let addr = spawn_proxy(admin_config().shutdown(shutdown.clone()));
+    let client = WsSocksClient::new(format!("ws://{addr}/ws"));
+
+    let _stream = client.connect(&echo.to_string()).await.unwrap();
+    wait_for(addr, 1).await;
+    shutdown.drain(Duration::ZERO).await;
+    wait_for(addr, 0).await;
+}
+
+#[tokio::test]
+async fn killed_tunnels_close_with_their_code() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(admin_config());
+    let client = WsSocksClient::new(format!("ws://{addr}/ws"));
+    let mut stream = client.connect(&echo.to_string()).await.unwrap();
+    wait_for(addr, 1).await;
+    let path = format!("/admin/tunnels/{}", stream.conn_id().unwrap());
+
+    assert_eq!(admin_request(addr, "DELETE", &path, None).await.0, 401);
+    assert_eq!(
+        admin_request(
+            addr,
+            "DELETE",
+            "/admin/tunnels/12345678",
+            Some("admin-secret")
+        )
+        .await
+        .0,
+        404
+    );
+    assert_eq!(
+        admin_request(addr, "DELETE", &path, Some("admin-secret"))
+            .await
+            .0,
+        204
+    );
+
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(
+        stream.peer_close().map(|(code, _)| code),
+        Some(CLOSE_KILLED)
+    );
+    wait_for(addr, 0).await;
+}
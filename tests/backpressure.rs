@@ -0,0 +1,93 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::spawn_proxy;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use wssocks::{ProxyConfig, WsSocksClient};
+
+// what a writer would send if nothing held it back
+const FLOOD: u64 = 256 * 1024 * 1024;
+
+// the socket buffers on the way may fill up, autotuning takes them to a few
+// MiB each, but nothing past them may pile up in the proxy
+const BOUND: u64 = 48 * 1024 * 1024;
+
+// writes FLOOD bytes as fast as it is let, counting them
+fn flood<W: AsyncWrite + Unpin + Send + 'static>(mut writer: W) -> Arc<AtomicU64> {
+    let written = Arc::new(AtomicU64::new(0));
+    let counter = written.clone();
+    tokio::spawn(async move {
+        let chunk = vec![0u8; 64 * 1024];
+        while counter.load(Ordering::Relaxed) < FLOOD {
+            if writer.write_all(&chunk).await.is_err() {
+                return;
+            }
+            counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+    });
+    written
+}
+
+// the count once it stopped growing, as the writer waits for the reader
+async fn stalled(written: &AtomicU64) -> u64 {
+    let mut last = u64::MAX;
+    loop {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let now = written.load(Ordering::Relaxed);
+        if now == last {
+            return now;
+        }
+        last = now;
+    }
+}
+
+// reading some of it lets the writer carry on
+async fn resumes<R: AsyncRead + Unpin>(reader: &mut R, written: &AtomicU64, stalled: u64) {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut read = 0;
+    while read < stalled {
+        let n = reader.read(&mut buf).await.unwrap();
+        assert!(n > 0, "tunnel closed early");
+        read += n as u64;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(written.load(Ordering::Relaxed) > stalled);
+}
+
+async fn spawn_target() -> (SocketAddr, TcpListener) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    (listener.local_addr().unwrap(), listener)
+}
+
+#[tokio::test]
+async fn slow_clients_hold_back_the_target() {
+    let (target, listener) = spawn_target().await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let client = WsSocksClient::new(format!("ws://{addr}/ws"));
+    let mut stream = client.connect(&target.to_string()).await.unwrap();
+    let (outbound, _) = listener.accept().await.unwrap();
+
+    let written = flood(outbound);
+    let stalled = stalled(&written).await;
+    assert!(stalled < BOUND, "{} bytes taken from the target", stalled);
+    resumes(&mut stream, &written, stalled).await;
+}
+
+#[tokio::test]
+async fn slow_targets_hold_back_the_client() {
+    let (target, listener) = spawn_target().await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let client = WsSocksClient::new(format!("ws://{addr}/ws"));
+    let stream = client.connect(&target.to_string()).await.unwrap();
+    let (mut outbound, _) = listener.accept().await.unwrap();
+
+    let written = flood(stream);
+    let stalled = stalled(&written).await;
+    assert!(stalled < BOUND, "{} bytes taken from the client", stalled);
+    resumes(&mut outbound, &written, stalled).await;
+}
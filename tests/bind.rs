@@ -0,0 +1,48 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use common::{connect, ip_address, recv, send, socks5_connect, spawn_proxy};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use wssocks::ProxyConfig;
+
+// BND.ADDR and BND.PORT of an ipv4 reply
+fn bound_v4(reply: &[u8]) -> SocketAddr {
+    assert_eq!(reply[..4], [0x05, 0x00, 0x00, 0x01]);
+    let ip = [reply[4], reply[5], reply[6], reply[7]];
+    SocketAddr::from((ip, u16::from_be_bytes([reply[8], reply[9]])))
+}
+
+#[tokio::test]
+async fn bind_is_refused_by_default() {
+    let addr = spawn_proxy(ProxyConfig::default());
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x02, &ip_address("0.0.0.0:0".parse().unwrap())).await;
+    assert_eq!(reply[..2], [0x05, 0x07]);
+}
+
+#[tokio::test]
+async fn bind_relays_the_inbound_connection() {
+    let config = ProxyConfig::default()
+        .block_private_addresses(false)
+        .bind_command(true);
+    let addr = spawn_proxy(config);
+    let mut ws = connect(addr).await;
+
+    let expected = ip_address("127.0.0.1:0".parse().unwrap());
+    let bound = bound_v4(&socks5_connect(&mut ws, 0x02, &expected).await);
+    assert_eq!(bound.ip().to_string(), "127.0.0.1");
+
+    let mut peer = TcpStream::connect(bound).await.unwrap();
+    let from = bound_v4(&recv(&mut ws).await.unwrap());
+    assert_eq!(from, peer.local_addr().unwrap());
+
+    peer.write_all(b"from peer").await.unwrap();
+    assert_eq!(recv(&mut ws).await, Some(b"from peer".to_vec()));
+    send(&mut ws, b"from client").await;
+    let mut buf = [0u8; 11];
+    peer.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"from client");
+}
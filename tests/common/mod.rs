@@ -0,0 +1,139 @@
+// each test crate uses its own share of these helpers
+#![allow(dead_code)]
+
+use std::net::{SocketAddr, TcpListener};
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use wssocks::ProxyConfig;
+
+// a server on ip writing back whatever it reads
+pub async fn spawn_echo(ip: &str) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind((ip, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    addr
+}
+
+pub type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// serve the proxy on an ephemeral loopback port
+pub fn spawn_proxy(config: ProxyConfig) -> SocketAddr {
+    spawn_router(wssocks::router(config))
+}
+
+pub fn spawn_router(router: axum::Router) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(router.into_make_service());
+    tokio::spawn(server);
+    addr
+}
+
+// serve the proxy with connect info, so it knows the client's address
+pub fn spawn_proxy_with_connect_info(config: ProxyConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(wssocks::router(config).into_make_service_with_connect_info::<SocketAddr>());
+    tokio::spawn(server);
+    addr
+}
+
+// the local address of the client's side of the websocket
+pub fn local_addr(ws: &Client) -> SocketAddr {
+    match ws.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream.local_addr().unwrap(),
+        _ => unreachable!("tests connect without tls"),
+    }
+}
+
+pub async fn connect(addr: SocketAddr) -> Client {
+    connect_path(addr, "/ws").await
+}
+
+pub async fn connect_path(addr: SocketAddr, path: &str) -> Client {
+    let (ws, _) = tokio_tungstenite::connect_async(format!("ws://{addr}{path}"))
+        .await
+        .unwrap();
+    ws
+}
+
+pub async fn send(ws: &mut Client, data: &[u8]) {
+    ws.send(Message::Binary(data.to_vec())).await.unwrap();
+}
+
+// the next binary frame, None once the server hung up
+pub async fn recv(ws: &mut Client) -> Option<Vec<u8>> {
+    loop {
+        match ws.next().await {
+            Some(Ok(Message::Binary(data))) => return Some(data),
+            Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+            _ => return None,
+        }
+    }
+}
+
+// the code of the close frame that ends the websocket, skipping data and
+// keepalives, None when it ends without one
+pub async fn close_code(ws: &mut Client) -> Option<u16> {
+    loop {
+        match ws.next().await {
+            Some(Ok(Message::Close(frame))) => return frame.map(|frame| frame.code.into()),
+            Some(Ok(_)) => continue,
+            _ => return None,
+        }
+    }
+}
+
+// negotiate no auth and send a connect request for the encoded address,
+// returns the reply
+pub async fn socks5_connect(ws: &mut Client, cmd: u8, address: &[u8]) -> Vec<u8> {
+    send(ws, &[0x05, 0x01, 0x00]).await;
+    assert_eq!(recv(ws).await, Some(vec![0x05, 0x00]));
+    let mut request = vec![0x05, cmd, 0x00];
+    request.extend_from_slice(address);
+    send(ws, &request).await;
+    recv(ws).await.expect("no reply")
+}
+
+// ATYP DST.ADDR DST.PORT of an ip address
+pub fn ip_address(addr: SocketAddr) -> Vec<u8> {
+    let mut buf = match addr {
+        SocketAddr::V4(v4) => [&[0x01][..], &v4.ip().octets()].concat(),
+        SocketAddr::V6(v6) => [&[0x04][..], &v6.ip().octets()].concat(),
+    };
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+    buf
+}
+
+// ATYP DST.ADDR DST.PORT of a domain
+pub fn domain_address(host: &str, port: u16) -> Vec<u8> {
+    let mut buf = vec![0x03, host.len() as u8];
+    buf.extend_from_slice(host.as_bytes());
+    buf.extend_from_slice(&port.to_be_bytes());
+    buf
+}
+
+// a plain http/1.0 GET, returning the whole response
+pub async fn http_get(addr: SocketAddr, path: &str) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {path} HTTP/1.0\r\nHost: {addr}\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "compression")]
+
+mod common;
+
+use common::{spawn_echo, spawn_proxy};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use wssocks::{ProxyConfig, WsSocksClient};
+
+fn config() -> ProxyConfig {
+    ProxyConfig::default()
+        .block_private_addresses(false)
+        .compression(true)
+}
+
+#[tokio::test]
+async fn compressed_tunnels_round_trip() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(config());
+    let client = WsSocksClient::new(format!("ws://{addr}/ws")).compression(true);
+
+    let mut stream = client.connect(&echo.to_string()).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    let mut echoed = [0u8; 5];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"hello");
+}
+
+#[tokio::test]
+async fn local_proxy_relays_plain_bytes_with_compression_on() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(config());
+    let local = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let local_addr = local.local_addr().unwrap();
+    drop(local);
+    let client = WsSocksClient::new(format!("ws://{addr}/ws")).compression(true);
+    tokio::spawn(client.run_local_proxy(local_addr));
+
+    let mut stream = loop {
+        match TcpStream::connect(local_addr).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::task::yield_now().await,
+        }
+    };
+    stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut selected = [0u8; 2];
+    stream.read_exact(&mut selected).await.unwrap();
+    assert_eq!(selected, [0x05, 0x00]);
+    let port = echo.port().to_be_bytes();
+    let request = [0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, port[0], port[1]];
+    stream.write_all(&request).await.unwrap();
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[..2], [0x05, 0x00]);
+
+    // deflated frames would come back as anything but the bytes sent
+    stream.write_all(b"hello hello hello").await.unwrap();
+    let mut echoed = [0u8; 17];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"hello hello hello");
+}
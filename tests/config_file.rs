@@ -0,0 +1,59 @@
+#![cfg(feature = "config-file")]
+
+use std::path::PathBuf;
+
+use wssocks::{ConfigError, ProxyConfig};
+
+// write contents to a file of this name in a fresh temp dir
+fn file(name: &str, contents: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wssocks-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn toml_file_loads() {
+    let path = file(
+        "proxy.toml",
+        r#"
+            ws_path = "/tunnel"
+            credentials = { alice = "secret" }
+            allow = ["example.com", "10.0.0.0/8"]
+            blocked_ports = ["25", "8000-9000"]
+            idle_timeout = 300
+            address_family = "prefer_ipv4"
+            rate_limit = { per_minute = 60, burst = 10 }
+        "#,
+    );
+    let config = ProxyConfig::from_file(&path).unwrap();
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+fn json_file_loads() {
+    let path = file(
+        "proxy.json",
+        r#"{"ws_path": "/tunnel", "connect_timeout": 2.5}"#,
+    );
+    assert!(ProxyConfig::from_file(path).is_ok());
+}
+
+#[test]
+fn bad_rules_point_at_the_entry() {
+    let path = file("rules.toml", r#"allow = ["10.0.0.0/33"]"#);
+    let Err(ConfigError::File(message)) = ProxyConfig::from_file(&path) else {
+        panic!("rule accepted");
+    };
+    assert!(message.contains("10.0.0.0/33"), "{message}");
+}
+
+#[test]
+fn unknown_keys_are_refused() {
+    let path = file("typo.toml", "idle_timeuot = 30");
+    let Err(ConfigError::File(message)) = ProxyConfig::from_file(&path) else {
+        panic!("typo accepted");
+    };
+    assert!(message.contains("idle_timeuot"), "{message}");
+}
@@ -0,0 +1,485 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{
+    close_code, connect, connect_path, domain_address, ip_address, recv, send, socks5_connect,
+    spawn_echo, spawn_proxy, spawn_router,
+};
+use wssocks::{ProxyConfig, RateLimit, TargetRule, CLOSE_IDLE_TIMEOUT};
+
+fn config() -> ProxyConfig {
+    ProxyConfig::default()
+        .block_private_addresses(false)
+        .connect_timeout(Duration::from_secs(2))
+}
+
+async fn assert_round_trip(address: &[u8]) {
+    let addr = spawn_proxy(config());
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, address).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+
+    send(&mut ws, b"hello").await;
+    assert_eq!(recv(&mut ws).await, Some(b"hello".to_vec()));
+}
+
+#[tokio::test]
+async fn connects_to_ipv4_target() {
+    let echo = spawn_echo("127.0.0.1").await;
+    assert_round_trip(&ip_address(echo)).await;
+}
+
+#[tokio::test]
+async fn connects_to_ipv6_target() {
+    let echo = spawn_echo("::1").await;
+    assert_round_trip(&ip_address(echo)).await;
+}
+
+#[tokio::test]
+async fn connects_to_domain_target() {
+    let echo = spawn_echo("127.0.0.1").await;
+    assert_round_trip(&domain_address("localhost", echo.port())).await;
+}
+
+#[tokio::test]
+async fn unsupported_command_is_refused() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(config());
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x09, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x07]);
+    assert_eq!(recv(&mut ws).await, None);
+}
+
+#[tokio::test]
+async fn refused_target_is_reported() {
+    let addr = spawn_proxy(config());
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address("127.0.0.1:1".parse().unwrap())).await;
+    assert_eq!(reply[..2], [0x05, 0x05]);
+    assert_eq!(close_code(&mut ws).await, Some(1011));
+}
+
+#[tokio::test]
+async fn unresolvable_host_is_unreachable() {
+    let addr = spawn_proxy(config());
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("wssocks.invalid", 80)).await;
+    assert_eq!(reply[..2], [0x05, 0x04]);
+}
+
+#[tokio::test]
+async fn smtp_ports_are_blocked_by_default() {
+    let addr = spawn_proxy(config());
+    let mut ws = connect(addr).await;
+
+    // refused before resolving, so the host never has to exist
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("wssocks.invalid", 25)).await;
+    assert_eq!(reply[..2], [0x05, 0x02]);
+    assert_eq!(close_code(&mut ws).await, Some(1008));
+}
+
+#[tokio::test]
+async fn ports_outside_the_allowed_ranges_are_refused() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let port = echo.port();
+    let other = if port == u16::MAX { port - 1 } else { port + 1 };
+    let addr = spawn_proxy(config().allowed_ports([other..=other]));
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x02]);
+}
+
+// the v4-mapped v6 form of a v4 address
+fn mapped(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(v4) => SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port()),
+        v6 => v6,
+    }
+}
+
+#[tokio::test]
+async fn v4_rules_match_mapped_targets() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let loopback: TargetRule = "127.0.0.0/8".parse().unwrap();
+
+    let addr = spawn_proxy(config().deny([loopback.clone()]));
+    let mut ws = connect(addr).await;
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(mapped(echo))).await;
+    assert_eq!(reply[..2], [0x05, 0x02]);
+
+    let addr = spawn_proxy(config().allow([loopback]));
+    let mut ws = connect(addr).await;
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(mapped(echo))).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, b"hello").await;
+    assert_eq!(recv(&mut ws).await, Some(b"hello".to_vec()));
+}
+
+#[tokio::test]
+async fn mapped_clients_are_known_by_their_v4_address() {
+    let seen = Arc::new(Mutex::new(None));
+    let keyed = seen.clone();
+    let limit = RateLimit::new(60, 10).key_by(move |peer, _| {
+        *keyed.lock().unwrap() = peer.map(|peer| peer.ip());
+        None
+    });
+    // v4 clients of a dual-stack listener are accepted v4-mapped
+    let listener = std::net::TcpListener::bind("[::]:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = axum::Server::from_tcp(listener).unwrap().serve(
+        wssocks::router(config().rate_limit(Some(limit)))
+            .into_make_service_with_connect_info::<SocketAddr>(),
+    );
+    tokio::spawn(server);
+
+    connect(SocketAddr::from(([127, 0, 0, 1], port))).await;
+    assert_eq!(*seen.lock().unwrap(), Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+}
+
+#[tokio::test]
+async fn each_endpoint_applies_its_own_config() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let internal = config().ws_path("/internal");
+    let app = wssocks::router(ProxyConfig::default()).merge(wssocks::tunnel_router(internal));
+    let addr = spawn_router(app);
+
+    // the default config refuses loopback targets
+    let mut ws = connect(addr).await;
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x02]);
+
+    let mut ws = connect_path(addr, "/internal").await;
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+}
+
+#[tokio::test]
+async fn small_buffers_relay_whole_frames() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(config().buffer_size(3));
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, b"hello world").await;
+    let mut echoed = Vec::new();
+    while echoed.len() < 11 {
+        echoed.extend(recv(&mut ws).await.unwrap());
+    }
+    assert_eq!(echoed, b"hello world");
+}
+
+#[tokio::test]
+async fn connector_gets_the_typed_target() {
+    use std::sync::{Arc, Mutex};
+    use wssocks::{AsyncReadWrite, OutboundConnector, Target};
+
+    struct Recording(std::net::SocketAddr, Arc<Mutex<Vec<Target>>>);
+
+    #[async_trait::async_trait]
+    impl OutboundConnector for Recording {
+        async fn connect(&self, target: &Target) -> std::io::Result<Box<dyn AsyncReadWrite>> {
+            self.1.lock().unwrap().push(target.clone());
+            let stream = tokio::net::TcpStream::connect(self.0).await?;
+            Ok(Box::new(stream))
+        }
+    }
+
+    let echo = spawn_echo("127.0.0.1").await;
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let addr = spawn_proxy(config().connector(Recording(echo, seen.clone())));
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("localhost", echo.port())).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, b"ping").await;
+    assert_eq!(recv(&mut ws).await, Some(b"ping".to_vec()));
+    let target = Target::Domain {
+        host: "localhost".to_string(),
+        port: echo.port(),
+    };
+    assert_eq!(*seen.lock().unwrap(), [target]);
+}
+
+#[tokio::test]
+async fn connector_domains_are_not_resolved_locally() {
+    use wssocks::{AsyncReadWrite, OutboundConnector, Target};
+
+    // an upstream knowing a name the system resolver does not
+    struct Upstream(std::net::SocketAddr);
+
+    #[async_trait::async_trait]
+    impl OutboundConnector for Upstream {
+        async fn connect(&self, target: &Target) -> std::io::Result<Box<dyn AsyncReadWrite>> {
+            match target {
+                Target::Domain { host, .. } if host == "upstream.test" => {
+                    Ok(Box::new(tokio::net::TcpStream::connect(self.0).await?))
+                }
+                _ => Err(std::io::ErrorKind::NotFound.into()),
+            }
+        }
+
+        fn resolves_remotely(&self) -> bool {
+            true
+        }
+    }
+
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(config().connector(Upstream(echo)));
+    let mut ws = connect(addr).await;
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("upstream.test", 80)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, b"ping").await;
+    assert_eq!(recv(&mut ws).await, Some(b"ping".to_vec()));
+
+    // still matched against the rules naming it
+    let denied: TargetRule = "upstream.test".parse().unwrap();
+    let addr = spawn_proxy(config().connector(Upstream(echo)).deny([denied]));
+    let mut ws = connect(addr).await;
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("upstream.test", 80)).await;
+    assert_eq!(reply[..2], [0x05, 0x02]);
+}
+
+#[tokio::test]
+async fn connector_domains_are_resolved_and_checked_locally_by_default() {
+    use wssocks::{AsyncReadWrite, OutboundConnector, Target};
+
+    // dials whatever it is handed, as a pool over direct connects would
+    struct Direct;
+
+    #[async_trait::async_trait]
+    impl OutboundConnector for Direct {
+        async fn connect(&self, target: &Target) -> std::io::Result<Box<dyn AsyncReadWrite>> {
+            Ok(Box::new(
+                tokio::net::TcpStream::connect(target.to_string()).await?,
+            ))
+        }
+    }
+
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(ProxyConfig::default().connector(Direct));
+    let mut ws = connect(addr).await;
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("localhost", echo.port())).await;
+    assert_eq!(reply[..2], [0x05, 0x02]);
+
+    let loopback = ["127.0.0.0/8", "::1/128"].map(|rule| rule.parse::<TargetRule>().unwrap());
+    let addr = spawn_proxy(config().connector(Direct).deny(loopback));
+    let mut ws = connect(addr).await;
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("localhost", echo.port())).await;
+    assert_eq!(reply[..2], [0x05, 0x02]);
+}
+
+#[tokio::test]
+async fn idle_tunnels_close_with_their_own_code() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(config().idle_timeout(Some(Duration::from_millis(200))));
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    assert_eq!(close_code(&mut ws).await, Some(CLOSE_IDLE_TIMEOUT));
+}
+
+#[tokio::test]
+async fn domains_go_through_the_configured_resolver() {
+    use wssocks::Resolver;
+
+    // knows one name only, which the system resolver does not
+    struct Static(std::net::SocketAddr);
+
+    #[async_trait::async_trait]
+    impl Resolver for Static {
+        async fn resolve(
+            &self,
+            host: &str,
+            port: u16,
+        ) -> std::io::Result<Vec<std::net::SocketAddr>> {
+            match host {
+                "echo.test" => Ok(vec![std::net::SocketAddr::new(self.0.ip(), port)]),
+                _ => Err(std::io::ErrorKind::NotFound.into()),
+            }
+        }
+    }
+
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(config().resolver(Static(echo)));
+    let mut ws = connect(addr).await;
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("echo.test", echo.port())).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, b"ping").await;
+    assert_eq!(recv(&mut ws).await, Some(b"ping".to_vec()));
+
+    let mut ws = connect(addr).await;
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("localhost", echo.port())).await;
+    assert_eq!(reply[..2], [0x05, 0x04]);
+}
+
+#[tokio::test]
+async fn cached_resolutions_are_still_checked() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use wssocks::{CachingResolver, Resolver};
+
+    // resolves everything to the echo server, counting lookups
+    struct Counting(std::net::SocketAddr, Arc<AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl Resolver for Counting {
+        async fn resolve(
+            &self,
+            _host: &str,
+            port: u16,
+        ) -> std::io::Result<Vec<std::net::SocketAddr>> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![std::net::SocketAddr::new(self.0.ip(), port)])
+        }
+    }
+
+    let echo = spawn_echo("127.0.0.1").await;
+    for (block_private, reply) in [(false, 0x00), (true, 0x02)] {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let resolver = CachingResolver::new(Counting(echo, lookups.clone()));
+        let addr = spawn_proxy(
+            config()
+                .block_private_addresses(block_private)
+                .resolver(resolver),
+        );
+        for host in ["echo.test", "ECHO.test"] {
+            let mut ws = connect(addr).await;
+            let got = socks5_connect(&mut ws, 0x01, &domain_address(host, echo.port())).await;
+            assert_eq!(got[..2], [0x05, reply]);
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[tokio::test]
+async fn caches_keep_neither_empty_answers_nor_huge_ttls() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use wssocks::{CachingResolver, Resolver};
+
+    // no address for one name, one for a day for the other, counting lookups
+    struct Bogus(Arc<AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl Resolver for Bogus {
+        async fn resolve(
+            &self,
+            host: &str,
+            port: u16,
+        ) -> std::io::Result<Vec<std::net::SocketAddr>> {
+            Ok(self.resolve_with_ttl(host, port).await?.0)
+        }
+
+        async fn resolve_with_ttl(
+            &self,
+            host: &str,
+            port: u16,
+        ) -> std::io::Result<(Vec<std::net::SocketAddr>, Option<Duration>)> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            let addrs = match host {
+                "empty.test" => Vec::new(),
+                _ => vec![(Ipv4Addr::new(192, 0, 2, 1), port).into()],
+            };
+            Ok((addrs, Some(Duration::from_secs(86_400))))
+        }
+    }
+
+    let lookups = Arc::new(AtomicUsize::new(0));
+    let resolver = CachingResolver::new(Bogus(lookups.clone()))
+        .min_ttl(Duration::ZERO)
+        .max_ttl(Duration::from_millis(100));
+    for _ in 0..2 {
+        assert!(resolver.resolve("empty.test", 80).await.unwrap().is_empty());
+    }
+    assert_eq!(lookups.swap(0, Ordering::SeqCst), 2);
+
+    resolver.resolve("day.test", 80).await.unwrap();
+    resolver.resolve("day.test", 80).await.unwrap();
+    assert_eq!(lookups.load(Ordering::SeqCst), 1);
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    resolver.resolve("day.test", 80).await.unwrap();
+    assert_eq!(lookups.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn domains_without_a_usable_address_are_unreachable() {
+    use wssocks::{AddressFamily, Resolver};
+
+    // an empty answer for one name, only an IPv6 address for the other
+    struct Sparse;
+
+    #[async_trait::async_trait]
+    impl Resolver for Sparse {
+        async fn resolve(
+            &self,
+            host: &str,
+            port: u16,
+        ) -> std::io::Result<Vec<std::net::SocketAddr>> {
+            match host {
+                "v6.test" => Ok(vec![(std::net::Ipv6Addr::LOCALHOST, port).into()]),
+                _ => Ok(Vec::new()),
+            }
+        }
+    }
+
+    let config = config()
+        .address_family(AddressFamily::Ipv4Only)
+        .resolver(Sparse);
+    let addr = spawn_proxy(config);
+    for host in ["empty.test", "v6.test"] {
+        let mut ws = connect(addr).await;
+        let reply = socks5_connect(&mut ws, 0x01, &domain_address(host, 80)).await;
+        assert_eq!(reply[..2], [0x05, 0x04]);
+    }
+}
+
+#[tokio::test]
+async fn outbound_source_port_is_used_while_free() {
+    use tokio::net::TcpListener;
+
+    // a target telling each connection which port it came from
+    let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_addr = target.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, peer)) = target.accept().await {
+            tokio::spawn(async move {
+                use tokio::io::AsyncWriteExt;
+                let _ = stream.write_all(&peer.port().to_be_bytes()).await;
+                // held open, so the port stays taken for this target
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            });
+        }
+    });
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = spawn_proxy(config().outbound_source_port(Some(port)));
+    let source_port = |data: Option<Vec<u8>>| {
+        let data = data.unwrap();
+        u16::from_be_bytes([data[0], data[1]])
+    };
+
+    let mut first = connect(addr).await;
+    let reply = socks5_connect(&mut first, 0x01, &ip_address(target_addr)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    assert_eq!(source_port(recv(&mut first).await), port);
+
+    // the same four tuple is taken while the first tunnel is open
+    let mut second = connect(addr).await;
+    let reply = socks5_connect(&mut second, 0x01, &ip_address(target_addr)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    assert_ne!(source_port(recv(&mut second).await), port);
+}
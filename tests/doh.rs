@@ -0,0 +1,162 @@
+#![cfg(feature = "doh")]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::service::service_fn;
+use hyper::{server::conn::Http, Body, Response};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use wssocks::{DohResolver, Resolver};
+
+// the server certificate of the mtls tests, issued to localhost by ca.der
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(format!(
+        "{}/tests/data/mtls/{name}",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap()
+}
+
+// one answer record, its name a pointer to the question
+fn record(kind: u16, ttl: u32, data: &[u8]) -> Vec<u8> {
+    let mut record = vec![0xc0, 0x0c];
+    record.extend_from_slice(&kind.to_be_bytes());
+    record.extend_from_slice(&1u16.to_be_bytes());
+    record.extend_from_slice(&ttl.to_be_bytes());
+    record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    record.extend_from_slice(data);
+    record
+}
+
+// the response to a query, from a zone knowing a few names
+fn answer(query: &[u8]) -> Vec<u8> {
+    let question = &query[12..];
+    let kind = u16::from_be_bytes([question[question.len() - 4], question[question.len() - 3]]);
+    let mut name = Vec::new();
+    let mut pos = 0;
+    while question[pos] != 0 {
+        let len = usize::from(question[pos]);
+        name.push(String::from_utf8_lossy(&question[pos + 1..pos + 1 + len]).into_owned());
+        pos += 1 + len;
+    }
+    let (rcode, records) = match (name.join(".").as_str(), kind) {
+        // the cname's ttl is not the addresses'
+        ("both.test", 1) => (
+            0,
+            vec![
+                [
+                    &[0xc0, 0x0c, 0, 5, 0, 1, 0, 0, 0, 5, 0, 2][..],
+                    &[0xc0, 0x0c],
+                ]
+                .concat(),
+                record(1, 300, &[192, 0, 2, 1]),
+                record(1, 120, &[192, 0, 2, 2]),
+            ],
+        ),
+        ("both.test", 28) => (
+            0,
+            vec![record(
+                28,
+                60,
+                &[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            )],
+        ),
+        ("v4.test", 1) => (0, vec![record(1, 30, &[192, 0, 2, 3])]),
+        ("v4.test", 28) => (0, Vec::new()),
+        ("broken.test", _) => (2, Vec::new()),
+        _ => (3, Vec::new()),
+    };
+    let mut response = vec![0, 0, 0x81, 0x80 | rcode, 0, 1];
+    response.extend_from_slice(&(records.len() as u16).to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]);
+    response.extend_from_slice(question);
+    response.extend(records.concat());
+    response
+}
+
+// a DoH server on localhost, answering on the returned port
+async fn spawn_doh() -> u16 {
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![rustls::Certificate(fixture("server.der"))],
+            rustls::PrivateKey(fixture("server.key")),
+        )
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(stream) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let service = service_fn(|request: hyper::Request<Body>| async move {
+                    let query = hyper::body::to_bytes(request.into_body()).await?;
+                    Ok::<_, hyper::Error>(Response::new(Body::from(answer(&query))))
+                });
+                let _ = Http::new().serve_connection(stream, service).await;
+            });
+        }
+    });
+    port
+}
+
+fn doh(port: u16) -> DohResolver {
+    DohResolver::new(&format!("https://localhost:{port}/dns-query")).unwrap()
+}
+
+#[test]
+fn doh_needs_an_https_url_with_a_domain() {
+    assert!(DohResolver::new("https://cloudflare-dns.com/dns-query").is_ok());
+    assert!(DohResolver::new("https://dns.google:8443/dns-query").is_ok());
+    assert!(DohResolver::new("http://cloudflare-dns.com/dns-query").is_err());
+    assert!(DohResolver::new("cloudflare-dns.com").is_err());
+    // certificates are not verified for addresses
+    assert!(DohResolver::new("https://1.1.1.1/dns-query").is_err());
+    assert!(DohResolver::new("https://[2606:4700:4700::1111]/dns-query").is_err());
+}
+
+#[tokio::test]
+async fn doh_answers_both_families_with_the_shortest_ttl() {
+    let doh = doh(spawn_doh().await).root_certificate(fixture("ca.der"));
+    let (addrs, ttl) = doh.resolve_with_ttl("both.test", 443).await.unwrap();
+    let expected: [SocketAddr; 3] = [
+        "192.0.2.1:443".parse().unwrap(),
+        "192.0.2.2:443".parse().unwrap(),
+        "[2001:db8::1]:443".parse().unwrap(),
+    ];
+    assert_eq!(addrs, expected);
+    assert_eq!(ttl, Some(Duration::from_secs(60)));
+
+    // no AAAA records is no error
+    let (addrs, ttl) = doh.resolve_with_ttl("v4.test", 80).await.unwrap();
+    assert_eq!(addrs, ["192.0.2.3:80".parse::<SocketAddr>().unwrap()]);
+    assert_eq!(ttl, Some(Duration::from_secs(30)));
+}
+
+#[tokio::test]
+async fn doh_failures_keep_their_kind() {
+    let doh = doh(spawn_doh().await).root_certificate(fixture("ca.der"));
+    let e = doh.resolve("missing.test", 80).await.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    let e = doh.resolve("broken.test", 80).await.unwrap_err();
+    assert!(e.to_string().contains("rcode 2"), "{}", e);
+    let e = doh
+        .resolve(&format!("{}.test", "a".repeat(64)), 80)
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn doh_servers_are_verified() {
+    // the bundled web roots do not know the test CA
+    let untrusted = doh(spawn_doh().await);
+    assert!(untrusted.resolve("both.test", 443).await.is_err());
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::{http_get, spawn_proxy};
+use wssocks::{ConfigError, ProxyConfig};
+
+// one test, the environment is shared by the whole process
+#[tokio::test]
+async fn env_overrides_config() {
+    std::env::set_var("WSSOCKS_IDLE_TIMEOUT", "soon");
+    let Err(ConfigError::Env(message)) = ProxyConfig::from_env() else {
+        panic!("bad timeout accepted");
+    };
+    assert!(message.starts_with("WSSOCKS_IDLE_TIMEOUT"), "{message}");
+    std::env::set_var("WSSOCKS_IDLE_TIMEOUT", "300");
+
+    std::env::set_var("WSSOCKS_AUTH_USER", "alice");
+    assert!(ProxyConfig::from_env().is_err());
+    std::env::set_var("WSSOCKS_AUTH_PASS", "secret");
+
+    std::env::set_var("WSSOCKS_HEALTH_PATH", "/alive");
+    std::env::set_var("WSSOCKS_BLOCKED_PORTS", "25, 8000-9000");
+    let config = ProxyConfig::from_env().unwrap();
+    assert_eq!(config.validate(), Ok(()));
+    let addr = spawn_proxy(config);
+    let response = http_get(addr, "/alive").await;
+    assert!(response.starts_with("HTTP/1.0 200"), "{response}");
+
+    // the environment wins over the file
+    #[cfg(feature = "config-file")]
+    {
+        let path = std::env::temp_dir().join(format!("wssocks-env-{}.toml", std::process::id()));
+        std::fs::write(&path, "health_path = \"/file\"\nready_path = \"/ready\"").unwrap();
+        std::env::set_var("WSSOCKS_CONFIG", &path);
+        let addr = spawn_proxy(ProxyConfig::from_env().unwrap());
+        let response = http_get(addr, "/alive").await;
+        assert!(response.starts_with("HTTP/1.0 200"), "{response}");
+        let response = http_get(addr, "/ready").await;
+        assert!(response.starts_with("HTTP/1.0 200"), "{response}");
+    }
+}
@@ -0,0 +1,75 @@
+#![cfg(feature = "geoip")]
+
+mod common;
+
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use common::spawn_proxy_with_connect_info;
+use tokio_tungstenite::{connect_async, tungstenite::Error};
+use wssocks::{GeoIp, ProxyConfig};
+
+// a MaxMind DB placing 127.0.0.0/8 in the US and nothing else anywhere: an
+// IPv4 tree of 24 bit records walking the eight bits of 127, each node's
+// other branch leading nowhere
+fn database() -> PathBuf {
+    static PATH: OnceLock<PathBuf> = OnceLock::new();
+    PATH.get_or_init(write_database).clone()
+}
+
+fn write_database() -> PathBuf {
+    const NODES: u32 = 8;
+    let mut buf = Vec::new();
+    for i in 0..NODES {
+        let bit = (127u8 >> (7 - i)) & 1;
+        // past the last node, node count + 16 points at the first record
+        let next = if i + 1 == NODES { NODES + 16 } else { i + 1 };
+        let (left, right) = if bit == 0 {
+            (next, NODES)
+        } else {
+            (NODES, next)
+        };
+        buf.extend_from_slice(&left.to_be_bytes()[1..]);
+        buf.extend_from_slice(&right.to_be_bytes()[1..]);
+    }
+    buf.extend_from_slice(&[0; 16]);
+    // {"country": {"iso_code": "US"}}
+    buf.extend_from_slice(b"\xe1\x47country\xe1\x48iso_code\x42US");
+    // {"node_count": 8, "record_size": 24, "ip_version": 4}
+    buf.extend_from_slice(b"\xab\xcd\xefMaxMind.com\xe3");
+    buf.extend_from_slice(b"\x4anode_count\xc1\x08");
+    buf.extend_from_slice(b"\x4brecord_size\xa1\x18");
+    buf.extend_from_slice(b"\x4aip_version\xa1\x04");
+
+    let path = std::env::temp_dir().join(format!("wssocks-{}.mmdb", std::process::id()));
+    std::fs::write(&path, buf).unwrap();
+    path
+}
+
+// the status the upgrade is answered with
+async fn upgrade_status(geoip: GeoIp) -> u16 {
+    let addr = spawn_proxy_with_connect_info(ProxyConfig::default().geoip(Some(geoip)));
+    match connect_async(format!("ws://{addr}/ws")).await {
+        Ok((_, response)) => response.status().as_u16(),
+        Err(Error::Http(response)) => response.status().as_u16(),
+        Err(e) => panic!("{e}"),
+    }
+}
+
+#[tokio::test]
+async fn allowed_countries_are_admitted() {
+    assert_eq!(upgrade_status(GeoIp::allow(database(), ["us"])).await, 101);
+    assert_eq!(upgrade_status(GeoIp::deny(database(), ["DE"])).await, 101);
+}
+
+#[tokio::test]
+async fn other_countries_are_forbidden() {
+    assert_eq!(upgrade_status(GeoIp::allow(database(), ["DE"])).await, 403);
+    assert_eq!(upgrade_status(GeoIp::deny(database(), ["US"])).await, 403);
+}
+
+#[tokio::test]
+async fn missing_database_refuses_everyone() {
+    let geoip = GeoIp::deny("/nonexistent/GeoLite2-Country.mmdb", ["DE"]);
+    assert_eq!(upgrade_status(geoip).await, 403);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use common::spawn_proxy;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use wssocks::{ProxyConfig, WsSocksClient};
+
+// a server answering only once the client ended its writes, like an
+// HTTP/1.0 request body read to EOF
+async fn spawn_read_to_end() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                stream.read_to_end(&mut request).await.unwrap();
+                let answer = format!("got {} bytes", request.len());
+                stream.write_all(answer.as_bytes()).await.unwrap();
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn client_can_read_after_ending_its_writes() {
+    let target = spawn_read_to_end().await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let client = WsSocksClient::new(format!("ws://{addr}/ws"));
+
+    let mut stream = client.connect(&target.to_string()).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).await.unwrap();
+    assert_eq!(answer, "got 5 bytes");
+}
+
+#[tokio::test]
+async fn local_proxy_passes_the_half_close_on() {
+    let target = spawn_read_to_end().await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let local = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let local_addr = local.local_addr().unwrap();
+    drop(local);
+    tokio::spawn(wssocks::run_local_proxy(
+        local_addr,
+        format!("ws://{addr}/ws"),
+    ));
+
+    let mut stream = loop {
+        match TcpStream::connect(local_addr).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::task::yield_now().await,
+        }
+    };
+    stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+    let mut selected = [0u8; 2];
+    stream.read_exact(&mut selected).await.unwrap();
+    assert_eq!(selected, [0x05, 0x00]);
+    let port = target.port().to_be_bytes();
+    let request = [0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, port[0], port[1]];
+    stream.write_all(&request).await.unwrap();
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[..2], [0x05, 0x00]);
+
+    stream.write_all(b"hello").await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).await.unwrap();
+    assert_eq!(answer, "got 5 bytes");
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::{
+    close_code, connect, ip_address, recv, send, socks5_connect, spawn_echo, spawn_proxy,
+};
+use wssocks::ProxyConfig;
+
+#[tokio::test]
+async fn no_acceptable_method_is_refused() {
+    let addr = spawn_proxy(ProxyConfig::default());
+    let mut ws = connect(addr).await;
+
+    // only CHAP, which we do not support
+    send(&mut ws, &[0x05, 0x01, 0x03]).await;
+    assert_eq!(recv(&mut ws).await, Some(vec![0x05, 0xff]));
+    assert_eq!(close_code(&mut ws).await, Some(1008));
+}
+
+#[tokio::test]
+async fn fragmented_messages_are_reassembled() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let mut ws = connect(addr).await;
+
+    send(&mut ws, &[0x05]).await;
+    send(&mut ws, &[0x01, 0x00]).await;
+    assert_eq!(recv(&mut ws).await, Some(vec![0x05, 0x00]));
+
+    let mut request = vec![0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    request.extend_from_slice(&echo.port().to_be_bytes());
+    send(&mut ws, &request[..5]).await;
+    send(&mut ws, &request[5..]).await;
+    assert_eq!(recv(&mut ws).await.map(|reply| reply[1]), Some(0x00));
+
+    send(&mut ws, b"ping").await;
+    assert_eq!(recv(&mut ws).await, Some(b"ping".to_vec()));
+}
+
+#[tokio::test]
+async fn pipelined_socks5_data_reaches_the_target() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let mut ws = connect(addr).await;
+
+    // method selection, request and payload without waiting for any reply
+    let mut frame = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+    frame.extend_from_slice(&echo.port().to_be_bytes());
+    frame.extend_from_slice(b"early");
+    send(&mut ws, &frame).await;
+    assert_eq!(recv(&mut ws).await, Some(vec![0x05, 0x00]));
+    assert_eq!(recv(&mut ws).await.map(|reply| reply[1]), Some(0x00));
+    assert_eq!(recv(&mut ws).await, Some(b"early".to_vec()));
+}
+
+#[tokio::test]
+async fn pipelined_socks4_data_reaches_the_target() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let mut ws = connect(addr).await;
+
+    let mut frame = vec![0x04, 0x01];
+    frame.extend_from_slice(&echo.port().to_be_bytes());
+    frame.extend_from_slice(&[127, 0, 0, 1, 0]);
+    frame.extend_from_slice(b"early");
+    send(&mut ws, &frame).await;
+    assert_eq!(recv(&mut ws).await.map(|reply| reply[1]), Some(0x5a));
+    assert_eq!(recv(&mut ws).await, Some(b"early".to_vec()));
+}
+
+#[tokio::test]
+async fn large_pipelined_socks4a_payloads_are_not_part_of_the_request() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let mut ws = connect(addr).await;
+
+    // more payload than the longest request could be, in the request's frame
+    let payload = vec![b'x'; 4096];
+    let mut frame = vec![0x04, 0x01];
+    frame.extend_from_slice(&echo.port().to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 1, 0]);
+    frame.extend_from_slice(b"localhost\0");
+    frame.extend_from_slice(&payload);
+    send(&mut ws, &frame).await;
+    assert_eq!(recv(&mut ws).await.map(|reply| reply[1]), Some(0x5a));
+
+    let mut echoed = Vec::new();
+    while echoed.len() < payload.len() {
+        echoed.extend(recv(&mut ws).await.expect("tunnel closed early"));
+    }
+    assert_eq!(echoed, payload);
+}
+
+#[tokio::test]
+async fn pipelined_http_data_reaches_the_target() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let mut ws = connect(addr).await;
+
+    let head = format!("CONNECT {echo} HTTP/1.1\r\nHost: {echo}\r\n\r\nearly");
+    send(&mut ws, head.as_bytes()).await;
+    let reply = recv(&mut ws).await.unwrap();
+    assert!(reply.starts_with(b"HTTP/1.1 200"), "{reply:?}");
+    assert_eq!(recv(&mut ws).await, Some(b"early".to_vec()));
+}
+
+#[tokio::test]
+async fn silenced_errors_close_without_a_reply() {
+    let addr = spawn_proxy(ProxyConfig::default().error_replies(false));
+    let mut ws = connect(addr).await;
+
+    send(&mut ws, &[0x05, 0x01, 0x03]).await;
+    assert_eq!(close_code(&mut ws).await, None);
+}
+
+#[tokio::test]
+async fn oversized_messages_close_with_message_too_big() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let config = ProxyConfig::default()
+        .block_private_addresses(false)
+        .max_message_size(1024);
+    let addr = spawn_proxy(config);
+
+    let mut ws = connect(addr).await;
+    send(&mut ws, &[0x05; 2048]).await;
+    assert_eq!(close_code(&mut ws).await, Some(1009));
+
+    // and mid-tunnel, once the handshake went through
+    let mut ws = connect(addr).await;
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, &[0; 1024]).await;
+    let mut echoed = 0;
+    while echoed < 1024 {
+        echoed += recv(&mut ws)
+            .await
+            .expect("closed on a message at the limit")
+            .len();
+    }
+    send(&mut ws, &[0; 2048]).await;
+    assert_eq!(close_code(&mut ws).await, Some(1009));
+}
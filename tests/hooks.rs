@@ -0,0 +1,185 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{
+    connect, domain_address, ip_address, recv, send, socks5_connect, spawn_echo, spawn_proxy,
+};
+use futures::SinkExt;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use wssocks::{CloseReason, ProxyConfig, Target, Verdict, WsSocksClient};
+
+fn config() -> ProxyConfig {
+    ProxyConfig::default().block_private_addresses(false)
+}
+
+#[tokio::test]
+async fn connect_hook_sees_the_target_and_can_veto_it() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let hook_seen = seen.clone();
+    let addr = spawn_proxy(config().on_connect(move |request| {
+        hook_seen.lock().unwrap().push(request.target.to_string());
+        false
+    }));
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x02]);
+    assert_eq!(*seen.lock().unwrap(), [echo.to_string()]);
+}
+
+#[tokio::test]
+async fn close_hook_gets_the_tunnel_totals() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let addr = spawn_proxy(config().on_connect(|_| true).on_close(move |stats| {
+        let _ = tx.send(stats.clone());
+    }));
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, b"hello").await;
+    assert_eq!(recv(&mut ws).await, Some(b"hello".to_vec()));
+    ws.close(None).await.unwrap();
+
+    let stats = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stats.target, echo.to_string());
+    assert_eq!((stats.bytes_up, stats.bytes_down), (5, 5));
+    assert_eq!(stats.reason, CloseReason::Closed);
+}
+
+#[tokio::test]
+async fn busy_tunnels_close_at_their_max_lifetime() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let config = config()
+        .max_lifetime(Some(Duration::from_millis(300)))
+        .on_close(move |stats| {
+            let _ = tx.send(stats.reason);
+        });
+    let addr = spawn_proxy(config);
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    // keep the tunnel busy until the server closes it
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while ws.send(Message::Binary(b"ping".to_vec())).await.is_ok()
+            && recv(&mut ws).await.is_some()
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let reason = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap();
+    assert_eq!(reason, Some(CloseReason::MaxLifetime));
+}
+
+#[tokio::test]
+async fn flagged_targets_close_and_report_the_client() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let addr = spawn_proxy(
+        config()
+            .abuse_classifier(|host| match host {
+                "honeypot.test" => Verdict::BlockAndFlag,
+                _ => Verdict::Allow,
+            })
+            .on_abuse(move |request| {
+                let _ = tx.send(request.target.to_string());
+            }),
+    );
+    let mut ws = connect(addr).await;
+
+    send(&mut ws, &[0x05, 0x01, 0x00]).await;
+    assert_eq!(recv(&mut ws).await, Some(vec![0x05, 0x00]));
+    let mut request = vec![0x05, 0x01, 0x00];
+    request.extend_from_slice(&domain_address("honeypot.test", 80));
+    send(&mut ws, &request).await;
+    assert_eq!(recv(&mut ws).await, None);
+    assert_eq!(rx.recv().await.as_deref(), Some("honeypot.test:80"));
+}
+
+#[tokio::test]
+async fn allowed_targets_pass_the_classifier() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(config().abuse_classifier(|_| Verdict::Allow));
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+}
+
+#[tokio::test]
+async fn client_and_close_hook_share_the_conn_id() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let echo = spawn_echo("127.0.0.1").await;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let addr = spawn_proxy(config().on_close(move |stats| {
+        let _ = tx.send(stats.conn_id);
+    }));
+    let client = WsSocksClient::new(format!("ws://{addr}/ws"));
+
+    let mut stream = client.connect(&echo.to_string()).await.unwrap();
+    let conn_id = stream.conn_id().map(str::to_string);
+    stream.write_all(b"hello").await.unwrap();
+    stream.read_exact(&mut [0; 5]).await.unwrap();
+    stream.shutdown().await.unwrap();
+    drop(stream);
+
+    let closed = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(conn_id, Some(closed.to_string()));
+}
+
+// sends the made up staging.invalid to to
+fn redirect(to: std::net::SocketAddr) -> impl Fn(&Target) -> Option<Target> {
+    move |target| match target {
+        Target::Domain { host, .. } if host == "staging.invalid" => Some(Target::Ip(to)),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn rewritten_targets_are_connected_to() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let hook_seen = seen.clone();
+    let config = config()
+        .rewrite_target(redirect(echo))
+        .on_connect(move |request| {
+            hook_seen.lock().unwrap().push(request.target.to_string());
+            true
+        });
+    let addr = spawn_proxy(config);
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("staging.invalid", 80)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, b"hello").await;
+    assert_eq!(recv(&mut ws).await, Some(b"hello".to_vec()));
+    assert_eq!(*seen.lock().unwrap(), [echo.to_string()]);
+}
+
+#[tokio::test]
+async fn rewritten_targets_are_still_checked() {
+    let echo = spawn_echo("127.0.0.1").await;
+    // private addresses are blocked by default
+    let addr = spawn_proxy(ProxyConfig::default().rewrite_target(redirect(echo)));
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address("staging.invalid", 80)).await;
+    assert_eq!(reply[..2], [0x05, 0x02]);
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use axum::http::{HeaderValue, Request};
+use common::{ip_address, recv, send, socks5_connect, spawn_echo, spawn_router};
+use tokio_tungstenite::connect_async;
+use tower_http::{set_header::SetResponseHeaderLayer, trace::TraceLayer};
+use wssocks::ProxyConfig;
+
+fn config() -> ProxyConfig {
+    ProxyConfig::default().block_private_addresses(false)
+}
+
+#[tokio::test]
+async fn tunnels_pass_through_a_trace_layer() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    let trace = TraceLayer::new_for_http().on_request(
+        move |_: &Request<axum::body::Body>, _: &tracing::Span| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        },
+    );
+    let addr = spawn_router(wssocks::router(config()).layer(trace));
+
+    let (mut ws, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, b"traced").await;
+    assert_eq!(recv(&mut ws).await, Some(b"traced".to_vec()));
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn layers_reach_the_upgrade_response() {
+    let layer = SetResponseHeaderLayer::overriding(
+        axum::http::header::SERVER,
+        HeaderValue::from_static("wssocks"),
+    );
+    let addr = spawn_router(wssocks::router(config()).layer(layer));
+
+    let (_, response) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+    assert_eq!(response.status(), 101);
+    assert_eq!(response.headers()["server"], "wssocks");
+}
@@ -0,0 +1,107 @@
+#![cfg(all(feature = "mtls", feature = "rustls"))]
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::spawn_echo;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use wssocks::{serve_tls, ProxyConfig, ServerTls, WsSocksClient};
+
+// a CA issuing the server's and alice's certificates, and mallory's from a
+// CA nobody trusts
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(format!(
+        "{}/tests/data/mtls/{name}",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap()
+}
+
+fn config() -> ProxyConfig {
+    ProxyConfig::default().block_private_addresses(false)
+}
+
+// serve over TLS trusting the test CA for clients, the subjects the connect
+// hook saw go into the returned list
+async fn spawn_tls(config: ProxyConfig) -> (SocketAddr, Arc<Mutex<Vec<Option<String>>>>) {
+    let subjects = Arc::new(Mutex::new(Vec::new()));
+    let seen = subjects.clone();
+    let config = config.on_connect(move |request| {
+        seen.lock().unwrap().push(request.client_subject.clone());
+        true
+    });
+    let tls = ServerTls::new(vec![fixture("server.der")], fixture("server.key"))
+        .client_root_certificate(fixture("ca.der"));
+
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    tokio::spawn(serve_tls(addr, config, tls));
+    while TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    (addr, subjects)
+}
+
+fn client(addr: SocketAddr) -> WsSocksClient {
+    WsSocksClient::new(format!("wss://localhost:{}/ws", addr.port()))
+        .root_certificate(fixture("ca.der"))
+}
+
+async fn assert_round_trip(client: WsSocksClient) {
+    let echo = spawn_echo("127.0.0.1").await;
+    let mut stream = client.connect(&echo.to_string()).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    let mut echoed = [0u8; 5];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"hello");
+}
+
+#[tokio::test]
+async fn verified_clients_are_known_by_their_subject() {
+    let (addr, subjects) = spawn_tls(config().require_client_certificate(true)).await;
+    let client =
+        client(addr).client_certificate(vec![fixture("client.der")], fixture("client.key"));
+
+    assert_round_trip(client).await;
+    assert_eq!(
+        *subjects.lock().unwrap(),
+        [Some("CN=alice,OU=ops,O=wssocks tests".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn clients_without_a_certificate_are_refused_when_required() {
+    let (addr, subjects) = spawn_tls(config().require_client_certificate(true)).await;
+
+    let e = client(addr)
+        .connect("127.0.0.1:80")
+        .await
+        .err()
+        .expect("upgraded without a certificate");
+    assert!(e.to_string().contains("403"), "{}", e);
+    assert!(subjects.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn certificates_are_optional_unless_required() {
+    let (addr, subjects) = spawn_tls(config()).await;
+
+    assert_round_trip(client(addr)).await;
+    assert_eq!(*subjects.lock().unwrap(), [None]);
+}
+
+#[tokio::test]
+async fn untrusted_certificates_fail_the_handshake() {
+    let (addr, subjects) = spawn_tls(config()).await;
+    let client = client(addr).client_certificate(vec![fixture("rogue.der")], fixture("rogue.key"));
+
+    assert!(client.connect("127.0.0.1:80").await.is_err());
+    assert!(subjects.lock().unwrap().is_empty());
+}
@@ -0,0 +1,20 @@
+#![cfg(all(feature = "netns", target_os = "linux"))]
+
+mod common;
+
+use common::{connect, ip_address, socks5_connect, spawn_echo, spawn_proxy};
+use wssocks::ProxyConfig;
+
+#[tokio::test]
+async fn missing_namespace_fails_every_connect() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let config = ProxyConfig::default()
+        .block_private_addresses(false)
+        .netns(Some("wssocks-missing"));
+    let addr = spawn_proxy(config);
+    for _ in 0..2 {
+        let mut ws = connect(addr).await;
+        let reply = socks5_connect(&mut ws, 0x01, &ip_address(echo)).await;
+        assert_eq!(reply[..2], [0x05, 0x01]);
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::FutureExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use wssocks::{AsyncReadWrite, OutboundConnector, PooledConnector, Target};
+
+// hands out in memory streams, each starting with the number of the connect
+// that opened it, and keeps their far ends
+#[derive(Clone, Default)]
+struct Numbered(Arc<Mutex<Vec<DuplexStream>>>);
+
+#[async_trait::async_trait]
+impl OutboundConnector for Numbered {
+    async fn connect(&self, _: &Target) -> std::io::Result<Box<dyn AsyncReadWrite>> {
+        let (near, mut far) = tokio::io::duplex(64);
+        let mut opened = self.0.lock().unwrap();
+        // the duplex buffer has room, so this never waits
+        far.write_u8(opened.len() as u8).now_or_never().unwrap()?;
+        opened.push(far);
+        Ok(Box::new(near))
+    }
+}
+
+fn target() -> Target {
+    "upstream.test:1080".parse().unwrap()
+}
+
+async fn number(stream: &mut Box<dyn AsyncReadWrite>) -> u8 {
+    stream.read_u8().await.unwrap()
+}
+
+#[tokio::test]
+async fn later_connects_get_a_spare() {
+    let inner = Numbered::default();
+    let pool = PooledConnector::new(inner.clone()).max_idle_per_key(2);
+
+    let mut first = pool.connect(&target()).await.unwrap();
+    assert_eq!(number(&mut first).await, 0);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(inner.0.lock().unwrap().len(), 3);
+
+    let mut second = pool.connect(&target()).await.unwrap();
+    assert!(matches!(number(&mut second).await, 1 | 2));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(inner.0.lock().unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn idle_spares_are_closed() {
+    let inner = Numbered::default();
+    let pool = PooledConnector::new(inner.clone()).idle_timeout(Duration::from_millis(100));
+
+    let _first = pool.connect(&target()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let mut far = inner.0.lock().unwrap().pop().unwrap();
+    let mut buf = [0u8; 1];
+    assert_eq!(far.read(&mut buf).await.unwrap(), 0);
+}
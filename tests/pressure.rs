@@ -0,0 +1,82 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use common::{http_get, spawn_echo, spawn_proxy};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use wssocks::{ClientConnection, ProxyConfig, WsSocksClient};
+
+fn config() -> ProxyConfig {
+    ProxyConfig::default()
+        .block_private_addresses(false)
+        .ready_path(Some("/readyz".to_string()))
+}
+
+async fn assert_echoes(stream: &mut ClientConnection) {
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    let mut echoed = [0u8; 5];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"hello");
+}
+
+// the upgrade is refused with 503 before any tunnel is set up
+async fn assert_refused(client: &WsSocksClient, echo: SocketAddr) {
+    let e = client
+        .connect(&echo.to_string())
+        .await
+        .err()
+        .expect("upgraded under pressure");
+    assert!(e.to_string().contains("503"), "{}", e);
+}
+
+// a new tunnel gets through again once the open ones closed
+async fn wait_for_upgrade(client: &WsSocksClient, echo: SocketAddr) -> ClientConnection {
+    for _ in 0..100 {
+        if let Ok(stream) = client.connect(&echo.to_string()).await {
+            return stream;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("still refused");
+}
+
+#[tokio::test]
+async fn upgrades_over_the_soft_tunnel_limit_are_refused() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(config().soft_max_tunnels(Some(1)));
+    let client = WsSocksClient::new(format!("ws://{addr}/ws"));
+
+    let mut open = client.connect(&echo.to_string()).await.unwrap();
+    assert_refused(&client, echo).await;
+    assert!(http_get(addr, "/readyz").await.starts_with("HTTP/1.0 503"));
+    // the open tunnel is kept
+    assert_echoes(&mut open).await;
+
+    drop(open);
+    let mut stream = wait_for_upgrade(&client, echo).await;
+    assert_echoes(&mut stream).await;
+}
+
+#[tokio::test]
+async fn upgrades_over_the_soft_memory_limit_are_refused() {
+    let echo = spawn_echo("127.0.0.1").await;
+    // two 4 KiB read buffers and a 64 KiB message per tunnel, room for two
+    let config = config()
+        .buffer_size(4096)
+        .max_message_size(64 * 1024)
+        .soft_memory_limit(Some(160 * 1024));
+    let addr = spawn_proxy(config);
+    let client = WsSocksClient::new(format!("ws://{addr}/ws"));
+
+    let mut first = client.connect(&echo.to_string()).await.unwrap();
+    assert!(http_get(addr, "/readyz").await.starts_with("HTTP/1.0 200"));
+    let _second = client.connect(&echo.to_string()).await.unwrap();
+    assert_refused(&client, echo).await;
+    assert!(http_get(addr, "/readyz").await.starts_with("HTTP/1.0 503"));
+    assert_echoes(&mut first).await;
+
+    drop(first);
+    wait_for_upgrade(&client, echo).await;
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use common::{
+    connect, ip_address, local_addr, send, socks5_connect, spawn_proxy,
+    spawn_proxy_with_connect_info,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::MaybeTlsStream;
+use wssocks::{ProxyConfig, TargetRule};
+
+const SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+fn config() -> ProxyConfig {
+    ProxyConfig::default().block_private_addresses(false)
+}
+
+// open a tunnel to a fresh target on ip, send ping and return the first
+// bytes the target got, header included
+async fn first_bytes(proxy: SocketAddr, ip: &str, len: usize) -> (SocketAddr, SocketAddr, Vec<u8>) {
+    let listener = TcpListener::bind((ip, 0)).await.unwrap();
+    let target = listener.local_addr().unwrap();
+    let mut ws = connect(proxy).await;
+    let reply = socks5_connect(&mut ws, 0x01, &ip_address(target)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, b"ping").await;
+
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await.unwrap();
+    (local_addr(&ws), target, buf)
+}
+
+#[tokio::test]
+async fn header_carries_the_ipv4_client() {
+    let proxy = spawn_proxy_with_connect_info(config().proxy_protocol(true));
+    let (client, target, buf) = first_bytes(proxy, "127.0.0.1", 28 + 4).await;
+
+    let mut expected = SIGNATURE.to_vec();
+    expected.extend_from_slice(&[0x21, 0x11, 0, 12, 127, 0, 0, 1, 127, 0, 0, 1]);
+    expected.extend_from_slice(&client.port().to_be_bytes());
+    expected.extend_from_slice(&target.port().to_be_bytes());
+    expected.extend_from_slice(b"ping");
+    assert_eq!(buf, expected);
+}
+
+#[tokio::test]
+async fn ipv4_client_to_ipv6_target_is_mapped() {
+    let proxy = spawn_proxy_with_connect_info(config().proxy_protocol(true));
+    let (client, target, buf) = first_bytes(proxy, "::1", 52).await;
+
+    let mut expected = SIGNATURE.to_vec();
+    expected.extend_from_slice(&[0x21, 0x21, 0, 36]);
+    expected.extend_from_slice(
+        &"::ffff:127.0.0.1"
+            .parse::<std::net::Ipv6Addr>()
+            .unwrap()
+            .octets(),
+    );
+    expected.extend_from_slice(&std::net::Ipv6Addr::LOCALHOST.octets());
+    expected.extend_from_slice(&client.port().to_be_bytes());
+    expected.extend_from_slice(&target.port().to_be_bytes());
+    assert_eq!(buf, expected);
+}
+
+#[tokio::test]
+async fn unknown_client_sends_local() {
+    let proxy = spawn_proxy(config().proxy_protocol(true));
+    let (_, _, buf) = first_bytes(proxy, "127.0.0.1", 16 + 4).await;
+
+    let mut expected = SIGNATURE.to_vec();
+    expected.extend_from_slice(&[0x20, 0x00, 0, 0]);
+    expected.extend_from_slice(b"ping");
+    assert_eq!(buf, expected);
+}
+
+#[tokio::test]
+async fn only_matching_targets_get_the_header() {
+    let rule: TargetRule = "10.0.0.0/8".parse().unwrap();
+    let proxy = spawn_proxy_with_connect_info(config().proxy_protocol_targets([rule]));
+    let (_, _, buf) = first_bytes(proxy, "127.0.0.1", 4).await;
+    assert_eq!(buf, b"ping");
+}
+
+// serve with inbound PROXY headers accepted, the peers the connect hook sees
+// come out of the channel
+async fn serve_proxied() -> (SocketAddr, mpsc::UnboundedReceiver<Option<SocketAddr>>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let config = config()
+        .accept_proxy_protocol(true)
+        .on_connect(move |request| {
+            let _ = tx.send(request.peer);
+            false
+        });
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    tokio::spawn(wssocks::serve(addr, config));
+    while TcpStream::connect(addr).await.is_err() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    (addr, rx)
+}
+
+// upgrade over a connection starting with header
+async fn connect_proxied(addr: SocketAddr, header: &[u8]) -> Result<common::Client, ()> {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(header).await.unwrap();
+    tokio_tungstenite::client_async(format!("ws://{addr}/ws"), MaybeTlsStream::Plain(stream))
+        .await
+        .map(|(ws, _)| ws)
+        .map_err(|_| ())
+}
+
+// the peer a tunnel request over a connection starting with header is seen
+// from
+async fn peer_seen(header: &[u8]) -> Option<SocketAddr> {
+    let (addr, mut peers) = serve_proxied().await;
+    let mut ws = connect_proxied(addr, header).await.unwrap();
+    let target = "127.0.0.1:9".parse().unwrap();
+    socks5_connect(&mut ws, 0x01, &ip_address(target)).await;
+    peers.recv().await.unwrap()
+}
+
+#[tokio::test]
+async fn inbound_v1_header_sets_the_client() {
+    let peer = peer_seen(b"PROXY TCP4 203.0.113.7 127.0.0.1 4000 80\r\n").await;
+    assert_eq!(peer, Some("203.0.113.7:4000".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn inbound_v2_header_sets_the_client() {
+    let mut header = SIGNATURE.to_vec();
+    header.extend_from_slice(&[0x21, 0x21, 0, 36]);
+    header.extend_from_slice(
+        &"2001:db8::7"
+            .parse::<std::net::Ipv6Addr>()
+            .unwrap()
+            .octets(),
+    );
+    header.extend_from_slice(&std::net::Ipv6Addr::LOCALHOST.octets());
+    header.extend_from_slice(&4000u16.to_be_bytes());
+    header.extend_from_slice(&80u16.to_be_bytes());
+    let peer = peer_seen(&header).await;
+    assert_eq!(peer, Some("[2001:db8::7]:4000".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn inbound_local_header_keeps_the_connection_peer() {
+    let mut header = SIGNATURE.to_vec();
+    header.extend_from_slice(&[0x20, 0x00, 0, 0]);
+    let peer = peer_seen(&header).await.unwrap();
+    assert_eq!(peer.ip(), std::net::Ipv4Addr::LOCALHOST);
+}
+
+#[tokio::test]
+async fn inbound_connection_without_header_is_dropped() {
+    let (addr, _) = serve_proxied().await;
+    assert!(connect_proxied(addr, b"").await.is_err());
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use common::{close_code, connect, domain_address, socks5_connect, spawn_proxy};
+use wssocks::{ProxyConfig, Resolver, Target, TargetRule};
+
+// Tor's RESOLVE command
+const CMD_RESOLVE: u8 = 0xf0;
+
+// knows a public name and a private one, which the system resolver does not
+struct Static;
+
+#[async_trait::async_trait]
+impl Resolver for Static {
+    async fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        match host {
+            "public.test" => Ok(vec![SocketAddr::from(([198, 51, 100, 7], port))]),
+            "private.test" => Ok(vec![SocketAddr::from(([10, 0, 0, 7], port))]),
+            _ => Err(std::io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+fn config() -> ProxyConfig {
+    ProxyConfig::default()
+        .resolver(Static)
+        .resolve_command(true)
+}
+
+async fn resolve(addr: SocketAddr, host: &str) -> Vec<u8> {
+    let mut ws = connect(addr).await;
+    socks5_connect(&mut ws, CMD_RESOLVE, &domain_address(host, 0)).await
+}
+
+#[tokio::test]
+async fn resolve_is_refused_by_default() {
+    let addr = spawn_proxy(ProxyConfig::default().resolver(Static));
+    assert_eq!(resolve(addr, "public.test").await[..2], [0x05, 0x07]);
+}
+
+#[tokio::test]
+async fn resolve_replies_with_the_address() {
+    let addr = spawn_proxy(config());
+    let mut ws = connect(addr).await;
+
+    let reply = socks5_connect(&mut ws, CMD_RESOLVE, &domain_address("public.test", 0)).await;
+    assert_eq!(reply, [0x05, 0x00, 0x00, 0x01, 198, 51, 100, 7, 0, 0]);
+    // nothing is connected, so the tunnel ends there
+    assert_eq!(close_code(&mut ws).await, Some(1000));
+}
+
+#[tokio::test]
+async fn resolve_applies_the_target_rules() {
+    let addr = spawn_proxy(config());
+    assert_eq!(resolve(addr, "private.test").await[..2], [0x05, 0x02]);
+    assert_eq!(resolve(addr, "missing.test").await[..2], [0x05, 0x04]);
+
+    let denied: TargetRule = "public.test".parse().unwrap();
+    let addr = spawn_proxy(config().deny([denied]));
+    assert_eq!(resolve(addr, "public.test").await[..2], [0x05, 0x02]);
+}
+
+#[tokio::test]
+async fn resolve_goes_through_the_rewrite_and_connect_hooks() {
+    let addr = spawn_proxy(config().rewrite_target(|target| match target {
+        Target::Domain { host, port } if host == "alias.test" => Some(Target::Domain {
+            host: "public.test".to_string(),
+            port: *port,
+        }),
+        _ => None,
+    }));
+    assert_eq!(
+        resolve(addr, "alias.test").await,
+        [0x05, 0x00, 0x00, 0x01, 198, 51, 100, 7, 0, 0]
+    );
+
+    let addr = spawn_proxy(config().on_connect(
+        |request| !matches!(&request.target, Target::Domain { host, .. } if host == "public.test"),
+    ));
+    assert_eq!(resolve(addr, "public.test").await[..2], [0x05, 0x02]);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+
+use common::spawn_echo;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use wssocks::{ProxyConfig, Retry, WsSocksClient};
+
+#[tokio::test]
+async fn retry_waits_out_a_server_restart() {
+    let echo = spawn_echo("127.0.0.1").await;
+    // a port nothing listens on until the proxy comes back
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let retry = Retry::new(20)
+        .initial_backoff(Duration::from_millis(20))
+        .max_backoff(Duration::from_millis(100));
+    let client = WsSocksClient::new(format!("ws://{addr}/ws")).retry(Some(retry));
+    let connecting = tokio::spawn(async move { client.connect(&echo.to_string()).await });
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let listener = TcpListener::bind(addr).unwrap();
+    let config = ProxyConfig::default().block_private_addresses(false);
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(wssocks::router(config).into_make_service());
+    tokio::spawn(server);
+
+    let mut stream = connecting.await.unwrap().unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    let mut echoed = [0u8; 5];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"hello");
+}
+
+#[tokio::test]
+async fn retry_gives_up_after_its_attempts() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let retry = Retry::new(3).initial_backoff(Duration::from_millis(40));
+    let client = WsSocksClient::new(format!("ws://{addr}/ws")).retry(Some(retry));
+
+    let started = Instant::now();
+    let e = client
+        .connect("127.0.0.1:80")
+        .await
+        .err()
+        .expect("connected");
+    assert_eq!(e.kind(), std::io::ErrorKind::ConnectionRefused);
+    // two waits of at least half their 40 and 80 ms ceilings
+    assert!(started.elapsed() >= Duration::from_millis(60));
+}
+
+#[tokio::test]
+async fn refused_upgrades_are_not_retried() {
+    let addr = common::spawn_proxy(ProxyConfig::default().auth_token(Some("secret".to_string())));
+    let retry = Retry::new(5).initial_backoff(Duration::from_secs(10));
+    let client = WsSocksClient::new(format!("ws://{addr}/ws")).retry(Some(retry));
+
+    let connected = tokio::time::timeout(Duration::from_secs(5), client.connect("127.0.0.1:80"));
+    assert!(connected.await.expect("a 401 was retried").is_err());
+}
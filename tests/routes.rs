@@ -0,0 +1,35 @@
+mod common;
+
+use common::{http_get, spawn_proxy};
+use wssocks::ProxyConfig;
+
+#[tokio::test]
+async fn root_serves_the_default_page() {
+    let addr = spawn_proxy(ProxyConfig::default());
+
+    let response = http_get(addr, "/").await;
+    assert!(response.starts_with("HTTP/1.0 200"), "{response}");
+    assert!(response.ends_with("Hello, World!"), "{response}");
+}
+
+#[tokio::test]
+async fn root_serves_a_custom_page() {
+    let config = ProxyConfig::default().root_page("text/html", "<h1>It works</h1>");
+    let addr = spawn_proxy(config);
+
+    let response = http_get(addr, "/").await;
+    assert!(response.starts_with("HTTP/1.0 200"), "{response}");
+    assert!(
+        response.contains("content-type: text/html\r\n"),
+        "{response}"
+    );
+    assert!(response.ends_with("<h1>It works</h1>"), "{response}");
+}
+
+#[tokio::test]
+async fn disabled_root_is_not_found() {
+    let addr = spawn_proxy(ProxyConfig::default().root(false));
+
+    let response = http_get(addr, "/").await;
+    assert!(response.starts_with("HTTP/1.0 404"), "{response}");
+}
@@ -0,0 +1,110 @@
+use wssocks::{parse_socks5_request, Socks5Error, Socks5Request, Target};
+
+#[test]
+fn parses_ipv4_target() {
+    let request = parse_socks5_request(&[5, 1, 0, 1, 10, 0, 0, 1, 0x1f, 0x90]).unwrap();
+    assert_eq!(
+        request,
+        Socks5Request {
+            cmd: 1,
+            target: Target::Ip("10.0.0.1:8080".parse().unwrap()),
+        }
+    );
+}
+
+#[test]
+fn parses_ipv6_target() {
+    let mut buf = vec![5, 3, 0, 4];
+    buf.extend_from_slice(&"::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+    buf.extend_from_slice(&443u16.to_be_bytes());
+    let request = parse_socks5_request(&buf).unwrap();
+    assert_eq!(request.cmd, 3);
+    assert_eq!(request.target.to_string(), "[::1]:443");
+}
+
+#[test]
+fn parses_domain_target() {
+    let mut buf = vec![5, 1, 0, 3, 11];
+    buf.extend_from_slice(b"example.com");
+    buf.extend_from_slice(&80u16.to_be_bytes());
+    let request = parse_socks5_request(&buf).unwrap();
+    assert_eq!(
+        request.target,
+        Target::Domain {
+            host: "example.com".to_string(),
+            port: 80,
+        }
+    );
+    assert_eq!(request.target.to_string(), "example.com:80");
+}
+
+#[test]
+fn rejects_short_messages() {
+    for len in 0..4 {
+        assert_eq!(
+            parse_socks5_request(&[5, 1, 0, 1][..len]),
+            Err(Socks5Error::TooShort)
+        );
+    }
+}
+
+#[test]
+fn rejects_bad_header_fields() {
+    assert_eq!(
+        parse_socks5_request(&[4, 1, 0, 1, 127, 0, 0, 1, 0, 80]),
+        Err(Socks5Error::BadVersion(4))
+    );
+    assert_eq!(
+        parse_socks5_request(&[5, 9, 0, 1, 127, 0, 0, 1, 0, 80]),
+        Err(Socks5Error::UnsupportedCommand(9))
+    );
+    // Tor's RESOLVE_PTR, unlike its RESOLVE
+    assert_eq!(
+        parse_socks5_request(&[5, 0xf1, 0, 1, 127, 0, 0, 1, 0, 0]),
+        Err(Socks5Error::UnsupportedCommand(0xf1))
+    );
+    assert_eq!(
+        parse_socks5_request(&[5, 1, 0, 2, 127, 0, 0, 1, 0, 80]),
+        Err(Socks5Error::UnsupportedAddressType(2))
+    );
+}
+
+#[test]
+fn rejects_malformed_addresses() {
+    let cases: &[&[u8]] = &[
+        // truncated ipv4 address and port
+        &[5, 1, 0, 1, 127, 0, 0],
+        // truncated ipv6 address
+        &[5, 1, 0, 4, 0, 0, 0, 0],
+        // domain longer than the message
+        &[5, 1, 0, 3, 20, b'a', 0, 80],
+        // domain without a length
+        &[5, 1, 0, 3],
+        // domain that is not utf-8
+        &[5, 1, 0, 3, 2, 0xff, 0xfe, 0, 80],
+        // trailing bytes
+        &[5, 1, 0, 1, 127, 0, 0, 1, 0, 80, 0],
+    ];
+    for case in cases {
+        assert_eq!(
+            parse_socks5_request(case),
+            Err(Socks5Error::MalformedAddress),
+            "{:?}",
+            case
+        );
+    }
+}
+
+#[test]
+fn targets_parse_from_host_port() {
+    assert_eq!(
+        "10.0.0.1:53".parse(),
+        Ok(Target::Ip("10.0.0.1:53".parse().unwrap()))
+    );
+    let target: Target = "example.com:443".parse().unwrap();
+    assert_eq!(target.port(), 443);
+    assert_eq!(target.to_string(), "example.com:443");
+    assert!("example.com".parse::<Target>().is_err());
+    assert!(":80".parse::<Target>().is_err());
+    assert!("::1:80".parse::<Target>().is_err());
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use common::spawn_proxy;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, http::HeaderValue};
+use wssocks::{ProxyConfig, SUBPROTOCOL};
+
+// upgrade offering these subprotocols, returns the one the server picked
+async fn upgrade(addr: SocketAddr, offered: Option<&str>) -> tungstenite::Result<Option<String>> {
+    let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+    if let Some(offered) = offered {
+        request.headers_mut().insert(
+            "sec-websocket-protocol",
+            HeaderValue::from_str(offered).unwrap(),
+        );
+    }
+    let (_, response) = tokio_tungstenite::connect_async(request).await?;
+    Ok(response
+        .headers()
+        .get("sec-websocket-protocol")
+        .map(|picked| picked.to_str().unwrap().to_string()))
+}
+
+#[tokio::test]
+async fn server_echoes_the_subprotocol() {
+    let addr = spawn_proxy(ProxyConfig::default());
+
+    let picked = upgrade(addr, Some("other, wssocks.v1")).await.unwrap();
+    assert_eq!(picked.as_deref(), Some(SUBPROTOCOL));
+    assert_eq!(upgrade(addr, Some("other")).await.unwrap(), None);
+    assert_eq!(upgrade(addr, None).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn strict_mode_refuses_unknown_subprotocols() {
+    let addr = spawn_proxy(ProxyConfig::default().strict_subprotocol(true));
+
+    match upgrade(addr, Some("other")).await {
+        Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 400),
+        res => panic!("unexpected upgrade result {res:?}"),
+    }
+    let picked = upgrade(addr, Some(SUBPROTOCOL)).await.unwrap();
+    assert_eq!(picked.as_deref(), Some(SUBPROTOCOL));
+    assert_eq!(upgrade(addr, None).await.unwrap(), None);
+}
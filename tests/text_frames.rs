@@ -0,0 +1,78 @@
+mod common;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use common::{ip_address, spawn_echo, spawn_proxy, Client};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+use wssocks::{ProxyConfig, WsSocksClient};
+
+async fn send_text(ws: &mut Client, data: &[u8]) {
+    ws.send(Message::Text(STANDARD.encode(data))).await.unwrap();
+}
+
+// the next frame decoded, failing on binary ones
+async fn recv_text(ws: &mut Client) -> Vec<u8> {
+    loop {
+        match ws.next().await {
+            Some(Ok(Message::Text(text))) => return STANDARD.decode(text).unwrap(),
+            Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn client_tunnels_over_text_frames() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let client = WsSocksClient::new(format!("ws://{addr}/ws")).text_frames(true);
+
+    let mut stream = client.connect(&echo.to_string()).await.unwrap();
+    // larger than a frame, so it is split and joined again
+    let data: Vec<u8> = (0..40_000u32).map(|i| i as u8).collect();
+    stream.write_all(&data).await.unwrap();
+    stream.flush().await.unwrap();
+    let mut echoed = vec![0u8; data.len()];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(echoed, data);
+}
+
+#[tokio::test]
+async fn every_frame_is_text_once_agreed() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let mut request = format!("ws://{addr}/ws").into_client_request().unwrap();
+    request
+        .headers_mut()
+        .insert("x-wssocks-text-frames", "base64".parse().unwrap());
+    let (mut ws, response) = tokio_tungstenite::connect_async(request).await.unwrap();
+    assert_eq!(response.headers()["x-wssocks-text-frames"], "base64");
+
+    send_text(&mut ws, &[0x05, 0x01, 0x00]).await;
+    assert_eq!(recv_text(&mut ws).await, [0x05, 0x00]);
+    let mut connect = vec![0x05, 0x01, 0x00];
+    connect.extend_from_slice(&ip_address(echo));
+    send_text(&mut ws, &connect).await;
+    assert_eq!(recv_text(&mut ws).await[..2], [0x05, 0x00]);
+
+    send_text(&mut ws, b"hello").await;
+    assert_eq!(recv_text(&mut ws).await, b"hello");
+}
+
+#[tokio::test]
+async fn server_without_text_frames_keeps_binary() {
+    let echo = spawn_echo("127.0.0.1").await;
+    let config = ProxyConfig::default()
+        .block_private_addresses(false)
+        .text_frames(false);
+    let addr = spawn_proxy(config);
+    let client = WsSocksClient::new(format!("ws://{addr}/ws")).text_frames(true);
+
+    let mut stream = client.connect(&echo.to_string()).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    let mut echoed = [0u8; 5];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"hello");
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use common::{close_code, connect, ip_address, recv, send, socks5_connect, spawn_proxy};
+use tokio::net::UdpSocket;
+use wssocks::{ProxyConfig, WebSocketDatagram, CLOSE_IDLE_TIMEOUT};
+
+// a udp server on loopback writing back whatever it receives
+async fn spawn_udp_echo() -> SocketAddr {
+    let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = udp.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+        while let Ok((n, from)) = udp.recv_from(&mut buf).await {
+            let _ = udp.send_to(&buf[..n], from).await;
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn datagrams_keep_their_boundaries() {
+    let echo = spawn_udp_echo().await;
+    let addr = spawn_proxy(ProxyConfig::default().block_private_addresses(false));
+    let mut ws = connect(addr).await;
+
+    let unspecified = ip_address("0.0.0.0:0".parse().unwrap());
+    let reply = socks5_connect(&mut ws, 0x03, &unspecified).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+
+    let mut datagrams = WebSocketDatagram::new(ws);
+    // RSV RSV FRAG, then the target and the payload
+    let header = [&[0x00, 0x00, 0x00][..], &ip_address(echo)].concat();
+    for payload in [&b"first"[..], b"second"] {
+        datagrams.send([&header, payload].concat()).await.unwrap();
+    }
+    for payload in [&b"first"[..], b"second"] {
+        let datagram = tokio::time::timeout(Duration::from_secs(5), datagrams.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(datagram, [&header, payload].concat());
+    }
+}
+
+#[tokio::test]
+async fn idle_associations_close_with_the_idle_code() {
+    let echo = spawn_udp_echo().await;
+    let config = ProxyConfig::default()
+        .block_private_addresses(false)
+        .idle_timeout(Some(Duration::from_millis(200)));
+    let addr = spawn_proxy(config);
+    let mut ws = connect(addr).await;
+
+    let unspecified = ip_address("0.0.0.0:0".parse().unwrap());
+    let reply = socks5_connect(&mut ws, 0x03, &unspecified).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+
+    // datagrams for longer than the timeout keep it open
+    let header = [&[0x00, 0x00, 0x00][..], &ip_address(echo)].concat();
+    for _ in 0..4 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        send(&mut ws, &[&header, &b"ping"[..]].concat()).await;
+        assert_eq!(recv(&mut ws).await, Some([&header, &b"ping"[..]].concat()));
+    }
+    assert_eq!(close_code(&mut ws).await, Some(CLOSE_IDLE_TIMEOUT));
+}
@@ -0,0 +1,65 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::path::PathBuf;
+
+use common::{connect, domain_address, recv, send, socks5_connect, spawn_proxy};
+use tokio::net::UnixListener;
+use wssocks::ProxyConfig;
+
+// a unix socket writing back whatever it reads, at a path of its own
+fn spawn_unix_echo(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("wssocks-{}-{}.sock", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    path
+}
+
+#[tokio::test]
+async fn unix_domains_reach_the_socket() {
+    let path = spawn_unix_echo("enabled");
+    let addr = spawn_proxy(ProxyConfig::default().unix_sockets([&path]));
+    let mut ws = connect(addr).await;
+
+    let host = format!("unix:{}", path.display());
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address(&host, 0)).await;
+    assert_eq!(reply[..2], [0x05, 0x00]);
+    send(&mut ws, b"hello").await;
+    assert_eq!(recv(&mut ws).await, Some(b"hello".to_vec()));
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn unix_sockets_off_the_list_are_refused() {
+    let listed = spawn_unix_echo("listed");
+    let unlisted = spawn_unix_echo("unlisted");
+    let addr = spawn_proxy(ProxyConfig::default().unix_sockets([&listed]));
+    let mut ws = connect(addr).await;
+
+    let host = format!("unix:{}", unlisted.display());
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address(&host, 0)).await;
+    assert_eq!(reply[..2], [0x05, 0x02]);
+    let _ = std::fs::remove_file(listed);
+    let _ = std::fs::remove_file(unlisted);
+}
+
+#[tokio::test]
+async fn unix_domains_are_resolved_when_disabled() {
+    let path = spawn_unix_echo("disabled");
+    let addr = spawn_proxy(ProxyConfig::default());
+    let mut ws = connect(addr).await;
+
+    let host = format!("unix:{}", path.display());
+    let reply = socks5_connect(&mut ws, 0x01, &domain_address(&host, 80)).await;
+    assert_ne!(reply[1], 0x00);
+    let _ = std::fs::remove_file(path);
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use wssocks::{ConfigError, ProxyConfig, UserPolicy};
+
+#[test]
+fn default_config_is_valid() {
+    assert_eq!(ProxyConfig::default().validate(), Ok(()));
+}
+
+#[test]
+fn zero_timeouts_are_named() {
+    let config = ProxyConfig::default().idle_timeout(Some(Duration::ZERO));
+    assert_eq!(config.validate(), Err(ConfigError::Zero("idle_timeout")));
+}
+
+#[test]
+fn empty_port_ranges_are_refused() {
+    let (start, end) = (9000, 8000);
+    let config = ProxyConfig::default().allowed_ports([443..=443, start..=end]);
+    assert_eq!(
+        config.validate(),
+        Err(ConfigError::EmptyPortRange("allowed_ports", start..=end))
+    );
+}
+
+#[test]
+fn policies_need_credentials() {
+    let credentials = HashMap::from([("alice".to_string(), "secret".to_string())]);
+    let config = ProxyConfig::default()
+        .credentials(credentials)
+        .user_policy("bob", UserPolicy::new());
+    assert_eq!(
+        config.validate(),
+        Err(ConfigError::UnknownPolicyUser("bob".to_string()))
+    );
+}
+
+#[test]
+fn foreign_bind_addresses_are_refused() {
+    // TEST-NET-3, not assigned to any interface here
+    let addr = "203.0.113.1".parse().unwrap();
+    let config = ProxyConfig::default().outbound_bind(Some(addr));
+    assert_eq!(
+        config.validate(),
+        Err(ConfigError::BadBindAddress(None, addr))
+    );
+}
+
+#[test]
+fn shared_paths_are_refused() {
+    let config = ProxyConfig::default().health_path(Some("/ws".to_string()));
+    let error = config.validate().unwrap_err();
+    assert_eq!(error.to_string(), "two routes on path /ws");
+}
+
+#[test]
+fn source_port_zero_is_refused() {
+    let config = ProxyConfig::default().outbound_source_port(Some(0));
+    assert_eq!(
+        config.validate(),
+        Err(ConfigError::Zero("outbound_source_port"))
+    );
+}